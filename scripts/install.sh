@@ -8,4 +8,6 @@ mkdir -p $MOUNTPOINT
 sudo mount --mkdir /dev/nvme0n1p4 $MOUNTPOINT
 sudo rm -rf $MOUNTPOINT/*
 sudo cp target/x86_64-abosbel-none/release/ab-os-bel $MOUNTPOINT
+scripts/symbols.sh target/x86_64-abosbel-none/release/ab-os-bel /tmp/symbols.map
+sudo cp /tmp/symbols.map $MOUNTPOINT
 sudo umount $MOUNTPOINT
@@ -10,9 +10,16 @@ if [[ $EXEC_PATH != *"ab-os-bel" ]]; then
   TEST="true"
 fi
 DEBUG="false"
-if [[ $2 == "debug" ]]; then
-  DEBUG="true"
-fi
+TCG="false" # Emulate the CPU instead of using KVM
+SMP="1" # Number of CPUs
+for arg in "${@:2}"; do
+  case $arg in
+    debug) DEBUG="true" ;;
+    tcg) TCG="true" ;;
+    smp=*) SMP="${arg#smp=}" ;;
+    *) ;; # Test filters and such, not for QEMU
+  esac
+done
 
 #### CREATE ISO ####
 
@@ -31,6 +38,7 @@ fi
 rm -rf ${ISO_DIR}
 mkdir -p ${ISO_DIR}/boot/grub
 cp ${EXEC_PATH} ${ISO_DIR}/boot/grub/ab-os-bel
+scripts/symbols.sh ${EXEC_PATH} ${ISO_DIR}/boot/grub/symbols.map
 cp grub.cfg ${ISO_DIR}/boot/grub/
 
 mkdir -p ${ISO_DIR}/EFI/BOOT
@@ -63,9 +71,15 @@ QEMU_FLAGS+='-vga vmware ' # Allows 32 bpp
 # Settings
 QEMU_FLAGS+='-cdrom target/ab-os-bel.iso '
 QEMU_FLAGS+='-serial stdio ' # Allows printing to console
+QEMU_FLAGS+='-serial tcp::4321,server=on,wait=off ' # COM2, for the GDB stub (gdb=2)
 QEMU_FLAGS+='-no-reboot ' # If the os reboots, exit instead
-QEMU_FLAGS+='-cpu host ' # Use the host cpu
-QEMU_FLAGS+='-enable-kvm ' # Enable KVM
+QEMU_FLAGS+="-smp ${SMP} "
+if [[ $TCG == "true" ]]; then
+  QEMU_FLAGS+='-accel tcg -cpu max ' # Everything TCG can emulate
+else
+  QEMU_FLAGS+='-cpu host ' # Use the host cpu
+  QEMU_FLAGS+='-enable-kvm ' # Enable KVM
+fi
 # Tests
 QEMU_FLAGS+='-device isa-debug-exit,iobase=0xf4,iosize=0x04 '
 
@@ -0,0 +1,50 @@
+#!/bin/bash
+
+# Makes the symbol map loaded by the kernel as the `symbols` module (see src/kernel/backtrace/symbols.rs)
+# Usage : scripts/symbols.sh <kernel ELF> <output>
+
+EXEC_PATH=$1
+OUTPUT=$2
+
+{
+  # Functions : address F size name, the size is 0 for the assembly ones which don't have one
+  nm --defined-only --demangle --numeric-sort --print-size "$EXEC_PATH" |
+    awk '
+      $2 ~ /^[tTwW]$/ { size = 0; first = 3 }
+      $3 ~ /^[tTwW]$/ { size = $2; first = 4 }
+      $2 ~ /^[tTwW]$/ || $3 ~ /^[tTwW]$/ {
+        name = $first
+        for (i = first + 1; i <= NF; i++) name = name " " $i
+        print $1, "F", size, name
+      }'
+
+  # Lines : address L line file
+  # objdump prints the full path before the rows of a file, the rows only have a relative one
+  objdump --wide --dwarf=decodedline "$EXEC_PATH" |
+    awk '
+      function basename(file) {
+        sub(/.*\//, "", file)
+        return file
+      }
+      /:$/ && !/^File name/ {
+        path = $NF
+        sub(/:$/, "", path)
+        next
+      }
+      $2 ~ /^[0-9]+$/ && $3 ~ /^0x/ {
+        addr = substr($3, 3)
+        # Functions removed by the linker keep their rows at address 0,
+        # and line 0 is code that belongs to no line
+        if (length(addr) < 5 || $2 == 0) next
+        while (length(addr) < 16) addr = "0" addr
+
+        file = (basename($1) == basename(path)) ? path : $1
+        sub(/.*\/rustlib\/src\/rust\/library\//, "", file)
+        sub(/.*\/registry\/src\/[^\/]*\//, "", file)
+        # Consecutive rows of the same line are merged
+        if (file == last_file && $2 == last_line) next
+        last_file = file
+        last_line = $2
+        print addr, "L", $2, file
+      }'
+} | LC_ALL=C sort --stable --key=1,1 > "$OUTPUT"
@@ -25,6 +25,7 @@ _start:
 
     call set_up_page_tables
     call enable_paging 
+    call enable_sse
 
     /* load the 64-bit GDT */
     lgdt [gdt64_pointer]
@@ -204,6 +205,23 @@ enable_paging:
 
     ret
 
+/**
+ * Enable SSE, the compiler uses it everywhere. The rest of the FPU is set up by the kernel (fpu.rs).
+*/
+enable_sse:
+    /* clear EM (x87 emulation) and set MP (monitor coprocessor) in cr0 */
+    mov eax, cr0
+    and eax, ~(1 << 2)
+    or eax, 1 << 1
+    mov cr0, eax
+
+    /* set OSFXSR (fxsave and SSE) and OSXMMEXCPT (#XM instead of #UD) in cr4 */
+    mov eax, cr4
+    or eax, (1 << 9) | (1 << 10)
+    mov cr4, eax
+
+    ret
+
 
 
 
@@ -225,6 +243,8 @@ long_mode_start:
     mov fs, ax
     mov gs, ax
 
+    /* ends the chain of frame pointers walked by backtraces */
+    xor rbp, rbp
     call main
 
     /* Shouldn't ever reach here */
@@ -258,6 +278,9 @@ p2_table:
     .space P2_TABLE_SIZE
 
 /* allocate stack */
+.global stack_guard
+stack_guard: /* unmapped by the kernel (see stacks.rs) so that overflowing the stack faults */
+    .space 0x1000
 stack_bottom:
     .space STACK_SIZE
 stack_top:
@@ -271,7 +294,7 @@ stack_top:
 /* RODATA : section where read-only data is stored */
 .section .rodata
 
-/* GDT : Global Descriptor Table */
+/* GDT : Global Descriptor Table, replaced by the one of gdt.rs once in Rust */
 gdt64:
     .quad 0 /* zero entry */
     .set GDT64_CODE, . - gdt64
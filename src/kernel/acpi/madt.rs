@@ -0,0 +1,156 @@
+// Multiple APIC Description Table : the interrupt controllers, and through the local APICs the CPUs
+
+use super::{AcpiError, Sdt, Signature, find_table};
+
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const LOCAL_X2APIC: u8 = 9;
+
+// The local APIC address and the flags, before the entries
+const MADT_FIELDS_SIZE: usize = 8;
+
+// Flags of the local APIC entries
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    Other {
+        kind: u8,
+    },
+}
+
+/// A CPU the firmware knows about
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub apic_id: u32,
+    /// Usable right away, otherwise it can only be hot plugged
+    pub enabled: bool,
+}
+
+pub struct Madt {
+    table: Sdt,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl Madt {
+    pub fn get() -> Result<Self, AcpiError> {
+        let table = find_table(Signature::MADT)?;
+        if table.data().len() < MADT_FIELDS_SIZE {
+            return Err(AcpiError::Truncated(Signature::MADT));
+        }
+        Ok(Madt { table })
+    }
+
+    /// Physical address of the local APIC registers, the same for every CPU
+    pub fn local_apic_address(&self) -> usize {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address as usize),
+                _ => None,
+            })
+            .unwrap_or(read_u32(self.table.data(), 0) as usize)
+    }
+
+    /// Whether there are also 8259 PICs
+    pub fn has_legacy_pics(&self) -> bool {
+        read_u32(self.table.data(), 4) & 1 != 0
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> {
+        // Each entry starts with its type and length
+        let mut bytes = &self.table.data()[MADT_FIELDS_SIZE..];
+        core::iter::from_fn(move || {
+            if bytes.len() < 2 {
+                return None;
+            }
+            let (kind, length) = (bytes[0], bytes[1] as usize);
+            if length < 2 || length > bytes.len() {
+                return None;
+            }
+            let entry = &bytes[..length];
+            bytes = &bytes[length..];
+            Some(match (kind, length) {
+                (LOCAL_APIC, 8..) => MadtEntry::LocalApic {
+                    processor_id: entry[2],
+                    apic_id: entry[3],
+                    flags: read_u32(entry, 4),
+                },
+                (IO_APIC, 12..) => MadtEntry::IoApic {
+                    id: entry[2],
+                    address: read_u32(entry, 4),
+                    gsi_base: read_u32(entry, 8),
+                },
+                (INTERRUPT_SOURCE_OVERRIDE, 10..) => MadtEntry::InterruptSourceOverride {
+                    bus: entry[2],
+                    source: entry[3],
+                    gsi: read_u32(entry, 4),
+                    flags: read_u16(entry, 8),
+                },
+                (LOCAL_APIC_ADDRESS_OVERRIDE, 12..) => MadtEntry::LocalApicAddressOverride {
+                    address: read_u64(entry, 4),
+                },
+                (LOCAL_X2APIC, 16..) => MadtEntry::LocalX2Apic {
+                    x2apic_id: read_u32(entry, 4),
+                    flags: read_u32(entry, 8),
+                    processor_uid: read_u32(entry, 12),
+                },
+                _ => MadtEntry::Other { kind },
+            })
+        })
+    }
+
+    /// CPUs that are enabled or can be, the others are absent
+    pub fn processors(&self) -> impl Iterator<Item = Processor> {
+        self.entries().filter_map(|entry| {
+            let (apic_id, flags) = match entry {
+                MadtEntry::LocalApic { apic_id, flags, .. } => (apic_id as u32, flags),
+                MadtEntry::LocalX2Apic {
+                    x2apic_id, flags, ..
+                } => (x2apic_id, flags),
+                _ => return None,
+            };
+            (flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0).then_some(Processor {
+                apic_id,
+                enabled: flags & PROCESSOR_ENABLED != 0,
+            })
+        })
+    }
+}
@@ -0,0 +1,130 @@
+// ACPI tables, found through the RSDP GRUB copies in the multiboot information. They are read in place, the
+// firmware puts them below 4GiB which is identity mapped.
+
+mod madt;
+
+pub use madt::*;
+
+use core::{fmt, slice, str};
+
+use crate::MULTIBOOT2_INFO;
+
+#[derive(Debug, Clone, Copy)]
+pub enum AcpiError {
+    NoRsdp,
+    InvalidChecksum(Signature),
+    TableNotFound(Signature),
+    Truncated(Signature), // Shorter than its header, or than the fixed fields of its kind
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcpiError::NoRsdp => write!(f, "no RSDP in the multiboot information"),
+            AcpiError::InvalidChecksum(signature) => write!(f, "invalid {} checksum", signature),
+            AcpiError::TableNotFound(signature) => write!(f, "no {} table", signature),
+            AcpiError::Truncated(signature) => write!(f, "truncated {} table", signature),
+        }
+    }
+}
+
+/// Four letters naming a table
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 4]);
+
+impl Signature {
+    pub const MADT: Signature = Signature(*b"APIC");
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(str::from_utf8(&self.0).unwrap_or("????"))
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// Header every system description table starts with
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: Signature,
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// A table as the firmware left it, header included
+#[derive(Clone, Copy)]
+pub struct Sdt {
+    bytes: &'static [u8],
+}
+
+impl Sdt {
+    /// Reads the table at a physical address, checking its length and checksum
+    unsafe fn at(addr: usize) -> Result<Self, AcpiError> {
+        let header = unsafe { (addr as *const SdtHeader).read_unaligned() };
+        if (header.length as usize) < size_of::<SdtHeader>() {
+            return Err(AcpiError::Truncated(header.signature));
+        }
+        let bytes = unsafe { slice::from_raw_parts(addr as *const u8, header.length as usize) };
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(AcpiError::InvalidChecksum(header.signature));
+        }
+        Ok(Sdt { bytes })
+    }
+
+    pub fn header(&self) -> SdtHeader {
+        unsafe { (self.bytes.as_ptr() as *const SdtHeader).read_unaligned() }
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.header().signature
+    }
+
+    /// What follows the header
+    pub fn data(&self) -> &'static [u8] {
+        &self.bytes[size_of::<SdtHeader>()..]
+    }
+}
+
+// The XSDT has 64 bits pointers, the RSDT of ACPI 1.0 32 bits ones
+fn root_table() -> Result<(Sdt, usize), AcpiError> {
+    let boot_info = MULTIBOOT2_INFO.get().ok_or(AcpiError::NoRsdp)?;
+    if let Some(rsdp) = boot_info.rsdp_v2_tag()
+        && rsdp.checksum_is_valid()
+        && rsdp.xsdt_address() != 0
+    {
+        return Ok((unsafe { Sdt::at(rsdp.xsdt_address())? }, 8));
+    }
+    let rsdp = boot_info.rsdp_v1_tag().ok_or(AcpiError::NoRsdp)?;
+    Ok((unsafe { Sdt::at(rsdp.rsdt_address())? }, 4))
+}
+
+/// Every table the root table points to, the ones with a bad checksum are skipped
+pub fn tables() -> Result<impl Iterator<Item = Sdt>, AcpiError> {
+    let (root, pointer_size) = root_table()?;
+    Ok(root
+        .data()
+        .chunks_exact(pointer_size)
+        .filter_map(|pointer| {
+            let mut addr = [0; 8];
+            addr[..pointer.len()].copy_from_slice(pointer);
+            unsafe { Sdt::at(u64::from_le_bytes(addr) as usize) }.ok()
+        }))
+}
+
+pub fn find_table(signature: Signature) -> Result<Sdt, AcpiError> {
+    tables()?
+        .find(|table| table.signature() == signature)
+        .ok_or(AcpiError::TableNotFound(signature))
+}
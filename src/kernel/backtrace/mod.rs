@@ -0,0 +1,117 @@
+// Stack backtraces for panics and exceptions. Every function keeps a frame pointer (forced on by the target
+// spec), so the frames are a linked list : `[rbp]` is the caller's RBP and `[rbp + 8]` the return address.
+
+mod symbols;
+
+pub use symbols::*;
+
+use core::{arch::asm, fmt};
+
+use crate::{
+    paging,
+    shell::{CommandError, FnCommand, parse_number},
+    shell_command,
+};
+
+// A corrupted stack can loop
+const MAX_FRAMES: usize = 64;
+
+/// Return addresses found by following the frame pointers
+pub struct Frames {
+    rbp: usize,
+    depth: usize,
+}
+
+impl Iterator for Frames {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        // boot.s starts the chain with a null RBP
+        if self.depth >= MAX_FRAMES || self.rbp == 0 || !self.rbp.is_multiple_of(8) {
+            return None;
+        }
+        // Faulting in the panic handler would hide the first panic
+        if paging::leaf_entry(self.rbp).is_none() || paging::leaf_entry(self.rbp + 8).is_none() {
+            return None;
+        }
+        let (caller_rbp, return_addr) = unsafe {
+            (
+                *(self.rbp as *const usize),
+                *((self.rbp + 8) as *const usize),
+            )
+        };
+        if return_addr == 0 {
+            return None;
+        }
+        self.rbp = caller_rbp;
+        self.depth += 1;
+        Some(return_addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Backtrace {
+    rbp: usize,
+}
+
+impl Backtrace {
+    /// Backtrace of the calling function
+    #[inline(always)]
+    pub fn current() -> Self {
+        let rbp;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+        Backtrace { rbp }
+    }
+
+    pub fn frames(&self) -> Frames {
+        Frames {
+            rbp: self.rbp,
+            depth: 0,
+        }
+    }
+}
+
+// `  3: 0x0000000000108345 ab_os_bel::kernel::gdb::handle_trap+0x15 (src/kernel/gdb/mod.rs:160)`
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Backtrace :")?;
+        for (i, addr) in self.frames().enumerate() {
+            write!(f, "\n{:>4}: {:#018x}", i, addr)?;
+            // Return addresses are after the call, which may be the last instruction of its line
+            if let Some(symbol) = resolve(addr - 1) {
+                let symbol = Symbol {
+                    offset: symbol.offset + 1,
+                    ..symbol
+                };
+                write!(f, " {}", symbol)?;
+            }
+        }
+        if !has_symbols() {
+            write!(f, "\n(no symbols module, see grub.cfg)")?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////
+
+fn sym(args: &[&str], out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    let addr = parse_number(args.first().ok_or(CommandError::Usage)?)? as usize;
+    if !has_symbols() {
+        return Err(CommandError::Failed("no symbols module"));
+    }
+    match resolve(addr) {
+        Some(symbol) => {
+            let _ = writeln!(out, "{}", symbol);
+            Ok(())
+        }
+        None => Err(CommandError::Failed("no symbol there")),
+    }
+}
+
+shell_command!(FnCommand {
+    name: "sym",
+    usage: "<addr>",
+    help: "Shows the function and line of an address",
+    run: sym,
+});
@@ -0,0 +1,146 @@
+// Symbol map given by GRUB as the `symbols` module, made from the kernel ELF by `scripts/symbols.sh`.
+// One record per line, sorted by address so that it can be searched without being parsed first :
+//   <address, 16 hex digits> F <size in hex> <function>
+//   <address, 16 hex digits> L <line> <file>
+
+use core::fmt;
+
+use spin::Once;
+
+use crate::find_module;
+
+static SYMBOL_MAP: Once<&'static str> = Once::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub file: &'static str,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub offset: usize,
+    pub location: Option<Location>,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)?;
+        if let Some(location) = self.location {
+            write!(f, " ({}:{})", location.file, location.line)?;
+        }
+        Ok(())
+    }
+}
+
+enum Record {
+    Function {
+        addr: usize,
+        size: usize,
+        name: &'static str,
+    },
+    Line(Location),
+}
+
+impl Record {
+    fn parse(line: &'static str) -> Option<Record> {
+        let mut fields = line.splitn(4, ' ');
+        let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let kind = fields.next()?;
+        let value = fields.next()?;
+        let rest = fields.next()?;
+        match kind {
+            "F" => Some(Record::Function {
+                addr,
+                size: usize::from_str_radix(value, 16).ok()?,
+                name: rest,
+            }),
+            "L" => Some(Record::Line(Location {
+                file: rest,
+                line: value.parse().ok()?,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Looks for the `symbols` module
+pub fn init() {
+    let Some(module) = find_module("symbols") else {
+        log::info!("No symbols module, backtraces won't have names");
+        return;
+    };
+    match core::str::from_utf8(module) {
+        Ok(map) => {
+            SYMBOL_MAP.call_once(|| map);
+            log::info!("Loaded {} KiB of symbols", module.len() / 1024);
+        }
+        Err(_) => log::warn!("The symbols module isn't a symbol map"),
+    }
+}
+
+pub fn has_symbols() -> bool {
+    SYMBOL_MAP.get().is_some()
+}
+
+// Address of the record starting at `start`, records are sorted on it
+fn record_addr(map: &str, start: usize) -> Option<usize> {
+    usize::from_str_radix(map.get(start..start + 16)?, 16).ok()
+}
+
+// Searched as bytes as `pos` may be in the middle of a character
+fn line_start(map: &str, pos: usize) -> usize {
+    map.as_bytes()[..pos]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |i| i + 1)
+}
+
+fn next_line(map: &str, pos: usize) -> usize {
+    map.as_bytes()[pos..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(map.len(), |i| pos + i + 1)
+}
+
+/// Function containing `addr`, with the source line when the map has it
+pub fn resolve(addr: usize) -> Option<Symbol> {
+    let map = *SYMBOL_MAP.get()?;
+
+    // Binary search of the first record after `addr`, `low` and `high` are always line starts
+    let (mut low, mut high) = (0, map.len());
+    while low < high {
+        let mid = line_start(map, low + (high - low) / 2);
+        if record_addr(map, mid).is_some_and(|record| record <= addr) {
+            low = next_line(map, mid);
+        } else {
+            high = mid;
+        }
+    }
+
+    // The closest line record comes before the function record, unless the function has no line
+    let mut location = None;
+    for line in map[..low].lines().rev() {
+        match Record::parse(line) {
+            Some(Record::Line(found)) if location.is_none() => location = Some(found),
+            Some(Record::Line(_)) => {}
+            Some(Record::Function {
+                addr: start,
+                size,
+                name,
+            }) => {
+                if size != 0 && addr >= start + size {
+                    return None;
+                }
+                return Some(Symbol {
+                    name,
+                    offset: addr - start,
+                    location,
+                });
+            }
+            None => {}
+        }
+    }
+    None
+}
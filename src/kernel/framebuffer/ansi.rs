@@ -0,0 +1,252 @@
+// VT100/xterm escape sequences parser, only the subset the console understands is meaningful
+// but every well formed sequence is consumed so nothing garbage ends up on screen
+
+use super::Color;
+
+const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CsiSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// `?`, `>`, ... prefix of private sequences
+    pub private: Option<char>,
+    pub final_byte: char,
+}
+
+impl CsiSequence {
+    const fn empty() -> Self {
+        CsiSequence {
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: None,
+            final_byte: '\0',
+        }
+    }
+
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Parameter `i`, or `default` if it's missing or 0 (which means default for most sequences)
+    pub fn param(&self, i: usize, default: usize) -> usize {
+        match self.params().get(i) {
+            Some(0) | None => default,
+            Some(param) => *param as usize,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AnsiAction {
+    Print(char),
+    /// C0 control character (`\n`, `\r`, `\t`, backspace, ...)
+    Control(char),
+    Csi(CsiSequence),
+    /// Two character escape sequence such as `ESC 7`
+    Escape(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    CsiIgnore,
+    Osc,
+    OscEscape,
+}
+
+#[derive(Debug)]
+pub struct AnsiParser {
+    state: State,
+    csi: CsiSequence,
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        AnsiParser {
+            state: State::Ground,
+            csi: CsiSequence::empty(),
+        }
+    }
+}
+
+impl AnsiParser {
+    /// Feeds one character, returning an action once a character or a whole sequence is read
+    pub fn advance(&mut self, c: char) -> Option<AnsiAction> {
+        // ESC and CAN/SUB abort any sequence in progress
+        match c {
+            '\x1b' if self.state != State::Osc => {
+                self.state = State::Escape;
+                return None;
+            }
+            '\x18' | '\x1a' => {
+                self.state = State::Ground;
+                return None;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match c {
+                '\0'..='\x1f' | '\x7f' => Some(AnsiAction::Control(c)),
+                _ => Some(AnsiAction::Print(c)),
+            },
+            State::Escape => match c {
+                '[' => {
+                    self.csi = CsiSequence::empty();
+                    self.state = State::Csi;
+                    None
+                }
+                ']' => {
+                    self.state = State::Osc;
+                    None
+                }
+                ' '..='/' => {
+                    self.state = State::EscapeIntermediate;
+                    None
+                }
+                _ => {
+                    self.state = State::Ground;
+                    Some(AnsiAction::Escape(c))
+                }
+            },
+            // Charset designations and such, ignored
+            State::EscapeIntermediate => {
+                if !(' '..='/').contains(&c) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Csi => self.advance_csi(c),
+            State::CsiIgnore => {
+                if ('@'..='~').contains(&c) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            // Window titles and such, terminated by BEL or ST (ESC \)
+            State::Osc => {
+                match c {
+                    '\x07' => self.state = State::Ground,
+                    '\x1b' => self.state = State::OscEscape,
+                    _ => {}
+                }
+                None
+            }
+            State::OscEscape => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+
+    fn advance_csi(&mut self, c: char) -> Option<AnsiAction> {
+        let csi = &mut self.csi;
+        match c {
+            '0'..='9' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                let param = &mut csi.params[csi.len - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(c as u16 - '0' as u16);
+                None
+            }
+            // ':' separates sub parameters (38:2:r:g:b), treated like ';'
+            ';' | ':' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if csi.len == MAX_PARAMS {
+                    self.state = State::CsiIgnore;
+                } else {
+                    csi.len += 1;
+                }
+                None
+            }
+            '<'..='?' if csi.len == 0 && csi.private.is_none() => {
+                csi.private = Some(c);
+                None
+            }
+            '@'..='~' => {
+                csi.final_byte = c;
+                self.state = State::Ground;
+                Some(AnsiAction::Csi(*csi))
+            }
+            // Control characters are executed in the middle of sequences
+            '\0'..='\x1f' => Some(AnsiAction::Control(c)),
+            _ => {
+                self.state = State::CsiIgnore;
+                None
+            }
+        }
+    }
+}
+
+////////////////////////////////
+
+/// Color as set by SGR sequences, resolved when drawing since bold changes the 8 first colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermColor {
+    Default,
+    Indexed(u8),
+    Rgb(Color),
+}
+
+// xterm default palette for the 16 first colors
+const PALETTE_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Color of the xterm 256 colors palette
+pub fn palette_color(index: u8) -> Color {
+    match index {
+        0..=15 => {
+            let (r, g, b) = PALETTE_16[index as usize];
+            Color::new(r, g, b, 255)
+        }
+        // 6x6x6 color cube
+        16..=231 => {
+            let level = |i: u8| if i == 0 { 0 } else { 55 + i * 40 };
+            let i = index - 16;
+            Color::new(level(i / 36), level((i / 6) % 6), level(i % 6), 255)
+        }
+        // Grayscale ramp
+        232..=255 => {
+            let level = 8 + (index - 232) * 10;
+            Color::new(level, level, level, 255)
+        }
+    }
+}
+
+/// Parses the color following a 38/48 SGR parameter (`5;n` or `2;r;g;b`),
+/// returning it along with the number of parameters consumed
+pub fn parse_extended_color(params: &[u16]) -> Option<(TermColor, usize)> {
+    match params {
+        [5, index, ..] => Some((TermColor::Indexed(*index as u8), 2)),
+        [2, r, g, b, ..] => Some((
+            TermColor::Rgb(Color::new(*r as u8, *g as u8, *b as u8, 255)),
+            4,
+        )),
+        _ => None,
+    }
+}
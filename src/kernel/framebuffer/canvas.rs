@@ -0,0 +1,271 @@
+use super::{Color, Image};
+
+/// Rectangle in pixel coordinates, can be partially or fully out of the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: isize, y: isize, width: usize, height: usize) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> isize {
+        self.x + self.width as isize
+    }
+
+    pub fn bottom(&self) -> isize {
+        self.y + self.height as isize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(
+            x,
+            y,
+            (right - x).max(0) as usize,
+            (bottom - y).max(0) as usize,
+        )
+    }
+}
+
+/// Something that can be drawn on, the screen or an offscreen `Surface`.
+/// Only the pixel accessors and the clip rectangle have to be implemented, every primitive
+/// goes through `plot`/`fill_span` which clip and alpha blend.
+pub trait Canvas {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+
+    /// Pixel at (x, y), the coordinates must be in bounds
+    fn pixel(&self, x: usize, y: usize) -> Color;
+    /// Overwrites the pixel at (x, y) without blending, the coordinates must be in bounds
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color);
+
+    fn clip_rect(&self) -> Rect;
+    /// Restricts every following drawing to `rect` (intersected with the canvas bounds)
+    fn set_clip_rect(&mut self, rect: Rect);
+
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
+    fn reset_clip_rect(&mut self) {
+        self.set_clip_rect(self.bounds());
+    }
+
+    /// Draws a single pixel, blending it if the color isn't opaque
+    fn plot(&mut self, x: isize, y: isize, color: Color) {
+        if !self.clip_rect().contains(x, y) {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        if color.is_opaque() {
+            self.set_pixel(x, y, color);
+        } else {
+            let dst = self.pixel(x, y);
+            self.set_pixel(x, y, color.blend_over(dst));
+        }
+    }
+
+    /// Horizontal line from x0 to x1 included. Canvases backed by memory can override it.
+    fn fill_span(&mut self, y: isize, x0: isize, x1: isize, color: Color) {
+        let clip = self.clip_rect();
+        if y < clip.y || y >= clip.bottom() {
+            return;
+        }
+        for x in x0.max(clip.x)..=x1.min(clip.right() - 1) {
+            self.plot(x, y, color);
+        }
+    }
+
+    fn fill(&mut self, color: Color) {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    /// Bresenham's line algorithm
+    fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: Color) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        loop {
+            self.plot(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    fn draw_rect(&mut self, rect: Rect, color: Color) {
+        if rect.is_empty() {
+            return;
+        }
+        let (right, bottom) = (rect.right() - 1, rect.bottom() - 1);
+        self.fill_span(rect.y, rect.x, right, color);
+        if bottom != rect.y {
+            self.fill_span(bottom, rect.x, right, color);
+        }
+        for y in rect.y + 1..bottom {
+            self.plot(rect.x, y, color);
+            if right != rect.x {
+                self.plot(right, y, color);
+            }
+        }
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let rect = rect.intersection(&self.clip_rect());
+        for y in rect.y..rect.bottom() {
+            self.fill_span(y, rect.x, rect.right() - 1, color);
+        }
+    }
+
+    /// Midpoint circle algorithm
+    fn draw_circle(&mut self, cx: isize, cy: isize, radius: usize, color: Color) {
+        let (mut x, mut y) = (radius as isize, 0);
+        let mut err = 1 - x;
+        while x >= y {
+            // Plotting each octant, avoiding to plot twice the same pixel for alpha blending
+            let mut points = [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ];
+            let points = dedup_points(&mut points);
+            for (px, py) in points {
+                self.plot(cx + *px, cy + *py, color);
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    fn fill_circle(&mut self, cx: isize, cy: isize, radius: usize, color: Color) {
+        self.fill_ellipse(cx, cy, radius, radius, color);
+    }
+
+    /// Midpoint ellipse algorithm, in integer arithmetic
+    fn draw_ellipse(&mut self, cx: isize, cy: isize, rx: usize, ry: usize, color: Color) {
+        let (rx, ry) = (rx as i64, ry as i64);
+        if rx == 0 || ry == 0 {
+            self.draw_line(
+                cx - rx as isize,
+                cy - ry as isize,
+                cx + rx as isize,
+                cy + ry as isize,
+                color,
+            );
+            return;
+        }
+        let (rx2, ry2) = (rx * rx, ry * ry);
+        let plot4 = |canvas: &mut Self, x: i64, y: i64| {
+            let mut points =
+                [(x, y), (-x, y), (x, -y), (-x, -y)].map(|(x, y)| (x as isize, y as isize));
+            for (px, py) in dedup_points(&mut points) {
+                canvas.plot(cx + *px, cy + *py, color);
+            }
+        };
+
+        // Region 1 : slope > -1
+        let (mut x, mut y) = (0, ry);
+        let mut p = 4 * ry2 - 4 * rx2 * ry + rx2;
+        while ry2 * x <= rx2 * y {
+            plot4(self, x, y);
+            x += 1;
+            if p < 0 {
+                p += 4 * ry2 * (2 * x + 1);
+            } else {
+                y -= 1;
+                p += 4 * ry2 * (2 * x + 1) - 8 * rx2 * y;
+            }
+        }
+
+        // Region 2 : slope < -1
+        let mut p = ry2 * (2 * x + 1) * (2 * x + 1) + 4 * rx2 * (y - 1) * (y - 1) - 4 * rx2 * ry2;
+        while y >= 0 {
+            plot4(self, x, y);
+            y -= 1;
+            if p > 0 {
+                p += 4 * rx2 * (1 - 2 * y);
+            } else {
+                x += 1;
+                p += 8 * ry2 * x + 4 * rx2 * (1 - 2 * y);
+            }
+        }
+    }
+
+    fn fill_ellipse(&mut self, cx: isize, cy: isize, rx: usize, ry: usize, color: Color) {
+        let (rx2, ry2) = ((rx * rx) as u64, (ry * ry) as u64);
+        for dy in -(ry as isize)..=ry as isize {
+            let dy2 = (dy * dy) as u64;
+            let dx = (rx2 * (ry2 - dy2))
+                .checked_div(ry2)
+                .map_or(rx as isize, |dx2| dx2.isqrt() as isize);
+            self.fill_span(cy + dy, cx - dx, cx + dx, color);
+        }
+    }
+
+    /// Draws `image` with its top left corner at (x, y), alpha blending every pixel
+    fn blit(&mut self, image: &Image, x: isize, y: isize) {
+        let dst = Rect::new(x, y, image.width(), image.height()).intersection(&self.clip_rect());
+        for dst_y in dst.y..dst.bottom() {
+            for dst_x in dst.x..dst.right() {
+                let color = image.pixel((dst_x - x) as usize, (dst_y - y) as usize);
+                self.plot(dst_x, dst_y, color);
+            }
+        }
+    }
+}
+
+// The symmetric points of the circle algorithms overlap on the axes and diagonals
+fn dedup_points(points: &mut [(isize, isize)]) -> &[(isize, isize)] {
+    let mut len = 0;
+    for i in 0..points.len() {
+        if !points[..len].contains(&points[i]) {
+            points[len] = points[i];
+            len += 1;
+        }
+    }
+    &points[..len]
+}
@@ -0,0 +1,275 @@
+use spin::Mutex;
+
+use super::{Color, OutOfBoundsError, TextAttributes, TextBuffer};
+
+// The grid is static as there is no allocator, it's sized for a 8x16 font on a 2048 pixels wide screen
+pub const MAX_COLUMNS: usize = 256;
+// Screen lines and scrollback share the ring
+pub const MAX_LINES: usize = 1024;
+pub const DEFAULT_SCROLLBACK: usize = 512;
+
+// Half a second at the PIT default frequency
+pub const CURSOR_BLINK_TICKS: u64 = 50;
+
+/// What is displayed in a character cell, colors are the ones drawn (bold and inverse already applied)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub char: char,
+    pub foreground: Color,
+    pub background: Color,
+    pub attributes: TextAttributes,
+}
+
+impl Cell {
+    const EMPTY: Cell = Cell {
+        char: '\0',
+        foreground: Color::new(0, 0, 0, 0),
+        background: Color::new(0, 0, 0, 0),
+        attributes: TextAttributes::empty(),
+    };
+
+    pub fn blank(background: Color) -> Self {
+        Cell {
+            char: ' ',
+            foreground: background,
+            background,
+            attributes: TextAttributes::empty(),
+        }
+    }
+
+    fn draw(
+        &self,
+        text_buffer: &mut TextBuffer,
+        x: usize,
+        y: usize,
+        inverse: bool,
+    ) -> Result<(), OutOfBoundsError> {
+        let (foreground, background) = if inverse {
+            (self.background, self.foreground)
+        } else {
+            (self.foreground, self.background)
+        };
+        let char = if self.char == '\0' { ' ' } else { self.char };
+        text_buffer.write_char(char, x, y, background, foreground)
+    }
+}
+
+/// Character cell model of the console : the screen and its scrollback history in a ring of lines.
+/// Everything on screen can be redrawn from it, when scrolling back or after a font change.
+pub struct Console {
+    lines: [[Cell; MAX_COLUMNS]; MAX_LINES],
+    head: usize,        // Ring index of the first screen line
+    history: usize,     // Number of lines available above the screen
+    scrollback: usize,  // Maximum number of lines kept above the screen
+    view_offset: usize, // Number of lines the view is scrolled back
+    cursor: (usize, usize),
+    cursor_hidden: bool, // DECTCEM
+    cursor_blink_on: bool,
+}
+
+// Only zeroes so that it lands in .bss, the scrollback is set by `init_graphics`
+pub static CONSOLE: Mutex<Console> = Mutex::new(Console {
+    lines: [[Cell::EMPTY; MAX_COLUMNS]; MAX_LINES],
+    head: 0,
+    history: 0,
+    scrollback: 0,
+    view_offset: 0,
+    cursor: (0, 0),
+    cursor_hidden: false,
+    cursor_blink_on: false,
+});
+
+impl Console {
+    fn ring_index(&self, row: isize) -> usize {
+        (self.head as isize + row).rem_euclid(MAX_LINES as isize) as usize
+    }
+
+    fn line(&mut self, row: usize) -> &mut [Cell; MAX_COLUMNS] {
+        let index = self.ring_index(row as isize);
+        &mut self.lines[index]
+    }
+
+    fn is_live(&self) -> bool {
+        self.view_offset == 0
+    }
+
+    fn max_history(&self, rows: usize) -> usize {
+        self.scrollback.min(MAX_LINES - rows)
+    }
+
+    /// Changes the number of lines kept above the screen
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.scrollback = lines;
+        self.history = self.history.min(lines);
+        self.view_offset = self.view_offset.min(self.history);
+    }
+
+    pub fn cell(&mut self, row: usize, column: usize) -> Cell {
+        self.line(row)[column]
+    }
+
+    pub fn set_cell(
+        &mut self,
+        text_buffer: &mut TextBuffer,
+        row: usize,
+        column: usize,
+        cell: Cell,
+    ) -> Result<(), OutOfBoundsError> {
+        self.line(row)[column] = cell;
+        if self.is_live() {
+            cell.draw(text_buffer, column, row, false)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear_cells(
+        &mut self,
+        text_buffer: &mut TextBuffer,
+        row: usize,
+        columns: core::ops::Range<usize>,
+        background: Color,
+    ) -> Result<(), OutOfBoundsError> {
+        for column in columns {
+            self.set_cell(text_buffer, row, column, Cell::blank(background))?;
+        }
+        Ok(())
+    }
+
+    /// Scrolls the screen lines `top..=bottom` by `lines` (positive is up).
+    /// Lines scrolled off the top of the whole screen go to the scrollback.
+    pub fn scroll(
+        &mut self,
+        text_buffer: &mut TextBuffer,
+        top: usize,
+        bottom: usize,
+        lines: isize,
+        background: Color,
+    ) -> Result<(), OutOfBoundsError> {
+        let (rows, columns) = text_buffer.size();
+        let height = bottom + 1 - top;
+        let shift = lines.unsigned_abs().min(height);
+
+        if lines > 0 && top == 0 && bottom == rows - 1 {
+            for _ in 0..shift {
+                self.head = self.ring_index(1);
+                self.history = (self.history + 1).min(self.max_history(rows));
+                *self.line(rows - 1) = [Cell::blank(background); MAX_COLUMNS];
+            }
+        } else {
+            // Region scrolling, nothing goes in the scrollback
+            let moved = height - shift;
+            for i in 0..moved {
+                let (dst, src) = if lines > 0 {
+                    (top + i, top + i + shift)
+                } else {
+                    (bottom - i, bottom - i - shift)
+                };
+                let line = *self.line(src);
+                *self.line(dst) = line;
+            }
+            let cleared = if lines > 0 {
+                bottom + 1 - shift..bottom + 1
+            } else {
+                top..top + shift
+            };
+            for row in cleared {
+                self.line(row)[..columns].fill(Cell::blank(background));
+            }
+        }
+
+        if self.is_live() {
+            text_buffer.scroll(top, bottom, lines, background)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the view back in the history (positive) or towards the live screen (negative)
+    pub fn scroll_view(&mut self, text_buffer: &mut TextBuffer, lines: isize) {
+        let view_offset = self
+            .view_offset
+            .saturating_add_signed(lines)
+            .min(self.history);
+        if view_offset != self.view_offset {
+            self.view_offset = view_offset;
+            self.redraw(text_buffer);
+        }
+    }
+
+    /// Goes back to the live screen if the view was scrolled back
+    pub fn reset_view(&mut self, text_buffer: &mut TextBuffer) {
+        if !self.is_live() {
+            self.view_offset = 0;
+            self.redraw(text_buffer);
+        }
+    }
+
+    /// Redraws every cell of the view from the model
+    pub fn redraw(&mut self, text_buffer: &mut TextBuffer) {
+        let (rows, columns) = text_buffer.size();
+        for row in 0..rows {
+            let index = self.ring_index(row as isize - self.view_offset as isize);
+            for column in 0..columns {
+                self.lines[index][column]
+                    .draw(text_buffer, column, row, false)
+                    .expect("Cells of the screen are in bounds");
+            }
+        }
+        self.draw_cursor(text_buffer);
+    }
+
+    /// Keeps the bottom of the screen in place when the number of rows changes,
+    /// returns by how many lines the screen content moved up
+    pub fn resize(&mut self, old_rows: usize, new_rows: usize) -> isize {
+        let delta = if new_rows < old_rows {
+            let shrink = old_rows - new_rows;
+            self.head = self.ring_index(shrink as isize);
+            self.history = (self.history + shrink).min(self.max_history(new_rows));
+            shrink as isize
+        } else {
+            let grow = (new_rows - old_rows).min(self.history);
+            self.head = self.ring_index(-(grow as isize));
+            self.history -= grow;
+            -(grow as isize)
+        };
+        self.view_offset = 0;
+        delta
+    }
+
+    // CURSOR
+
+    fn draw_cursor(&mut self, text_buffer: &mut TextBuffer) {
+        let (row, column) = self.cursor;
+        let (rows, columns) = text_buffer.size();
+        if !self.is_live() || row >= rows || column >= columns {
+            return;
+        }
+        let visible = !self.cursor_hidden && self.cursor_blink_on;
+        let cell = self.cell(row, column);
+        // Can't fail, the position was checked
+        let _ = cell.draw(text_buffer, column, row, visible);
+    }
+
+    /// Erases the cursor from the screen, to be done before moving it
+    pub fn hide_cursor(&mut self, text_buffer: &mut TextBuffer) {
+        let blink_on = self.cursor_blink_on;
+        self.cursor_blink_on = false;
+        self.draw_cursor(text_buffer);
+        self.cursor_blink_on = blink_on;
+    }
+
+    pub fn show_cursor(&mut self, text_buffer: &mut TextBuffer, row: usize, column: usize) {
+        self.cursor = (row, column);
+        self.draw_cursor(text_buffer);
+    }
+
+    /// DECTCEM, the cursor isn't drawn at all when disabled
+    pub fn enable_cursor(&mut self, text_buffer: &mut TextBuffer, enabled: bool) {
+        self.cursor_hidden = !enabled;
+        self.draw_cursor(text_buffer);
+    }
+
+    pub fn blink_cursor(&mut self, text_buffer: &mut TextBuffer) {
+        self.cursor_blink_on = !self.cursor_blink_on;
+        self.draw_cursor(text_buffer);
+    }
+}
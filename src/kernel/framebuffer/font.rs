@@ -0,0 +1,364 @@
+// PC Screen Font (PSF1 and PSF2) parsing, see https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html
+
+use core::fmt;
+
+use spin::Mutex;
+
+pub const BUILTIN_FONT: &[u8] = include_bytes!("../../../assets/VGA9.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TAB: u8 = 0x02;
+const PSF1_MODE_SEQ: u8 = 0x04;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQ: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32; // The smallest one, the header says how big it is
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQ: u8 = 0xFE;
+
+// Shared by every loaded font, the biggest ones out there (Terminus, Unifont subsets) map a few thousands code points
+const UNICODE_POOL_SIZE: usize = 16384;
+
+// TODO : Use a Vec once there is an allocator
+static UNICODE_POOL: Mutex<UnicodePool> = Mutex::new(UnicodePool {
+    used: 0,
+    entries: [('\0', 0); UNICODE_POOL_SIZE],
+});
+
+struct UnicodePool {
+    used: usize,
+    entries: [(char, u32); UNICODE_POOL_SIZE],
+}
+
+impl UnicodePool {
+    fn take(&mut self, len: usize) -> Option<&'static mut [(char, u32)]> {
+        if self.used + len > UNICODE_POOL_SIZE {
+            return None;
+        }
+        let ptr = unsafe { self.entries.as_mut_ptr().add(self.used) };
+        self.used += len;
+        // The pool is static and never given back, so each slice is only handed out once
+        Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FontError {
+    BadMagic,
+    Truncated,
+    BadHeader,
+    UnicodePoolExhausted,
+    TooBig, // Not a single cell fits on the screen
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::BadMagic => write!(f, "not a PSF1 or PSF2 font"),
+            FontError::Truncated => write!(f, "font data is truncated"),
+            FontError::BadHeader => write!(f, "invalid font header"),
+            FontError::UnicodePoolExhausted => write!(
+                f,
+                "unicode tables have more than {} entries in total",
+                UNICODE_POOL_SIZE
+            ),
+            FontError::TooBig => write!(f, "font doesn't fit on the screen"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PsfVersion {
+    Psf1,
+    Psf2,
+}
+
+/// Calls `f` for every (code point, glyph) of a unicode table, skipping sequences
+/// as combining characters aren't rendered
+fn for_each_unicode_entry(
+    table: &[u8],
+    version: PsfVersion,
+    glyph_count: usize,
+    mut f: impl FnMut(char, u32),
+) {
+    let mut glyph = 0;
+    match version {
+        PsfVersion::Psf1 => {
+            let mut in_sequence = false;
+            for entry in table.chunks_exact(2) {
+                if glyph >= glyph_count as u32 {
+                    break;
+                }
+                match u16::from_le_bytes([entry[0], entry[1]]) {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    PSF1_START_SEQ => in_sequence = true,
+                    _ if in_sequence => {}
+                    code_point => {
+                        if let Some(c) = char::from_u32(code_point as u32) {
+                            f(c, glyph);
+                        }
+                    }
+                }
+            }
+        }
+        PsfVersion::Psf2 => {
+            let mut i = 0;
+            while i < table.len() && glyph < glyph_count as u32 {
+                match table[i] {
+                    PSF2_SEPARATOR => {
+                        glyph += 1;
+                        i += 1;
+                    }
+                    PSF2_START_SEQ => {
+                        while i < table.len() && table[i] != PSF2_SEPARATOR {
+                            i += 1;
+                        }
+                    }
+                    first_byte => {
+                        let len = utf8_len(first_byte);
+                        let c = table
+                            .get(i..i + len)
+                            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+                            .and_then(|s| s.chars().next());
+                        if let Some(c) = c {
+                            f(c, glyph);
+                        }
+                        i += len;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sorted code point -> glyph index table, built from the font unicode table
+struct UnicodeMap {
+    entries: &'static [(char, u32)],
+}
+
+impl UnicodeMap {
+    fn build(table: &[u8], version: PsfVersion, glyph_count: usize) -> Result<Self, FontError> {
+        let mut len = 0;
+        for_each_unicode_entry(table, version, glyph_count, |_, _| len += 1);
+
+        let entries = UNICODE_POOL
+            .lock()
+            .take(len)
+            .ok_or(FontError::UnicodePoolExhausted)?;
+        let mut i = 0;
+        for_each_unicode_entry(table, version, glyph_count, |c, glyph| {
+            entries[i] = (c, glyph);
+            i += 1;
+        });
+
+        // Sorted by glyph too so the first glyph given for a code point wins when deduplicating
+        entries.sort_unstable();
+        let mut len = 0;
+        for i in 0..entries.len() {
+            if len == 0 || entries[len - 1].0 != entries[i].0 {
+                entries[len] = entries[i];
+                len += 1;
+            }
+        }
+        Ok(UnicodeMap {
+            entries: &entries[..len],
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&self, c: char) -> Option<u32> {
+        self.entries
+            .binary_search_by_key(&c, |(c, _)| *c)
+            .ok()
+            .map(|i| self.entries[i].1)
+    }
+}
+
+pub struct Font {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    glyph_size: usize, // bytes per glyph
+    width: usize,
+    height: usize,
+    unicode_map: Option<UnicodeMap>,
+}
+
+impl fmt::Debug for Font {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Font")
+            .field("glyph_count", &self.glyph_count)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field(
+                "unicode_entries",
+                &self.unicode_map.as_ref().map(UnicodeMap::len),
+            )
+            .finish()
+    }
+}
+
+impl Font {
+    /// Parses a PSF1 or PSF2 font
+    pub fn parse(data: &'static [u8]) -> Result<Self, FontError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else {
+            Err(FontError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Self, FontError> {
+        let mode = *data.get(2).ok_or(FontError::Truncated)?;
+        let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+        let glyph_count: usize = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        if height == 0 {
+            return Err(FontError::BadHeader);
+        }
+
+        let glyphs_end = glyph_count
+            .checked_mul(height)
+            .and_then(|size| size.checked_add(PSF1_HEADER_SIZE))
+            .ok_or(FontError::BadHeader)?;
+        let glyphs = data
+            .get(PSF1_HEADER_SIZE..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+
+        let unicode_map = if mode & (PSF1_MODE_HAS_TAB | PSF1_MODE_SEQ) != 0 {
+            Some(UnicodeMap::build(
+                &data[glyphs_end..],
+                PsfVersion::Psf1,
+                glyph_count,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Font {
+            glyphs,
+            glyph_count,
+            glyph_size: height,
+            width: 8,
+            height,
+            unicode_map,
+        })
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Self, FontError> {
+        let field = |i: usize| -> Result<usize, FontError> {
+            let bytes = data.get(i * 4..i * 4 + 4).ok_or(FontError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        // magic, version, headersize, flags, length, charsize, height, width
+        let header_size = field(2)?;
+        let flags = field(3)? as u32;
+        let glyph_count = field(4)?;
+        let glyph_size = field(5)?;
+        let height = field(6)?;
+        let width = field(7)?;
+        if width == 0 || height == 0 || glyph_size != width.div_ceil(8) * height {
+            return Err(FontError::BadHeader);
+        }
+        // Glyphs can't overlap the header, and there must be one to fall back on
+        if header_size < PSF2_HEADER_SIZE || glyph_count == 0 {
+            return Err(FontError::BadHeader);
+        }
+
+        // The fields come from the module, they can be anything
+        let glyphs_end = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(FontError::BadHeader)?;
+        let glyphs = data
+            .get(header_size..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+
+        let unicode_map = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            Some(UnicodeMap::build(
+                &data[glyphs_end..],
+                PsfVersion::Psf2,
+                glyph_count,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Font {
+            glyphs,
+            glyph_count,
+            glyph_size,
+            width,
+            height,
+            unicode_map,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    /// Index of the glyph for `c`, falling back on U+FFFD then '?' if the font doesn't have it
+    pub fn glyph_index(&self, c: char) -> usize {
+        let lookup = |c: char| match &self.unicode_map {
+            Some(map) => map.get(c).map(|glyph| glyph as usize),
+            // Without unicode table, glyphs are assumed to be in code point order
+            None => Some(c as usize).filter(|i| *i < self.glyph_count),
+        };
+        lookup(c)
+            .or_else(|| lookup(char::REPLACEMENT_CHARACTER))
+            .or_else(|| lookup('?'))
+            .unwrap_or(0)
+    }
+
+    pub fn glyph(&self, c: char) -> Glyph<'_> {
+        let index = self.glyph_index(c);
+        Glyph {
+            font: self,
+            bitmap: &self.glyphs[index * self.glyph_size..(index + 1) * self.glyph_size],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Glyph<'a> {
+    font: &'a Font,
+    bitmap: &'a [u8],
+}
+
+impl Glyph<'_> {
+    /// True if the pixel at (x, y) of the glyph is set
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let bytes_per_row = self.font.width.div_ceil(8);
+        let byte = self.bitmap[y * bytes_per_row + x / 8];
+        (byte >> (7 - x % 8)) & 1 != 0
+    }
+}
+
+fn utf8_len(first_byte: u8) -> usize {
+    match first_byte.leading_ones() {
+        0 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        _ => 1, // Invalid, skipped one byte at a time
+    }
+}
@@ -1,10 +1,20 @@
 // This whole thing needs to be rewritten after having made an allocator
 
+mod ansi;
+mod canvas;
+mod console;
+mod font;
 mod macros;
 mod screen;
+mod surface;
 mod text;
 mod utils;
 
+pub use ansi::*;
+pub use canvas::*;
+pub use console::*;
+pub use font::*;
 pub use screen::*;
+pub use surface::*;
 pub use text::*;
 pub use utils::*;
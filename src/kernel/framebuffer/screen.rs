@@ -1,15 +1,48 @@
 use multiboot2::FramebufferTag;
 
-use super::utils::*;
+use super::{Canvas, Rect, utils::*};
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color(u8, u8, u8, u8);
 
 impl Color {
-    pub fn new(r: u8, g: u8, b: u8, alpha: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8, alpha: u8) -> Self {
         Color(b, g, r, alpha)
     }
+
+    pub const fn r(&self) -> u8 {
+        self.2
+    }
+
+    pub const fn g(&self) -> u8 {
+        self.1
+    }
+
+    pub const fn b(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn alpha(&self) -> u8 {
+        self.3
+    }
+
+    pub const fn is_opaque(&self) -> bool {
+        self.3 == 255
+    }
+
+    /// Source over compositing of `self` on top of `dst`
+    pub fn blend_over(self, dst: Color) -> Color {
+        let alpha = self.3 as u32;
+        let mix =
+            |src: u8, dst: u8| ((src as u32 * alpha + dst as u32 * (255 - alpha)) / 255) as u8;
+        Color(
+            mix(self.0, dst.0),
+            mix(self.1, dst.1),
+            mix(self.2, dst.2),
+            (alpha + dst.3 as u32 * (255 - alpha) / 255) as u8,
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -19,6 +52,7 @@ pub struct Buffer {
     pitch: usize,
     bpp: usize,
     buffer: &'static mut [Color],
+    clip: Rect,
 }
 
 // TODO : Implement a Buffer trait to account for different bpp (also more Color types)
@@ -40,6 +74,7 @@ impl Buffer {
             buffer: unsafe {
                 core::slice::from_raw_parts_mut(framebuffer_tag.address() as *mut Color, len)
             },
+            clip: Rect::new(0, 0, width, height),
         }
     }
 
@@ -63,11 +98,10 @@ impl Buffer {
     }
 
     pub fn clear(&mut self, color: Color) {
-        for y in 0..self.max_y {
-            for x in 0..self.max_x {
-                self.write(x, y, color).unwrap()
-            }
-        }
+        let clip = self.clip;
+        self.clip = self.bounds();
+        self.fill(color);
+        self.clip = clip;
     }
 
     pub fn max_y(&self) -> usize {
@@ -113,3 +147,46 @@ impl Buffer {
         Ok(())
     }
 }
+
+impl Canvas for Buffer {
+    fn width(&self) -> usize {
+        self.max_x
+    }
+
+    fn height(&self) -> usize {
+        self.max_y
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        self.buffer[self.coord_to_pxl_offset(x as isize, y as isize) as usize]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let offset = self.coord_to_pxl_offset(x as isize, y as isize) as usize;
+        self.buffer[offset] = color;
+    }
+
+    fn clip_rect(&self) -> Rect {
+        self.clip
+    }
+
+    fn set_clip_rect(&mut self, rect: Rect) {
+        self.clip = rect.intersection(&self.bounds());
+    }
+
+    fn fill_span(&mut self, y: isize, x0: isize, x1: isize, color: Color) {
+        let clip = self.clip;
+        let (x0, x1) = (x0.max(clip.x), x1.min(clip.right() - 1));
+        if y < clip.y || y >= clip.bottom() || x0 > x1 {
+            return;
+        }
+        let start = self.coord_to_pxl_offset(x0, y) as usize;
+        let span = &mut self.buffer[start..=start + (x1 - x0) as usize];
+        if color.is_opaque() {
+            span.fill(color);
+        } else {
+            span.iter_mut()
+                .for_each(|dst| *dst = color.blend_over(*dst));
+        }
+    }
+}
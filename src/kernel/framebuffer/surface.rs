@@ -0,0 +1,108 @@
+use super::{Canvas, Color, Rect};
+
+/// Read only image in memory, to be blitted on a canvas
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a [Color],
+}
+
+impl<'a> Image<'a> {
+    /// `pixels` is row major and must hold at least `width * height` colors
+    pub fn new(pixels: &'a [Color], width: usize, height: usize) -> Self {
+        assert!(
+            pixels.len() >= width * height,
+            "Image too small for its size"
+        );
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Offscreen canvas over a caller provided pixel array
+// TODO : own the pixels once there is an allocator
+#[derive(Debug)]
+pub struct Surface<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a mut [Color],
+    clip: Rect,
+}
+
+impl<'a> Surface<'a> {
+    /// `pixels` is row major and must hold at least `width * height` colors
+    pub fn new(pixels: &'a mut [Color], width: usize, height: usize) -> Self {
+        assert!(
+            pixels.len() >= width * height,
+            "Surface too small for its size"
+        );
+        Surface {
+            width,
+            height,
+            pixels,
+            clip: Rect::new(0, 0, width, height),
+        }
+    }
+
+    pub fn as_image(&self) -> Image<'_> {
+        Image::new(self.pixels, self.width, self.height)
+    }
+}
+
+impl Canvas for Surface<'_> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    fn clip_rect(&self) -> Rect {
+        self.clip
+    }
+
+    fn set_clip_rect(&mut self, rect: Rect) {
+        self.clip = rect.intersection(&self.bounds());
+    }
+
+    fn fill_span(&mut self, y: isize, x0: isize, x1: isize, color: Color) {
+        let clip = self.clip;
+        let (x0, x1) = (x0.max(clip.x), x1.min(clip.right() - 1));
+        if y < clip.y || y >= clip.bottom() || x0 > x1 {
+            return;
+        }
+        let row = y as usize * self.width;
+        let span = &mut self.pixels[row + x0 as usize..=row + x1 as usize];
+        if color.is_opaque() {
+            span.fill(color);
+        } else {
+            span.iter_mut()
+                .for_each(|dst| *dst = color.blend_over(*dst));
+        }
+    }
+}
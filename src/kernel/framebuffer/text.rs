@@ -1,12 +1,18 @@
 use core::fmt;
 
+use bitflags::bitflags;
 use spin::MutexGuard;
 
-use super::{BUFFER, Buffer, Color, OutOfBoundsError, TEXT_BUFFER};
+use super::{
+    AnsiAction, AnsiParser, BUFFER, Buffer, CONSOLE, Cell, Color, Console, CsiSequence, Font,
+    FontError, MAX_COLUMNS, OutOfBoundsError, TEXT_BUFFER, TermColor, palette_color,
+    parse_extended_color,
+};
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct TextBuffer {
+    font: &'static Font,
     max_char_x: usize,
     max_char_y: usize,
     scale_factor: usize,
@@ -15,13 +21,17 @@ pub struct TextBuffer {
 }
 
 impl TextBuffer {
-    pub fn new(scale_factor: usize) -> Self {
+    pub fn new(font: &'static Font, scale_factor: usize) -> Self {
         let buffer = BUFFER.get().expect("Buffer required").lock();
-        let max_char_x = buffer.max_x() / (VGA_CHAR_SIZE.0 * scale_factor);
-        let padding_char_x = (buffer.max_x() % (VGA_CHAR_SIZE.0 * scale_factor)) * VGA_CHAR_SIZE.1;
-        let max_char_y = buffer.max_y() / (VGA_CHAR_SIZE.1 * scale_factor);
-        let padding_char_y = (buffer.max_y() % (VGA_CHAR_SIZE.1 * scale_factor)) * VGA_CHAR_SIZE.0;
+        let (char_width, char_height) = (font.width(), font.height());
+        let max_char_x = (buffer.max_x() / (char_width * scale_factor)).min(MAX_COLUMNS);
+        // Everything right of the last column, more than a partial cell when there are more than MAX_COLUMNS
+        let padding_char_x =
+            (buffer.max_x() - max_char_x * char_width * scale_factor) * char_height * scale_factor;
+        let max_char_y = buffer.max_y() / (char_height * scale_factor);
+        let padding_char_y = (buffer.max_y() % (char_height * scale_factor)) * char_width;
         TextBuffer {
+            font,
             max_char_x,
             max_char_y,
             scale_factor,
@@ -30,21 +40,39 @@ impl TextBuffer {
         }
     }
 
+    /// Whether at least one cell of `font` scaled by `scale_factor` fits on the screen
+    pub fn fits(font: &Font, scale_factor: usize) -> bool {
+        let buffer = Self::get_buffer();
+        let fits = |font_size: usize, screen_size: usize| {
+            font_size
+                .checked_mul(scale_factor)
+                .is_some_and(|size| size <= screen_size)
+        };
+        fits(font.width(), buffer.max_x()) && fits(font.height(), buffer.max_y())
+    }
+
+    /// Size in characters, (rows, columns)
+    pub fn size(&self) -> (usize, usize) {
+        (self.max_char_y, self.max_char_x)
+    }
+
+    pub fn scale_factor(&self) -> usize {
+        self.scale_factor
+    }
+
     fn get_buffer() -> MutexGuard<'static, Buffer> {
         BUFFER.get().expect("Buffer required").lock()
     }
 
     fn char_coord_to_buffer_coord(&self, char_x: isize, char_y: isize) -> (isize, isize) {
-        let real_char_x = char_x * (VGA_CHAR_SIZE.0 * self.scale_factor) as isize;
-        let real_char_y = char_y * (VGA_CHAR_SIZE.1 * self.scale_factor) as isize;
+        let real_char_x = char_x * (self.font.width() * self.scale_factor) as isize;
+        let real_char_y = char_y * (self.font.height() * self.scale_factor) as isize;
         (real_char_x, real_char_y)
     }
 
-    // Assumes the slice is of length 16 (16 bytes = 8*16 pixels = char)
-    #[allow(clippy::needless_range_loop)]
-    fn draw_char_rect(
+    pub fn write_char(
         &mut self,
-        rect: &[u8],
+        r#char: char,
         char_x: usize,
         char_y: usize,
         background_color: Color,
@@ -58,17 +86,17 @@ impl TextBuffer {
                 self.max_char_y,
             ));
         }
+        let glyph = self.font.glyph(r#char);
+        let (buffer_x, buffer_y) =
+            self.char_coord_to_buffer_coord(char_x as isize, char_y as isize);
         let mut buffer = Self::get_buffer();
-        for char_pxl_x in 0..VGA_CHAR_SIZE.0 {
-            for char_pxl_y in 0..VGA_CHAR_SIZE.1 {
-                let char_pxl = (rect[char_pxl_y] >> (VGA_CHAR_SIZE.0 - 1 - char_pxl_x)) & 1;
-                let color = if char_pxl == 0 {
-                    background_color
-                } else {
+        for char_pxl_y in 0..self.font.height() {
+            for char_pxl_x in 0..self.font.width() {
+                let color = if glyph.pixel(char_pxl_x, char_pxl_y) {
                     foreground_color
+                } else {
+                    background_color
                 };
-                let (buffer_x, buffer_y) =
-                    self.char_coord_to_buffer_coord(char_x as isize, char_y as isize);
                 for i in 0..self.scale_factor {
                     for j in 0..self.scale_factor {
                         buffer.write(
@@ -83,24 +111,6 @@ impl TextBuffer {
         Ok(())
     }
 
-    fn write_char(
-        &mut self,
-        r#char: char,
-        char_x: usize,
-        char_y: usize,
-        background_color: Color,
-        foreground_color: Color,
-    ) -> Result<(), OutOfBoundsError> {
-        let char_rect = nth_char(char_to_font_idx(r#char));
-        self.draw_char_rect(
-            char_rect,
-            char_x,
-            char_y,
-            background_color,
-            foreground_color,
-        )
-    }
-
     pub fn write_str(
         &mut self,
         string: &str,
@@ -141,89 +151,500 @@ impl TextBuffer {
         let (x, y) = self.char_coord_to_buffer_coord(char_x as isize, char_y as isize);
         let (dx, dy) = self.char_coord_to_buffer_coord(char_dx, char_dy);
         let padding = self.padding_char_x * text_len / self.max_char_x;
-        let len =
-            text_len * VGA_CHAR_SIZE.0 * VGA_CHAR_SIZE.1 * self.scale_factor * self.scale_factor
-                + padding;
+        let len = text_len
+            * self.font.width()
+            * self.font.height()
+            * self.scale_factor
+            * self.scale_factor
+            + padding;
         let mut buffer = Self::get_buffer();
         buffer.move_slice(x as usize, y as usize, len, dx, dy)
     }
 
     fn clear_line(&mut self, y: usize, background_color: Color) -> Result<(), OutOfBoundsError> {
-        for x in 0..self.max_char_x {
+        self.clear_cells(y, 0..self.max_char_x, background_color)
+    }
+
+    fn clear_cells(
+        &mut self,
+        y: usize,
+        xs: core::ops::Range<usize>,
+        background_color: Color,
+    ) -> Result<(), OutOfBoundsError> {
+        for x in xs {
             self.write_char(' ', x, y, background_color, background_color)?;
         }
         Ok(())
     }
+
+    /// Scrolls the lines `top..=bottom` by `lines` (positive is up), clearing the uncovered lines
+    pub fn scroll(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        lines: isize,
+        background_color: Color,
+    ) -> Result<(), OutOfBoundsError> {
+        let height = bottom + 1 - top;
+        let shift = lines.unsigned_abs().min(height);
+        if shift < height {
+            let text_len = self.max_char_x * (height - shift);
+            if lines > 0 {
+                self.move_slice_text(0, top + shift, text_len, 0, -(shift as isize))?;
+            } else {
+                self.move_slice_text(0, top, text_len, 0, shift as isize)?;
+            }
+        }
+        let cleared = if lines > 0 {
+            bottom + 1 - shift..bottom + 1
+        } else {
+            top..top + shift
+        };
+        for y in cleared {
+            self.clear_line(y, background_color)?;
+        }
+        Ok(())
+    }
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TextAttributes: u8 {
+        const BOLD = 1 << 0;
+        const INVERSE = 1 << 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    column: usize,
+    row: usize,
+    foreground: TermColor,
+    background: TermColor,
+    attributes: TextAttributes,
+}
+
+/// Console writing at a cursor, interpreting the ANSI escape sequences handled by `AnsiParser`
 #[derive(Debug)]
 pub struct Writer {
     column_position: usize,
-    background_code: Color,
-    foreground_code: Color,
+    row_position: usize,
+    default_background: Color,
+    default_foreground: Color,
+    background_code: TermColor,
+    foreground_code: TermColor,
+    attributes: TextAttributes,
+    saved_cursor: Option<SavedCursor>,
+    scroll_region: Option<(usize, usize)>, // top and bottom lines, whole screen if None
+    parser: AnsiParser,
 }
 
 impl Default for Writer {
     fn default() -> Self {
+        Self::new_with(&Self::get_text_buffer())
+    }
+}
+
+impl Writer {
+    fn new_with(text_buffer: &TextBuffer) -> Self {
         Writer {
             column_position: 0,
-            background_code: Color::new(0, 0, 0, 255),
-            foreground_code: Color::new(255, 255, 255, 255),
+            // Starting at the bottom so that the output scrolls up like a log
+            row_position: text_buffer.max_char_y - 1,
+            default_background: Color::new(0, 0, 0, 255),
+            default_foreground: Color::new(255, 255, 255, 255),
+            background_code: TermColor::Default,
+            foreground_code: TermColor::Default,
+            attributes: TextAttributes::empty(),
+            saved_cursor: None,
+            scroll_region: None,
+            parser: AnsiParser::default(),
         }
     }
-}
 
-impl Writer {
     fn get_text_buffer() -> MutexGuard<'static, TextBuffer> {
         TEXT_BUFFER.get().expect("Text buffer required").lock()
     }
 
+    /// Changes the color used by the default background (SGR 49)
     pub fn change_background_color(&mut self, color: Color) {
-        self.background_code = color;
+        self.default_background = color;
     }
 
+    /// Changes the color used by the default foreground (SGR 39)
     pub fn change_foreground_color(&mut self, color: Color) {
-        self.foreground_code = color;
+        self.default_foreground = color;
     }
 
     pub fn write_str(&mut self, string: &str) -> Result<(), OutOfBoundsError> {
         let mut text_buffer = Self::get_text_buffer();
+        let mut console = CONSOLE.lock();
+        let mut screen = Screen {
+            console: &mut console,
+            text_buffer: &mut text_buffer,
+        };
+        screen.console.reset_view(screen.text_buffer);
+        screen.console.hide_cursor(screen.text_buffer);
         for char in string.chars() {
-            match char {
-                '\n' => self.new_line(&mut text_buffer),
-                char => {
-                    if self.column_position >= text_buffer.max_char_x {
-                        self.new_line(&mut text_buffer);
-                    }
-                    let max_char_y = text_buffer.max_char_y;
-                    text_buffer.write_char(
-                        char,
-                        self.column_position,
-                        max_char_y - 1,
-                        self.background_code,
-                        self.foreground_code,
-                    )?;
-                    self.column_position += 1;
+            match self.parser.advance(char) {
+                Some(AnsiAction::Print(char)) => self.put_char(&mut screen, char)?,
+                Some(AnsiAction::Control(char)) => self.control(&mut screen, char)?,
+                Some(AnsiAction::Csi(csi)) => self.csi(&mut screen, &csi)?,
+                Some(AnsiAction::Escape(char)) => self.escape(&mut screen, char)?,
+                None => {}
+            }
+        }
+        let column = self.column_position.min(screen.columns() - 1);
+        screen
+            .console
+            .show_cursor(screen.text_buffer, self.row_position, column);
+        Ok(())
+    }
+
+    /// Switches to another font, re-rendering everything from the cell model
+    pub fn set_font(&mut self, font: &'static Font, scale_factor: usize) -> Result<(), FontError> {
+        if !TextBuffer::fits(font, scale_factor) {
+            return Err(FontError::TooBig);
+        }
+        let mut text_buffer = Self::get_text_buffer();
+        let mut console = CONSOLE.lock();
+        let old_rows = text_buffer.size().0;
+        *text_buffer = TextBuffer::new(font, scale_factor);
+        let (rows, columns) = text_buffer.size();
+        let shift = console.resize(old_rows, rows);
+        self.resized(rows, columns, shift);
+
+        // The margins not covered by cells don't get redrawn
+        TextBuffer::get_buffer().clear(self.default_background);
+        console.redraw(&mut text_buffer);
+        console.show_cursor(&mut text_buffer, self.row_position, self.column_position);
+        Ok(())
+    }
+
+    /// Called when the text buffer changed size, the screen content moved up by `shift` lines
+    fn resized(&mut self, rows: usize, columns: usize, shift: isize) {
+        self.row_position = self
+            .row_position
+            .saturating_add_signed(-shift)
+            .min(rows - 1);
+        self.column_position = self.column_position.min(columns - 1);
+        self.scroll_region = None;
+        self.saved_cursor = None;
+    }
+
+    // Colors actually drawn, after bold and inverse are applied
+    fn colors(&self) -> (Color, Color) {
+        let resolve = |color: TermColor, default: Color, bold: bool| match color {
+            TermColor::Default => default,
+            TermColor::Indexed(index) if bold && index < 8 => palette_color(index + 8),
+            TermColor::Indexed(index) => palette_color(index),
+            TermColor::Rgb(color) => color,
+        };
+        let bold = self.attributes.contains(TextAttributes::BOLD);
+        let foreground = resolve(self.foreground_code, self.default_foreground, bold);
+        let background = resolve(self.background_code, self.default_background, false);
+        if self.attributes.contains(TextAttributes::INVERSE) {
+            (background, foreground)
+        } else {
+            (foreground, background)
+        }
+    }
+
+    // Background used to clear cells, inverse doesn't apply to erased cells
+    fn erase_color(&self) -> Color {
+        match self.background_code {
+            TermColor::Default => self.default_background,
+            TermColor::Indexed(index) => palette_color(index),
+            TermColor::Rgb(color) => color,
+        }
+    }
+
+    fn scroll_region(&self, screen: &Screen) -> (usize, usize) {
+        self.scroll_region.unwrap_or((0, screen.rows() - 1))
+    }
+
+    fn put_char(&mut self, screen: &mut Screen, char: char) -> Result<(), OutOfBoundsError> {
+        if self.column_position >= screen.columns() {
+            self.column_position = 0;
+            self.line_feed(screen)?;
+        }
+        let (foreground, background) = self.colors();
+        let cell = Cell {
+            char,
+            foreground,
+            background,
+            attributes: self.attributes,
+        };
+        screen.put(self.row_position, self.column_position, cell)?;
+        self.column_position += 1;
+        Ok(())
+    }
+
+    fn line_feed(&mut self, screen: &mut Screen) -> Result<(), OutOfBoundsError> {
+        let (top, bottom) = self.scroll_region(screen);
+        if self.row_position == bottom {
+            screen.scroll(top, bottom, 1, self.erase_color())?;
+        } else if self.row_position + 1 < screen.rows() {
+            self.row_position += 1;
+        }
+        Ok(())
+    }
+
+    fn reverse_line_feed(&mut self, screen: &mut Screen) -> Result<(), OutOfBoundsError> {
+        let (top, bottom) = self.scroll_region(screen);
+        if self.row_position == top {
+            screen.scroll(top, bottom, -1, self.erase_color())?;
+        } else {
+            self.row_position = self.row_position.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    fn control(&mut self, screen: &mut Screen, char: char) -> Result<(), OutOfBoundsError> {
+        match char {
+            // Newlines also return to the first column, as the console has always done
+            '\n' | '\x0b' | '\x0c' => {
+                self.column_position = 0;
+                self.line_feed(screen)?;
+            }
+            '\r' => self.column_position = 0,
+            '\t' => {
+                self.column_position =
+                    ((self.column_position / 8 + 1) * 8).min(screen.columns() - 1)
+            }
+            '\x08' => self.column_position = self.column_position.saturating_sub(1),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn escape(&mut self, screen: &mut Screen, char: char) -> Result<(), OutOfBoundsError> {
+        match char {
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            'D' => self.line_feed(screen)?,
+            'E' => {
+                self.column_position = 0;
+                self.line_feed(screen)?;
+            }
+            'M' => self.reverse_line_feed(screen)?,
+            'c' => {
+                let (default_foreground, default_background) =
+                    (self.default_foreground, self.default_background);
+                *self = Writer {
+                    default_foreground,
+                    default_background,
+                    row_position: 0,
+                    ..Writer::new_with(screen.text_buffer)
+                };
+                for y in 0..screen.rows() {
+                    screen.clear_line(y, default_background)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn csi(&mut self, screen: &mut Screen, csi: &CsiSequence) -> Result<(), OutOfBoundsError> {
+        let (max_x, max_y) = (screen.columns(), screen.rows());
+        let n = csi.param(0, 1);
+        match (csi.private, csi.final_byte) {
+            (None, 'A') => self.row_position = self.row_position.saturating_sub(n),
+            (None, 'B') => self.row_position = (self.row_position + n).min(max_y - 1),
+            (None, 'C') => self.column_position = (self.column_position + n).min(max_x - 1),
+            (None, 'D') => self.column_position = self.column_position.saturating_sub(n),
+            (None, 'E') => {
+                self.row_position = (self.row_position + n).min(max_y - 1);
+                self.column_position = 0;
+            }
+            (None, 'F') => {
+                self.row_position = self.row_position.saturating_sub(n);
+                self.column_position = 0;
+            }
+            (None, 'G') => self.column_position = (n - 1).min(max_x - 1),
+            (None, 'd') => self.row_position = (n - 1).min(max_y - 1),
+            (None, 'H' | 'f') => {
+                self.row_position = (csi.param(0, 1) - 1).min(max_y - 1);
+                self.column_position = (csi.param(1, 1) - 1).min(max_x - 1);
+            }
+            (None, 'J') => self.erase_screen(screen, csi.param(0, 0))?,
+            (None, 'K') => self.erase_line(screen, csi.param(0, 0))?,
+            (None, 'S') => {
+                let (top, bottom) = self.scroll_region(screen);
+                screen.scroll(top, bottom, n as isize, self.erase_color())?;
+            }
+            (None, 'T') => {
+                let (top, bottom) = self.scroll_region(screen);
+                screen.scroll(top, bottom, -(n as isize), self.erase_color())?;
+            }
+            (None, 'm') => self.select_graphic_rendition(csi),
+            (None, 'r') => {
+                let top = csi.param(0, 1) - 1;
+                let bottom = csi.param(1, max_y).min(max_y) - 1;
+                if top < bottom {
+                    self.scroll_region =
+                        Some((top, bottom)).filter(|region| *region != (0, max_y - 1));
+                    self.row_position = 0;
+                    self.column_position = 0;
                 }
             }
+            (Some('?'), 'h' | 'l') if csi.params().contains(&25) => {
+                screen
+                    .console
+                    .enable_cursor(screen.text_buffer, csi.final_byte == 'h');
+            }
+            (None, 's') => self.save_cursor(),
+            (None, 'u') => self.restore_cursor(),
+            _ => {}
         }
         Ok(())
     }
 
-    fn clear_line(&mut self, text_buffer: &mut MutexGuard<'_, TextBuffer>) {
-        let max_char_y = text_buffer.max_char_y;
-        text_buffer
-            .clear_line(max_char_y - 1, self.background_code)
-            .expect("Is not out of bounds as it is a function called manually.");
-        self.column_position = 0;
+    fn erase_screen(&mut self, screen: &mut Screen, mode: usize) -> Result<(), OutOfBoundsError> {
+        let color = self.erase_color();
+        let (row, max_y) = (self.row_position, screen.rows());
+        let lines = match mode {
+            0 => {
+                self.erase_line(screen, 0)?;
+                row + 1..max_y
+            }
+            1 => {
+                self.erase_line(screen, 1)?;
+                0..row
+            }
+            2 | 3 => 0..max_y,
+            _ => return Ok(()),
+        };
+        for y in lines {
+            screen.clear_line(y, color)?;
+        }
+        Ok(())
     }
 
-    fn new_line(&mut self, text_buffer: &mut MutexGuard<'_, TextBuffer>) {
-        let text_len = text_buffer.max_char_x * (text_buffer.max_char_y - 1);
-        text_buffer
-            .move_slice_text(0, 1, text_len, 0, -1)
-            .expect("Is not out of bound as it is a function called manually.");
-        self.clear_line(text_buffer);
+    fn erase_line(&mut self, screen: &mut Screen, mode: usize) -> Result<(), OutOfBoundsError> {
+        let (column, max_x) = (
+            self.column_position.min(screen.columns() - 1),
+            screen.columns(),
+        );
+        let columns = match mode {
+            0 => column..max_x,
+            1 => 0..column + 1,
+            2 => 0..max_x,
+            _ => return Ok(()),
+        };
+        screen.clear_cells(self.row_position, columns, self.erase_color())
+    }
+
+    fn select_graphic_rendition(&mut self, csi: &CsiSequence) {
+        let params = csi.params();
+        if params.is_empty() {
+            self.reset_graphic_rendition();
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => self.reset_graphic_rendition(),
+                1 => self.attributes.insert(TextAttributes::BOLD),
+                22 => self.attributes.remove(TextAttributes::BOLD),
+                7 => self.attributes.insert(TextAttributes::INVERSE),
+                27 => self.attributes.remove(TextAttributes::INVERSE),
+                code @ 30..=37 => self.foreground_code = TermColor::Indexed(code as u8 - 30),
+                code @ 90..=97 => self.foreground_code = TermColor::Indexed(code as u8 - 90 + 8),
+                39 => self.foreground_code = TermColor::Default,
+                code @ 40..=47 => self.background_code = TermColor::Indexed(code as u8 - 40),
+                code @ 100..=107 => self.background_code = TermColor::Indexed(code as u8 - 100 + 8),
+                49 => self.background_code = TermColor::Default,
+                code @ (38 | 48) => {
+                    if let Some((color, consumed)) = parse_extended_color(&params[i + 1..]) {
+                        if code == 38 {
+                            self.foreground_code = color;
+                        } else {
+                            self.background_code = color;
+                        }
+                        i += consumed;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn reset_graphic_rendition(&mut self) {
+        self.foreground_code = TermColor::Default;
+        self.background_code = TermColor::Default;
+        self.attributes = TextAttributes::empty();
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            column: self.column_position,
+            row: self.row_position,
+            foreground: self.foreground_code,
+            background: self.background_code,
+            attributes: self.attributes,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        if let Some(saved) = self.saved_cursor {
+            self.column_position = saved.column;
+            self.row_position = saved.row;
+            self.foreground_code = saved.foreground;
+            self.background_code = saved.background;
+            self.attributes = saved.attributes;
+        } else {
+            self.column_position = 0;
+            self.row_position = 0;
+            self.reset_graphic_rendition();
+        }
+    }
+}
+
+// What the writer draws on : the cell model, rendered through the text buffer
+struct Screen<'a> {
+    console: &'a mut Console,
+    text_buffer: &'a mut TextBuffer,
+}
+
+impl Screen<'_> {
+    fn rows(&self) -> usize {
+        self.text_buffer.max_char_y
+    }
+
+    fn columns(&self) -> usize {
+        self.text_buffer.max_char_x
+    }
+
+    fn put(&mut self, row: usize, column: usize, cell: Cell) -> Result<(), OutOfBoundsError> {
+        self.console.set_cell(self.text_buffer, row, column, cell)
+    }
+
+    fn clear_cells(
+        &mut self,
+        row: usize,
+        columns: core::ops::Range<usize>,
+        background: Color,
+    ) -> Result<(), OutOfBoundsError> {
+        self.console
+            .clear_cells(self.text_buffer, row, columns, background)
+    }
+
+    fn clear_line(&mut self, row: usize, background: Color) -> Result<(), OutOfBoundsError> {
+        self.clear_cells(row, 0..self.columns(), background)
+    }
+
+    fn scroll(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        lines: isize,
+        background: Color,
+    ) -> Result<(), OutOfBoundsError> {
+        self.console
+            .scroll(self.text_buffer, top, bottom, lines, background)
     }
 }
 
@@ -236,275 +657,4 @@ impl fmt::Write for Writer {
 
 ////////////////////////////////////////////
 
-const VGA_FONT: &[u8] = include_bytes!("../../../assets/VGA9.F16");
-const VGA_CHAR_SIZE: (usize, usize) = (8, 16);
-
 pub const VGA_TEST_SLICE: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼ !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~⌂ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■ ";
-
-fn nth_char(n: usize) -> &'static [u8] {
-    &VGA_FONT[n * 16..(n + 1) * 16]
-}
-
-// TODO : transform this to a BTreeMap once there is a memory allocator
-fn char_to_font_idx(c: char) -> usize {
-    match c {
-        //' ' => 0,
-        '☺' => 1,
-        '☻' => 2,
-        '♥' => 3,
-        '♦' => 4,
-        '♣' => 5,
-        '♠' => 6,
-        '•' => 7,
-        '◘' => 8,
-        '○' => 9,
-        '◙' => 10,
-        '♂' => 11,
-        '♀' => 12,
-        '♪' => 13,
-        '♫' => 14,
-        '☼' => 15,
-        '►' => 16,
-        '◄' => 17,
-        '↕' => 18,
-        '‼' => 19,
-        '¶' => 20,
-        '§' => 21,
-        '▬' => 22,
-        '↨' => 23,
-        '↑' => 24,
-        '↓' => 25,
-        '→' => 26,
-        '←' => 27,
-        '∟' => 28,
-        '↔' => 29,
-        '▲' => 30,
-        '▼' => 31,
-        ' ' => 32, // Space (again)
-        '!' => 33,
-        '"' => 34,
-        '#' => 35,
-        '$' => 36,
-        '%' => 37,
-        '&' => 38,
-        '\'' => 39,
-        '(' => 40,
-        ')' => 41,
-        '*' => 42,
-        '+' => 43,
-        ',' => 44,
-        '-' => 45,
-        '.' => 46,
-        '/' => 47,
-        '0' => 48,
-        '1' => 49,
-        '2' => 50,
-        '3' => 51,
-        '4' => 52,
-        '5' => 53,
-        '6' => 54,
-        '7' => 55,
-        '8' => 56,
-        '9' => 57,
-        ':' => 58,
-        ';' => 59,
-        '<' => 60,
-        '=' => 61,
-        '>' => 62,
-        '?' => 63,
-        '@' => 64,
-        'A' => 65,
-        'B' => 66,
-        'C' => 67,
-        'D' => 68,
-        'E' => 69,
-        'F' => 70,
-        'G' => 71,
-        'H' => 72,
-        'I' => 73,
-        'J' => 74,
-        'K' => 75,
-        'L' => 76,
-        'M' => 77,
-        'N' => 78,
-        'O' => 79,
-        'P' => 80,
-        'Q' => 81,
-        'R' => 82,
-        'S' => 83,
-        'T' => 84,
-        'U' => 85,
-        'V' => 86,
-        'W' => 87,
-        'X' => 88,
-        'Y' => 89,
-        'Z' => 90,
-        '[' => 91,
-        '\\' => 92,
-        ']' => 93,
-        '^' => 94,
-        '_' => 95,
-        '`' => 96,
-        'a' => 97,
-        'b' => 98,
-        'c' => 99,
-        'd' => 100,
-        'e' => 101,
-        'f' => 102,
-        'g' => 103,
-        'h' => 104,
-        'i' => 105,
-        'j' => 106,
-        'k' => 107,
-        'l' => 108,
-        'm' => 109,
-        'n' => 110,
-        'o' => 111,
-        'p' => 112,
-        'q' => 113,
-        'r' => 114,
-        's' => 115,
-        't' => 116,
-        'u' => 117,
-        'v' => 118,
-        'w' => 119,
-        'x' => 120,
-        'y' => 121,
-        'z' => 122,
-        '{' => 123,
-        '|' => 124,
-        '}' => 125,
-        '~' => 126,
-        '⌂' => 127,
-        'Ç' => 128,
-        'ü' => 129,
-        'é' => 130,
-        'â' => 131,
-        'ä' => 132,
-        'à' => 133,
-        'å' => 134,
-        'ç' => 135,
-        'ê' => 136,
-        'ë' => 137,
-        'è' => 138,
-        'ï' => 139,
-        'î' => 140,
-        'ì' => 141,
-        'Ä' => 142,
-        'Å' => 143,
-        'É' => 144,
-        'æ' => 145,
-        'Æ' => 146,
-        'ô' => 147,
-        'ö' => 148,
-        'ò' => 149,
-        'û' => 150,
-        'ù' => 151,
-        'ÿ' => 152,
-        'Ö' => 153,
-        'Ü' => 154,
-        '¢' => 155,
-        '£' => 156,
-        '¥' => 157,
-        '₧' => 158,
-        'ƒ' => 159,
-        'á' => 160,
-        'í' => 161,
-        'ó' => 162,
-        'ú' => 163,
-        'ñ' => 164,
-        'Ñ' => 165,
-        'ª' => 166,
-        'º' => 167,
-        '¿' => 168,
-        '⌐' => 169,
-        '¬' => 170,
-        '½' => 171,
-        '¼' => 172,
-        '¡' => 173,
-        '«' => 174,
-        '»' => 175,
-        '░' => 176,
-        '▒' => 177,
-        '▓' => 178,
-        '│' => 179,
-        '┤' => 180,
-        '╡' => 181,
-        '╢' => 182,
-        '╖' => 183,
-        '╕' => 184,
-        '╣' => 185,
-        '║' => 186,
-        '╗' => 187,
-        '╝' => 188,
-        '╜' => 189,
-        '╛' => 190,
-        '┐' => 191,
-        '└' => 192,
-        '┴' => 193,
-        '┬' => 194,
-        '├' => 195,
-        '─' => 196,
-        '┼' => 197,
-        '╞' => 198,
-        '╟' => 199,
-        '╚' => 200,
-        '╔' => 201,
-        '╩' => 202,
-        '╦' => 203,
-        '╠' => 204,
-        '═' => 205,
-        '╬' => 206,
-        '╧' => 207,
-        '╨' => 208,
-        '╤' => 209,
-        '╥' => 210,
-        '╙' => 211,
-        '╘' => 212,
-        '╒' => 213,
-        '╓' => 214,
-        '╫' => 215,
-        '╪' => 216,
-        '┘' => 217,
-        '┌' => 218,
-        '█' => 219,
-        '▄' => 220,
-        '▌' => 221,
-        '▐' => 222,
-        '▀' => 223,
-        'α' => 224,
-        'ß' => 225,
-        'Γ' => 226,
-        'π' => 227,
-        'Σ' => 228,
-        'σ' => 229,
-        'µ' => 230,
-        'τ' => 231,
-        'Φ' => 232,
-        'Θ' => 233,
-        'Ω' => 234,
-        'δ' => 235,
-        '∞' => 236,
-        'φ' => 237,
-        'ε' => 238,
-        '∩' => 239,
-        '≡' => 240,
-        '±' => 241,
-        '≥' => 242,
-        '≤' => 243,
-        '⌠' => 244,
-        '⌡' => 245,
-        '÷' => 246,
-        '≈' => 247,
-        '°' => 248,
-        '∙' => 249,
-        '·' => 250,
-        '√' => 251,
-        'ⁿ' => 252,
-        '²' => 253,
-        '■' => 254,
-        ' ' => 255, // NBSP
-
-        _ => 178,
-    }
-}
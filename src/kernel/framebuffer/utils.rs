@@ -5,9 +5,16 @@ use core::fmt::{self, Write};
 use lazy_static::lazy_static;
 use spin::{Mutex, Once};
 
-use crate::{MULTIBOOT2_INFO, x86::without_interrupts};
+use crate::{
+    MULTIBOOT2_INFO, find_module,
+    paging::{self, PAGE_SIZE, PagingError},
+    params::{ParamError, ParamType},
+    x86::{self, Feature, MemoryType, MsrError, cpu_info, without_interrupts},
+};
 
-use super::{Buffer, TextBuffer, Writer};
+use super::{
+    BUILTIN_FONT, Buffer, CONSOLE, DEFAULT_SCROLLBACK, Font, FontError, TextBuffer, Writer,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub enum OutOfBoundsError {
@@ -71,10 +78,62 @@ impl SliceOutOfBoundsError {
 
 lazy_static! {
     pub static ref BUFFER: Once<Mutex<Buffer>> = Once::new();
+    pub static ref FONT: Once<Font> = Once::new();
     pub static ref TEXT_BUFFER: Once<Mutex<TextBuffer>> = Once::new();
     pub static ref WRITER: Once<Mutex<Writer>> = Once::new();
 }
 
+crate::kernel_param!(pub FB_SCALE: usize = 1, "fb.scale", "Scale factor of the console font");
+crate::kernel_param!(
+    pub FB_SCROLLBACK: usize = DEFAULT_SCROLLBACK,
+    "fb.scrollback",
+    "Lines of console history kept above the screen"
+);
+crate::kernel_param!(
+    pub CONSOLE_OUTPUT: ConsoleOutput = ConsoleOutput::Framebuffer,
+    "console",
+    "Where print! goes : fb, serial or both"
+);
+
+/// Destination of `print!`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleOutput {
+    Framebuffer,
+    Serial,
+    Both,
+}
+
+impl ConsoleOutput {
+    fn framebuffer(self) -> bool {
+        self != ConsoleOutput::Serial
+    }
+
+    fn serial(self) -> bool {
+        self != ConsoleOutput::Framebuffer
+    }
+}
+
+impl fmt::Display for ConsoleOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleOutput::Framebuffer => write!(f, "fb"),
+            ConsoleOutput::Serial => write!(f, "serial"),
+            ConsoleOutput::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl ParamType for ConsoleOutput {
+    fn parse(value: &'static str) -> Result<Self, ParamError> {
+        match value {
+            "fb" => Ok(ConsoleOutput::Framebuffer),
+            "serial" => Ok(ConsoleOutput::Serial),
+            "both" => Ok(ConsoleOutput::Both),
+            _ => Err(ParamError::InvalidValue("fb, serial or both")),
+        }
+    }
+}
+
 pub fn init_graphics() {
     let framebuffer_tag = MULTIBOOT2_INFO
         .get()
@@ -83,18 +142,81 @@ pub fn init_graphics() {
         .expect("Framebuffer required")
         .expect("Framebuffer required");
 
-    // TODO : Causes GPF and is probably not needed
-    // let height = framebuffer_tag.height() as usize;
-    // let pitch = framebuffer_tag.pitch() as usize;
-    // crate::x86::set_mtrr_wc(framebuffer_tag.address() as usize, height * pitch).expect("MTTR WC failed");
+    let fb_addr = framebuffer_tag.address() as usize;
+    let fb_size = framebuffer_tag.height() as usize * framebuffer_tag.pitch() as usize;
+    if let Err(err) = map_write_combining(fb_addr, fb_size) {
+        log::warn!(
+            "Framebuffer stays uncached, write combining failed : {:?}",
+            err
+        );
+    }
 
     BUFFER.call_once(|| Mutex::new(Buffer::new(framebuffer_tag)));
-    TEXT_BUFFER.call_once(|| Mutex::new(TextBuffer::new(1)));
+    let mut scale_factor = FB_SCALE.get().max(1);
+    let font = FONT.call_once(|| load_font(scale_factor));
+    if !TextBuffer::fits(font, scale_factor) {
+        log::warn!(
+            "Font scale {} doesn't fit on the screen, using 1",
+            scale_factor
+        );
+        scale_factor = 1;
+    }
+    TEXT_BUFFER.call_once(|| Mutex::new(TextBuffer::new(font, scale_factor)));
+    CONSOLE.lock().set_scrollback(FB_SCROLLBACK.get());
     WRITER.call_once(|| Mutex::new(Writer::default()));
 }
 
+/// Loads the font given as a Multiboot2 module named `font` (`module2 /boot/grub/font.psf font`),
+/// or the builtin one if there is none, it's invalid or it doesn't fit on the screen at `scale_factor`
+fn load_font(scale_factor: usize) -> Font {
+    if let Some(module) = find_module("font") {
+        let font = Font::parse(module).and_then(|font| {
+            if TextBuffer::fits(&font, scale_factor) {
+                Ok(font)
+            } else {
+                Err(FontError::TooBig)
+            }
+        });
+        match font {
+            Ok(font) => return font,
+            Err(err) => log::warn!("Invalid font module, using the builtin font : {}", err),
+        }
+    }
+    Font::parse(BUILTIN_FONT).expect("Builtin font is valid")
+}
+
+#[derive(Debug)]
+pub enum WriteCombiningError {
+    Pat(MsrError),
+    Paging(PagingError),
+    Mtrr(MsrError),
+}
+
+/// Maps the range WC through the PAT, falling back on variable MTRRs if the CPU has no PAT
+fn map_write_combining(addr: usize, size: usize) -> Result<(), WriteCombiningError> {
+    if cpu_info().has(Feature::PAT) {
+        x86::init_pat().map_err(WriteCombiningError::Pat)?;
+        paging::set_memory_type(addr, size, MemoryType::WriteCombining)
+            .map_err(WriteCombiningError::Paging)
+    } else {
+        let size = size.next_multiple_of(PAGE_SIZE);
+        x86::set_mtrr_wc(addr, size).map_err(WriteCombiningError::Mtrr)
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    let output = CONSOLE_OUTPUT.get();
+    if output.framebuffer() {
+        print_framebuffer(args);
+    }
+    if output.serial() {
+        crate::io::_serial_print(args);
+    }
+}
+
+/// Prints on the framebuffer console whatever the `console` parameter says
+pub fn print_framebuffer(args: fmt::Arguments) {
     // Deactivating interrupts to avoid deadlocks
     without_interrupts(|| {
         WRITER
@@ -106,6 +228,42 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// Switches the console to another font, everything is redrawn from the cell model. Refused if not a single
+/// cell of it fits on the screen.
+pub fn set_font(font: &'static Font, scale_factor: usize) -> Result<(), FontError> {
+    without_interrupts(|| {
+        WRITER
+            .get()
+            .expect("Writer required")
+            .lock()
+            .set_font(font, scale_factor)
+    })
+}
+
+/// Toggles the cursor, called from the timer interrupt so it gives up if the console is busy
+pub fn blink_cursor() {
+    let Some(text_buffer) = TEXT_BUFFER.get() else {
+        return;
+    };
+    if let (Some(mut text_buffer), Some(mut console)) = (text_buffer.try_lock(), CONSOLE.try_lock())
+    {
+        console.blink_cursor(&mut text_buffer);
+    }
+}
+
+/// Scrolls the view through the scrollback by half screens (positive is back in history),
+/// called from the keyboard interrupt so it gives up if the console is busy
+pub fn scroll_view(half_screens: isize) {
+    let Some(text_buffer) = TEXT_BUFFER.get() else {
+        return;
+    };
+    if let (Some(mut text_buffer), Some(mut console)) = (text_buffer.try_lock(), CONSOLE.try_lock())
+    {
+        let half_screen = (text_buffer.size().0 / 2).max(1) as isize;
+        console.scroll_view(&mut text_buffer, half_screens * half_screen);
+    }
+}
+
 ///////////////
 
 pub fn usize_plus_isize(u: usize, i: isize) -> usize {
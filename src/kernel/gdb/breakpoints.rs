@@ -0,0 +1,169 @@
+// Software breakpoints replace a byte of code with `int3`, hardware ones use the debug registers
+
+use spin::Mutex;
+
+use super::{GdbError, read_memory, write_memory};
+use crate::x86::{write_dr, write_dr7};
+
+const INT3: u8 = 0xCC;
+
+// TODO : Unlimited software breakpoints once there is an allocator
+const MAX_SOFTWARE_BREAKPOINTS: usize = 64;
+const DEBUG_REGISTERS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct SoftwareBreakpoint {
+    addr: usize,
+    original: u8,
+}
+
+static SOFTWARE_BREAKPOINTS: Mutex<[Option<SoftwareBreakpoint>; MAX_SOFTWARE_BREAKPOINTS]> =
+    Mutex::new([None; MAX_SOFTWARE_BREAKPOINTS]);
+
+pub fn insert_software(addr: usize) -> Result<(), GdbError> {
+    let mut breakpoints = SOFTWARE_BREAKPOINTS.lock();
+    if breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+        return Ok(());
+    }
+    let slot = breakpoints
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(GdbError::NoFreeSlot)?;
+
+    let mut original = [0];
+    read_memory(addr, &mut original)?;
+    write_memory(addr, &[INT3])?;
+    *slot = Some(SoftwareBreakpoint {
+        addr,
+        original: original[0],
+    });
+    Ok(())
+}
+
+pub fn remove_software(addr: usize) -> Result<(), GdbError> {
+    let mut breakpoints = SOFTWARE_BREAKPOINTS.lock();
+    let slot = breakpoints
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|bp| bp.addr == addr))
+        .ok_or(GdbError::InvalidArgument)?;
+    if let Some(bp) = slot.take() {
+        write_memory(bp.addr, &[bp.original])?;
+    }
+    Ok(())
+}
+
+/// Whether the `int3` at `addr` was put there by GDB
+pub fn is_software(addr: usize) -> bool {
+    SOFTWARE_BREAKPOINTS
+        .lock()
+        .iter()
+        .flatten()
+        .any(|bp| bp.addr == addr)
+}
+
+////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Execute,
+    Write,
+    /// Reads or writes, x86 can't watch only reads
+    Access,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct HardwareBreakpoint {
+    addr: usize,
+    kind: WatchKind,
+    len: usize,
+}
+
+impl HardwareBreakpoint {
+    // R/W and LEN fields of DR7
+    fn control(&self) -> usize {
+        let rw = match self.kind {
+            WatchKind::Execute => 0b00,
+            WatchKind::Write => 0b01,
+            WatchKind::Access => 0b11,
+        };
+        let len = match self.len {
+            2 => 0b01,
+            4 => 0b11,
+            8 => 0b10,
+            _ => 0b00,
+        };
+        rw | (len << 2)
+    }
+}
+
+static HARDWARE_BREAKPOINTS: Mutex<[Option<HardwareBreakpoint>; DEBUG_REGISTERS]> =
+    Mutex::new([None; DEBUG_REGISTERS]);
+
+fn program_debug_registers(breakpoints: &[Option<HardwareBreakpoint>]) {
+    let mut dr7 = 0;
+    for (i, bp) in breakpoints.iter().enumerate() {
+        if let Some(bp) = bp {
+            unsafe { write_dr(i, bp.addr) };
+            // Local enable, the fields of DR0 start at bit 16
+            dr7 |= (1 << (i * 2)) | (bp.control() << (16 + i * 4));
+        }
+    }
+    unsafe { write_dr7(dr7) };
+}
+
+pub fn insert_hardware(addr: usize, kind: WatchKind, len: usize) -> Result<(), GdbError> {
+    let valid_len = match kind {
+        WatchKind::Execute => len == 1,
+        WatchKind::Write | WatchKind::Access => matches!(len, 1 | 2 | 4 | 8),
+    };
+    // The CPU ignores the low bits of the address
+    if !valid_len || !addr.is_multiple_of(len) {
+        return Err(GdbError::InvalidArgument);
+    }
+
+    let breakpoint = HardwareBreakpoint { addr, kind, len };
+    let mut breakpoints = HARDWARE_BREAKPOINTS.lock();
+    if breakpoints.contains(&Some(breakpoint)) {
+        return Ok(());
+    }
+    let slot = breakpoints
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(GdbError::NoFreeSlot)?;
+    *slot = Some(breakpoint);
+    program_debug_registers(&breakpoints[..]);
+    Ok(())
+}
+
+pub fn remove_hardware(addr: usize, kind: WatchKind, len: usize) -> Result<(), GdbError> {
+    let breakpoint = HardwareBreakpoint { addr, kind, len };
+    let mut breakpoints = HARDWARE_BREAKPOINTS.lock();
+    let slot = breakpoints
+        .iter_mut()
+        .find(|slot| **slot == Some(breakpoint))
+        .ok_or(GdbError::InvalidArgument)?;
+    *slot = None;
+    program_debug_registers(&breakpoints[..]);
+    Ok(())
+}
+
+/// Hardware breakpoint that caused a #DB, from the B0-B3 bits of DR6
+pub fn triggered(dr6: usize) -> Option<(usize, WatchKind)> {
+    let breakpoints = HARDWARE_BREAKPOINTS.lock();
+    (0..DEBUG_REGISTERS)
+        .filter(|i| dr6 & (1 << i) != 0)
+        .find_map(|i| breakpoints[i])
+        .map(|bp| (bp.addr, bp.kind))
+}
+
+/// Puts the code back as it was and clears the debug registers, for when GDB goes away
+pub fn remove_all() {
+    for bp in SOFTWARE_BREAKPOINTS.lock().iter_mut() {
+        if let Some(bp) = bp.take() {
+            let _ = write_memory(bp.addr, &[bp.original]);
+        }
+    }
+    let mut breakpoints = HARDWARE_BREAKPOINTS.lock();
+    *breakpoints = [None; DEBUG_REGISTERS];
+    program_debug_registers(&breakpoints[..]);
+}
@@ -0,0 +1,488 @@
+// GDB remote stub on a serial port of its own, for debugging on real hardware.
+// Boot with `gdb=2` to put it on COM2 (`gdb.wait` to stop before anything runs), then from the host :
+// `gdb target/ab-os-bel -ex "set architecture i386:x86-64" -ex "target remote /dev/ttyUSB0"`
+//
+// The kernel stops on the #BP and #DB traps : software breakpoints, single steps and the hardware
+// breakpoints of the debug registers. Everything stays stopped, interrupts included, until GDB resumes it.
+
+mod breakpoints;
+mod packet;
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::{Mutex, Once};
+
+use breakpoints::WatchKind;
+use packet::{Connection, PACKET_SIZE, Reply, parse_hex, parse_hex_bytes};
+
+use crate::{
+    interrupts::{BREAKPOINT_VECTOR, TrapFrame},
+    io::serial::{self, SerialPort},
+    paging::{self, PAGE_SIZE, PageTableFlags},
+    shell::{CommandError, FnCommand},
+    shell_command,
+    x86::{
+        self, CR0_WRITE_PROTECT, DR6_SINGLE_STEP, RFLAGS_RF, RFLAGS_TF, read_cr0, read_dr6,
+        write_cr0, write_dr6,
+    },
+};
+
+crate::kernel_param!(GDB_PORT: usize = 0, "gdb", "COM port of the GDB stub (1-4), 0 to disable it");
+crate::kernel_param!(GDB_WAIT: bool = false, "gdb.wait", "Waits for GDB to attach at boot");
+
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug, Clone, Copy)]
+pub enum GdbError {
+    Unmapped,
+    NoFreeSlot,
+    InvalidArgument,
+}
+
+impl GdbError {
+    // Sent as `Exx`, GDB only shows the number
+    fn code(self) -> u8 {
+        match self {
+            GdbError::Unmapped => 14,        // EFAULT
+            GdbError::NoFreeSlot => 28,      // ENOSPC
+            GdbError::InvalidArgument => 22, // EINVAL
+        }
+    }
+}
+
+impl fmt::Display for GdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GdbError::Unmapped => write!(f, "memory isn't mapped"),
+            GdbError::NoFreeSlot => write!(f, "no breakpoint slot left"),
+            GdbError::InvalidArgument => write!(f, "invalid argument"),
+        }
+    }
+}
+
+static PORT: Once<&'static Mutex<SerialPort>> = Once::new();
+// Stop replies are only sent to a GDB that is listening, otherwise it would wait for an ack forever
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// Takes the serial port given by the `gdb` parameter, the serial ports must be initialized
+pub fn init() {
+    let com = GDB_PORT.get();
+    if com == 0 {
+        return;
+    }
+    match serial::reserve(com) {
+        Ok(port) => {
+            PORT.call_once(|| port);
+            log::info!("GDB stub listening on COM{}", com);
+        }
+        Err(err) => {
+            log::warn!("No GDB stub on COM{} : {:?}", com, err);
+            return;
+        }
+    }
+
+    if GDB_WAIT.get() {
+        log::info!("Waiting for GDB to attach");
+        x86::breakpoint();
+    }
+}
+
+pub fn is_enabled() -> bool {
+    PORT.get().is_some()
+}
+
+#[derive(Clone, Copy)]
+enum Stop {
+    Breakpoint,
+    SoftwareBreakpoint,
+    HardwareBreakpoint,
+    Watchpoint(usize, WatchKind),
+    Step,
+}
+
+impl Stop {
+    // Figures out why we stopped, cleaning up after the CPU on the way
+    fn from_trap(frame: &mut TrapFrame) -> Stop {
+        if frame.vector == BREAKPOINT_VECTOR as u64 {
+            // RIP is after the `int3`, GDB wants the address of its breakpoints
+            let addr = frame.rip as usize - 1;
+            if breakpoints::is_software(addr) {
+                frame.rip -= 1;
+                return Stop::SoftwareBreakpoint;
+            }
+            return Stop::Breakpoint;
+        }
+
+        let dr6 = read_dr6();
+        unsafe { write_dr6(0) };
+        frame.rflags &= !RFLAGS_TF;
+        match breakpoints::triggered(dr6) {
+            Some((_, WatchKind::Execute)) => Stop::HardwareBreakpoint,
+            Some((addr, kind)) => Stop::Watchpoint(addr, kind),
+            None if dr6 & DR6_SINGLE_STEP != 0 => Stop::Step,
+            None => Stop::Breakpoint,
+        }
+    }
+
+    fn write_reply(self, reply: &mut Reply) {
+        use fmt::Write;
+
+        let _ = write!(reply, "T{:02x}", SIGTRAP);
+        let _ = match self {
+            Stop::SoftwareBreakpoint => write!(reply, "swbreak:;"),
+            Stop::HardwareBreakpoint => write!(reply, "hwbreak:;"),
+            Stop::Watchpoint(addr, WatchKind::Write) => write!(reply, "watch:{:x};", addr),
+            Stop::Watchpoint(addr, _) => write!(reply, "awatch:{:x};", addr),
+            Stop::Breakpoint | Stop::Step => Ok(()),
+        };
+    }
+}
+
+/// Called by the #DB and #BP traps, returns false when there is no stub to hand them to
+pub fn handle_trap(frame: &mut TrapFrame) -> bool {
+    let Some(port) = PORT.get() else {
+        return false;
+    };
+    let stop = Stop::from_trap(frame);
+
+    // Interrupts are off in the trap, the port stays ours until GDB resumes the kernel
+    let mut connection = Connection::new(port.lock());
+    if ATTACHED.load(Ordering::Relaxed) {
+        let mut reply = Reply::new();
+        stop.write_reply(&mut reply);
+        connection.send(reply.as_bytes());
+    }
+
+    let mut buffer = [0; PACKET_SIZE];
+    loop {
+        let packet = connection.receive(&mut buffer);
+        let mut reply = Reply::new();
+        match handle_packet(packet, frame, stop, &mut reply) {
+            Ok(Action::Reply) => connection.send(reply.as_bytes()),
+            Ok(Action::Resume) => return true,
+            Ok(Action::ReplyAndResume) => {
+                connection.send(reply.as_bytes());
+                return true;
+            }
+            Err(err) => {
+                let mut reply = Reply::new();
+                reply.push(b'E');
+                reply.push_hex_byte(err.code());
+                connection.send(reply.as_bytes());
+            }
+        }
+    }
+}
+
+////////////////////////////////
+
+enum Action {
+    Reply,
+    Resume,
+    ReplyAndResume,
+}
+
+fn handle_packet(
+    packet: &[u8],
+    frame: &mut TrapFrame,
+    stop: Stop,
+    reply: &mut Reply,
+) -> Result<Action, GdbError> {
+    let Some((&command, args)) = packet.split_first() else {
+        return Ok(Action::Reply);
+    };
+    match command {
+        b'?' => {
+            ATTACHED.store(true, Ordering::Relaxed);
+            stop.write_reply(reply);
+        }
+        b'q' => query(args, reply),
+        // Only one thread, whichever is selected
+        b'H' | b'T' => reply.push_str("OK"),
+
+        b'g' => {
+            for register in 0..REGISTER_COUNT {
+                let (value, size) = read_register(frame, register);
+                reply.push_hex_le(value, size);
+            }
+        }
+        b'G' => {
+            let mut rest = args;
+            for register in 0..REGISTER_COUNT {
+                let size = register_size(register);
+                if rest.len() < size * 2 {
+                    break;
+                }
+                let (hex, next) = rest.split_at(size * 2);
+                write_register(frame, register, parse_le(hex)?);
+                rest = next;
+            }
+            reply.push_str("OK");
+        }
+        b'p' => {
+            let register = parse_hex(args).ok_or(GdbError::InvalidArgument)? as usize;
+            if register >= REGISTER_COUNT {
+                return Err(GdbError::InvalidArgument);
+            }
+            let (value, size) = read_register(frame, register);
+            reply.push_hex_le(value, size);
+        }
+        b'P' => {
+            let (register, value) = split(args, b'=')?;
+            let register = parse_hex(register).ok_or(GdbError::InvalidArgument)? as usize;
+            if register >= REGISTER_COUNT {
+                return Err(GdbError::InvalidArgument);
+            }
+            write_register(frame, register, parse_le(value)?);
+            reply.push_str("OK");
+        }
+
+        b'm' => {
+            let (addr, len) = parse_range(args)?;
+            let mut bytes = [0; PACKET_SIZE / 2];
+            let bytes = bytes.get_mut(..len).ok_or(GdbError::InvalidArgument)?;
+            read_memory(addr, bytes)?;
+            bytes.iter().for_each(|&byte| reply.push_hex_byte(byte));
+        }
+        b'M' => {
+            let (range, data) = split(args, b':')?;
+            let (addr, len) = parse_range(range)?;
+            let mut bytes = [0; PACKET_SIZE / 2];
+            if parse_hex_bytes(data, &mut bytes) != Some(len) {
+                return Err(GdbError::InvalidArgument);
+            }
+            write_memory(addr, &bytes[..len])?;
+            reply.push_str("OK");
+        }
+
+        b'c' | b's' => {
+            if !args.is_empty() {
+                frame.rip = parse_hex(args).ok_or(GdbError::InvalidArgument)?;
+            }
+            // Steps over a hardware breakpoint at RIP instead of hitting it again
+            frame.rflags |= RFLAGS_RF;
+            if command == b's' {
+                frame.rflags |= RFLAGS_TF;
+            }
+            return Ok(Action::Resume);
+        }
+
+        b'Z' | b'z' => {
+            let mut fields = args.split(|&byte| byte == b',');
+            let mut next = || {
+                fields
+                    .next()
+                    .and_then(parse_hex)
+                    .ok_or(GdbError::InvalidArgument)
+            };
+            let (kind, addr, len) = (next()?, next()? as usize, next()? as usize);
+            let insert = command == b'Z';
+            let watch_kind = match kind {
+                0 if insert => return breakpoints::insert_software(addr).map(|_| ok(reply)),
+                0 => return breakpoints::remove_software(addr).map(|_| ok(reply)),
+                1 => WatchKind::Execute,
+                2 => WatchKind::Write,
+                3 | 4 => WatchKind::Access,
+                // Unsupported kinds get an empty reply
+                _ => return Ok(Action::Reply),
+            };
+            if insert {
+                breakpoints::insert_hardware(addr, watch_kind, len)?;
+            } else {
+                breakpoints::remove_hardware(addr, watch_kind, len)?;
+            }
+            reply.push_str("OK");
+        }
+
+        // Detach, or kill which can't mean much to a kernel
+        b'D' | b'k' => {
+            breakpoints::remove_all();
+            ATTACHED.store(false, Ordering::Relaxed);
+            frame.rflags &= !RFLAGS_TF;
+            if command == b'k' {
+                return Ok(Action::Resume);
+            }
+            reply.push_str("OK");
+            return Ok(Action::ReplyAndResume);
+        }
+
+        // Anything else is unsupported, which is an empty reply
+        _ => {}
+    }
+    Ok(Action::Reply)
+}
+
+fn ok(reply: &mut Reply) -> Action {
+    reply.push_str("OK");
+    Action::Reply
+}
+
+fn query(args: &[u8], reply: &mut Reply) {
+    use fmt::Write;
+
+    let name = args.split(|&byte| byte == b':').next().unwrap_or(args);
+    match name {
+        b"Supported" => {
+            ATTACHED.store(true, Ordering::Relaxed);
+            let _ = write!(reply, "PacketSize={:x};swbreak+;hwbreak+", PACKET_SIZE);
+        }
+        b"Attached" => reply.push_str("1"),
+        b"C" => reply.push_str("QC1"),
+        b"fThreadInfo" => reply.push_str("m1"),
+        b"sThreadInfo" => reply.push_str("l"),
+        _ => {}
+    }
+}
+
+fn split(args: &[u8], separator: u8) -> Result<(&[u8], &[u8]), GdbError> {
+    let i = args
+        .iter()
+        .position(|&byte| byte == separator)
+        .ok_or(GdbError::InvalidArgument)?;
+    Ok((&args[..i], &args[i + 1..]))
+}
+
+// `addr,len`
+fn parse_range(args: &[u8]) -> Result<(usize, usize), GdbError> {
+    let (addr, len) = split(args, b',')?;
+    let addr = parse_hex(addr).ok_or(GdbError::InvalidArgument)? as usize;
+    let len = parse_hex(len).ok_or(GdbError::InvalidArgument)? as usize;
+    Ok((addr, len))
+}
+
+// Little endian hex value of a register
+fn parse_le(hex: &[u8]) -> Result<u64, GdbError> {
+    let mut bytes = [0; 8];
+    parse_hex_bytes(hex, &mut bytes).ok_or(GdbError::InvalidArgument)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// REGISTERS
+
+// rax rbx rcx rdx rsi rdi rbp rsp r8-r15 rip eflags cs ss ds es fs gs, the order GDB uses for amd64.
+// The registers after them (x87, SSE, ...) are left out, GDB shows them as unavailable
+const REGISTER_COUNT: usize = 24;
+const RIP: usize = 16;
+
+fn register_size(register: usize) -> usize {
+    if register <= RIP { 8 } else { 4 }
+}
+
+fn register_mut(frame: &mut TrapFrame, register: usize) -> Option<&mut u64> {
+    Some(match register {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        RIP => &mut frame.rip,
+        17 => &mut frame.rflags,
+        18 => &mut frame.cs,
+        19 => &mut frame.ss,
+        // The data segments aren't saved by the trap
+        _ => return None,
+    })
+}
+
+fn data_segment(register: usize) -> u64 {
+    let selector = match register {
+        20 => x86::read_ds(),
+        21 => x86::read_es(),
+        22 => x86::read_fs(),
+        _ => x86::read_gs(),
+    };
+    selector as u64
+}
+
+fn read_register(frame: &mut TrapFrame, register: usize) -> (u64, usize) {
+    let value = match register_mut(frame, register) {
+        Some(value) => *value,
+        None => data_segment(register),
+    };
+    (value, register_size(register))
+}
+
+// Writes to the data segments are ignored, the kernel doesn't use them
+fn write_register(frame: &mut TrapFrame, register: usize, value: u64) {
+    if let Some(slot) = register_mut(frame, register) {
+        *slot = value;
+    }
+}
+
+// MEMORY
+
+fn is_mapped(addr: usize, len: usize) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    (addr & !(PAGE_SIZE - 1)..end)
+        .step_by(PAGE_SIZE)
+        .all(|page| paging::leaf_entry(page).is_some())
+}
+
+fn read_memory(addr: usize, bytes: &mut [u8]) -> Result<(), GdbError> {
+    if !is_mapped(addr, bytes.len()) {
+        return Err(GdbError::Unmapped);
+    }
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+    }
+    Ok(())
+}
+
+/// Writes even to read only pages, breakpoints go in the code
+fn write_memory(addr: usize, bytes: &[u8]) -> Result<(), GdbError> {
+    if !is_mapped(addr, bytes.len()) {
+        return Err(GdbError::Unmapped);
+    }
+    let read_only = (addr & !(PAGE_SIZE - 1)..addr + bytes.len())
+        .step_by(PAGE_SIZE)
+        .filter_map(paging::leaf_entry)
+        .any(|(entry, _)| !entry.flags().contains(PageTableFlags::WRITABLE));
+
+    let cr0 = read_cr0();
+    if read_only {
+        unsafe { write_cr0(cr0 & !CR0_WRITE_PROTECT) };
+    }
+    for (i, &byte) in bytes.iter().enumerate() {
+        unsafe { core::ptr::write_volatile((addr + i) as *mut u8, byte) };
+    }
+    if read_only {
+        unsafe { write_cr0(cr0) };
+    }
+    Ok(())
+}
+
+// SHELL
+
+fn gdb(_args: &[&str], out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    if !is_enabled() {
+        return Err(CommandError::Failed(
+            "no GDB stub, boot with gdb=<COM port>",
+        ));
+    }
+    let _ = writeln!(out, "Stopping in the debugger");
+    x86::breakpoint();
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "gdb",
+    usage: "",
+    help: "Breaks into the GDB stub",
+    run: gdb,
+});
@@ -0,0 +1,174 @@
+// Framing of the remote serial protocol : `$<data>#<checksum>`, acknowledged with `+` or `-`
+
+use core::fmt;
+
+use spin::MutexGuard;
+
+use crate::io::serial::SerialPort;
+
+// TODO : Bigger packets once there is an allocator
+pub const PACKET_SIZE: usize = 2048;
+
+const ESCAPE: u8 = b'}';
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|digit| digit as u8)
+}
+
+pub fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter().try_fold(0u64, |value, &digit| {
+        Some((value << 4) | hex_digit(digit)? as u64)
+    })
+}
+
+/// Decodes pairs of hex digits into `out`, returning how many bytes were written
+pub fn parse_hex_bytes(hex: &[u8], out: &mut [u8]) -> Option<usize> {
+    if !hex.len().is_multiple_of(2) || hex.len() / 2 > out.len() {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+    Some(hex.len() / 2)
+}
+
+/// Reply being built, longer replies are truncated
+pub struct Reply {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    pub fn new() -> Self {
+        Reply {
+            data: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|byte| self.push(byte));
+    }
+
+    pub fn push_hex_byte(&mut self, byte: u8) {
+        self.push(HEX_DIGITS[(byte >> 4) as usize]);
+        self.push(HEX_DIGITS[(byte & 0xF) as usize]);
+    }
+
+    /// Registers are sent in target order, little endian
+    pub fn push_hex_le(&mut self, value: u64, size: usize) {
+        value.to_le_bytes()[..size]
+            .iter()
+            .for_each(|&byte| self.push_hex_byte(byte));
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+/// The serial port of the stub, held for as long as the kernel is stopped
+pub struct Connection<'a> {
+    port: MutexGuard<'a, SerialPort>,
+}
+
+impl<'a> Connection<'a> {
+    pub fn new(port: MutexGuard<'a, SerialPort>) -> Self {
+        Connection { port }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.port.read_byte() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read_hex_byte(&mut self) -> Option<u8> {
+        let high = hex_digit(self.read_byte())?;
+        let low = hex_digit(self.read_byte())?;
+        Some((high << 4) | low)
+    }
+
+    /// Waits for the next valid packet and acknowledges it, returning its unescaped data
+    pub fn receive<'b>(&mut self, buffer: &'b mut [u8; PACKET_SIZE]) -> &'b [u8] {
+        loop {
+            // Acks and interrupt requests (^C) are meaningless while stopped
+            while self.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut sum = 0u8;
+            let mut escaped = false;
+            loop {
+                let byte = self.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                if byte == ESCAPE && !escaped {
+                    escaped = true;
+                    continue;
+                }
+                if len < PACKET_SIZE {
+                    buffer[len] = if escaped { byte ^ 0x20 } else { byte };
+                    len += 1;
+                }
+                escaped = false;
+            }
+
+            if self.read_hex_byte() == Some(sum) && len < PACKET_SIZE {
+                self.port.write_byte(b'+');
+                return &buffer[..len];
+            }
+            self.port.write_byte(b'-');
+        }
+    }
+
+    /// Sends a packet until GDB acknowledges it
+    pub fn send(&mut self, data: &[u8]) {
+        loop {
+            self.port.write_byte(b'$');
+            // The checksum covers the escaped data
+            let mut sum = 0u8;
+            for &byte in data {
+                let escaped = matches!(byte, b'$' | b'#' | ESCAPE | b'*');
+                let sent: &[u8] = if escaped {
+                    &[ESCAPE, byte ^ 0x20]
+                } else {
+                    &[byte]
+                };
+                for &byte in sent {
+                    sum = sum.wrapping_add(byte);
+                    self.port.write_byte(byte);
+                }
+            }
+            self.port.write_byte(b'#');
+            self.port.write_byte(HEX_DIGITS[(sum >> 4) as usize]);
+            self.port.write_byte(HEX_DIGITS[(sum & 0xF) as usize]);
+
+            if self.read_byte() != b'-' {
+                return;
+            }
+        }
+    }
+}
@@ -1,61 +1,81 @@
 use core::arch::asm;
 
-use lazy_static::lazy_static;
-
-//// Initialization
-
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::empty();
-        tss.iopb_offset = core::mem::size_of::<TaskStateSegment>() as u16; // essentially disabling IOPB
-        tss
-    };
-
-    static ref sGDT: Gdt = {
-        let mut gdt = GdtArr::new();
-        gdt.tss_descriptor.change_base(&TSS as *const _ as u64);
-        Gdt::new(gdt)
-    };
-}
+use crate::{
+    smp::{self, MAX_CPUS},
+    stacks,
+};
 
-pub fn init() {
-    sGDT.load();
-}
+///// Initialization
 
-/*
-static mut TSS: TaskStateSegment = TaskStateSegment::empty();
-
-unsafe fn reload_cs() {
-    unsafe{asm!(
-        "mov ax, 0x10",
-        "mov ds, ax",
-        "mov es, ax",
-        "mov fs, ax",
-        "mov gs, ax",
-        "mov ss, ax",
-    )}
-}
+// Replaces the GDT of boot.s, which only has a code segment for the jump to long mode.
+// The order of the user segments is the one SYSRET expects : it loads SS from STAR + 8 and CS from STAR + 16.
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+
+// One of each per CPU, each one has its own IST stacks and kernel stack
+static mut TSS: [TaskStateSegment; MAX_CPUS] = [const { TaskStateSegment::empty() }; MAX_CPUS];
+
+// `ltr` marks the TSS descriptor busy, so the tables can't be read-only nor shared
+static mut GDT: [GdtArr; MAX_CPUS] = [const { GdtArr::new() }; MAX_CPUS];
 
 pub fn init() {
-    crate::x86::without_interrupts(|| unsafe {
-        // compile_error!("TODO : Implement GDT init");
+    init_cpu(0);
+}
 
-        let tss_ptr = &raw const TSS as u64;
-        #[allow(static_mut_refs)] // TODO : do that better
-        GDT.change_tss_base(tss_ptr);
+/// Loads the GDT and the TSS of a CPU, on that CPU
+pub fn init_cpu(cpu: usize) {
+    assert!(cpu < MAX_CPUS, "No GDT for CPU {}", cpu);
+    crate::x86::without_interrupts(|| unsafe {
+        let tss = &raw mut TSS[cpu];
+        (*tss).iopb_offset = core::mem::size_of::<TaskStateSegment>() as u16; // essentially disabling IOPB
+        (*tss).ist1 = stacks::ist_stack_top(cpu, stacks::DOUBLE_FAULT_IST);
+        (*tss).ist2 = stacks::ist_stack_top(cpu, stacks::NMI_IST);
+        (*tss).ist3 = stacks::ist_stack_top(cpu, stacks::MACHINE_CHECK_IST);
+
+        let gdt = &raw mut GDT[cpu];
+        (*gdt).tss_descriptor.change_base(tss as u64);
+        Gdt::new(&*gdt).load();
+        reload_segments();
+        // The IST stacks are only used once the TSS is loaded
+        asm!("ltr {:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    })
+}
 
-        // Load the GDT
+// CS can't be moved into, it's changed by returning to the same code through a far return
+unsafe fn reload_segments() {
+    unsafe {
         asm!(
-            "push 0x08", // TODO : tf is that
-            "lea rax, [rip + {rcs}]",
-            "push rax",
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
             "retfq",
-            rcs = sym reload_cs,
-        )
-    })
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov gs, {data:x}",
+            "mov ss, {data:x}",
+            code = in(reg) KERNEL_CODE_SELECTOR as u64,
+            data = in(reg) KERNEL_DATA_SELECTOR as u64,
+            tmp = out(reg) _,
+        );
+    }
 }
 
-*/
+/// Stack the calling CPU switches to when an interrupt or a system call comes from ring 3
+pub fn set_kernel_stack(rsp0: u64) {
+    let tss = unsafe { &raw mut TSS[smp::cpu_index()] };
+    unsafe { (*tss).set_rsp0(rsp0) };
+    smp::this_cpu().set_syscall_stack(rsp0 as usize);
+}
+
+pub fn kernel_stack() -> u64 {
+    let tss = unsafe { &raw const TSS[smp::cpu_index()] };
+    unsafe { (*tss).rsp0() }
+}
 
 // TODO : use bitflags everywhere
 
@@ -68,10 +88,10 @@ struct Gdt {
 }
 
 impl Gdt {
-    fn new(gdt: GdtArr) -> Self {
+    fn new(gdt: &GdtArr) -> Self {
         Gdt {
             limit: (core::mem::size_of::<GdtArr>() - 1) as u16,
-            base: &gdt as *const GdtArr as u64,
+            base: gdt as *const GdtArr as u64,
         }
     }
 
@@ -87,26 +107,41 @@ struct GdtArr {
     null_descriptor: GdtNormalDescriptor,
     kernel_code_descriptor: GdtNormalDescriptor,
     kernel_data_descriptor: GdtNormalDescriptor,
+    user_data_descriptor: GdtNormalDescriptor,
+    user_code_descriptor: GdtNormalDescriptor,
     tss_descriptor: GdtSystemDescriptor,
 }
 
 impl GdtArr {
-    fn new() -> Self {
+    const fn new() -> Self {
+        // Base and limit are ignored in long mode, except for the TSS
         let kernel_code_descriptor = GdtNormalDescriptor::new(
-            0x00400000,
-            0x003FFFFF,
+            0,
+            0xFFFFF,
             GdtNormalAccess::from_u8(0x9A),
-            GdtFlags::from_u8(0xC),
+            GdtFlags::from_u8(0xA), // Long mode code, interrupt gates refuse anything else
         );
         let kernel_data_descriptor = GdtNormalDescriptor::new(
-            0x00800000,
-            0x003FFFFF,
+            0,
+            0xFFFFF,
             GdtNormalAccess::from_u8(0x92),
             GdtFlags::from_u8(0xC),
         );
+        let user_data_descriptor = GdtNormalDescriptor::new(
+            0,
+            0xFFFFF,
+            GdtNormalAccess::from_u8(0xF2), // Same as the kernel ones with DPL 3
+            GdtFlags::from_u8(0xC),
+        );
+        let user_code_descriptor = GdtNormalDescriptor::new(
+            0,
+            0xFFFFF,
+            GdtNormalAccess::from_u8(0xFA),
+            GdtFlags::from_u8(0xA),
+        );
         let tss_descriptor = GdtSystemDescriptor::new(
             0, // Base will be changed later
-            core::mem::size_of::<GdtSystemDescriptor>() as u32,
+            (core::mem::size_of::<TaskStateSegment>() - 1) as u32,
             GdtSystemAccess::from_u8(0x89),
             GdtFlags::from_u8(0x0),
         );
@@ -115,6 +150,8 @@ impl GdtArr {
             null_descriptor: GdtNormalDescriptor::null_descriptor(),
             kernel_code_descriptor,
             kernel_data_descriptor,
+            user_data_descriptor,
+            user_code_descriptor,
             tss_descriptor,
         }
     }
@@ -188,7 +225,7 @@ impl GdtSystemDescriptor {
             _reserved: 0,
             base_very_high: ((base >> 32) & 0xffffffff) as u32,
             base_high: ((base >> 24) & 0xff) as u8,
-            flags_limit_high: ((granularity.value() & 0xf) << 4) & (((limit >> 16) & 0xf) as u8),
+            flags_limit_high: ((granularity.value() & 0xf) << 4) | (((limit >> 16) & 0xf) as u8),
             access: access.value(),
             base_mid: ((base >> 16) & 0xff) as u8,
             base_low: (base & 0xffff) as u16,
@@ -350,4 +387,11 @@ impl TaskStateSegment {
             iopb_offset: 0,
         }
     }
+    fn rsp0(&self) -> u64 {
+        self.rsp0
+    }
+
+    fn set_rsp0(&mut self, rsp0: u64) {
+        self.rsp0 = rsp0;
+    }
 }
@@ -0,0 +1,294 @@
+// What gets printed when an exception kills the kernel : the exception, its decoded error code,
+// every register of the interrupted code and the control registers
+
+use core::fmt;
+
+use bitflags::bitflags;
+
+use super::{DOUBLE_FAULT_VECTOR, PAGE_FAULT_VECTOR, SIMD_FPU_VECTOR, TrapFrame, X87_FPU_VECTOR};
+use crate::{stacks, x86};
+
+/// Mnemonic and name of an exception
+pub fn exception_name(vector: u8) -> (&'static str, &'static str) {
+    match vector {
+        0x00 => ("#DE", "DIVISION ERROR"),
+        0x01 => ("#DB", "DEBUG EXCEPTION"),
+        0x02 => ("NMI", "NON-MASKABLE INTERRUPT"),
+        0x03 => ("#BP", "BREAKPOINT"),
+        0x04 => ("#OF", "OVERFLOW"),
+        0x05 => ("#BR", "BOUND RANGE EXCEEDED"),
+        0x06 => ("#UD", "INVALID OPCODE"),
+        0x07 => ("#NM", "DEVICE NOT AVAILABLE"),
+        0x08 => ("#DF", "DOUBLE FAULT"),
+        0x0A => ("#TS", "INVALID TSS"),
+        0x0B => ("#NP", "SEGMENT NOT PRESENT"),
+        0x0C => ("#SS", "STACK SEGMENT FAULT"),
+        0x0D => ("#GP", "GENERAL PROTECTION FAULT"),
+        0x0E => ("#PF", "PAGE FAULT"),
+        0x10 => ("#MF", "x87 FLOATING POINT EXCEPTION"),
+        0x11 => ("#AC", "ALIGNMENT CHECK"),
+        0x12 => ("#MC", "MACHINE CHECK"),
+        0x13 => ("#XM", "SIMD FLOATING POINT EXCEPTION"),
+        0x14 => ("#VE", "VIRTUALIZATION EXCEPTION"),
+        0x15 => ("#CP", "CONTROL PROTECTION EXCEPTION"),
+        0x1C => ("#HV", "HYPERVISOR INJECTION EXCEPTION"),
+        0x1D => ("#VC", "VMM COMMUNICATION EXCEPTION"),
+        0x1E => ("#SX", "SECURITY EXCEPTION"),
+        _ => ("", "UNKNOWN EXCEPTION"),
+    }
+}
+
+/// Whether the CPU pushes an error code for the exception
+pub fn has_error_code(vector: u8) -> bool {
+    matches!(vector, 0x08 | 0x0A..=0x0E | 0x11 | 0x15 | 0x1D | 0x1E)
+}
+
+// ERROR CODES
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageFaultError: u64 {
+        const PRESENT = 1 << 0; // Protection violation, otherwise the page isn't present
+        const WRITE = 1 << 1;
+        const USER = 1 << 2;
+        const RESERVED_BIT = 1 << 3; // A reserved bit is set in a paging entry
+        const INSTRUCTION_FETCH = 1 << 4;
+        const PROTECTION_KEY = 1 << 5;
+        const SHADOW_STACK = 1 << 6;
+        const SGX = 1 << 15;
+    }
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = if self.contains(PageFaultError::PRESENT) {
+            "protection violation"
+        } else {
+            "page not present"
+        };
+        let access = if self.contains(PageFaultError::INSTRUCTION_FETCH) {
+            "instruction fetch"
+        } else if self.contains(PageFaultError::WRITE) {
+            "write"
+        } else {
+            "read"
+        };
+        let mode = if self.contains(PageFaultError::USER) {
+            "user"
+        } else {
+            "supervisor"
+        };
+        write!(f, "{}, {}, {} mode", cause, access, mode)?;
+
+        let extras = [
+            (PageFaultError::RESERVED_BIT, "reserved bit set"),
+            (PageFaultError::PROTECTION_KEY, "protection key"),
+            (PageFaultError::SHADOW_STACK, "shadow stack"),
+            (PageFaultError::SGX, "SGX"),
+        ];
+        for (flag, name) in extras {
+            if self.contains(flag) {
+                write!(f, ", {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// Error code of #TS, #NP, #SS and #GP : the selector that caused the fault
+#[derive(Debug, Clone, Copy)]
+pub struct SelectorError(pub u64);
+
+impl SelectorError {
+    /// The exception happened while delivering an external event
+    pub fn external(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        (self.0 >> 3) & 0x1FFF
+    }
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            // #GP doesn't always come from a segment
+            return write!(f, "no selector");
+        }
+        let table = match self.table() {
+            DescriptorTable::Gdt => "GDT",
+            DescriptorTable::Idt => "IDT",
+            DescriptorTable::Ldt => "LDT",
+        };
+        write!(f, "{} entry {}", table, self.index())?;
+        if self.table() == DescriptorTable::Idt {
+            write!(f, " (vector {:#x})", self.index())?;
+        }
+        if self.external() {
+            write!(f, ", external event")?;
+        }
+        Ok(())
+    }
+}
+
+// Error code of #CP
+fn control_protection_cause(error_code: u64) -> &'static str {
+    match error_code & 0x7FFF {
+        1 => "near return",
+        2 => "far return or iret",
+        3 => "missing endbranch",
+        4 => "rstorssp",
+        5 => "setssbsy",
+        _ => "unknown cause",
+    }
+}
+
+// REPORT
+
+/// State of the CPU when an exception happened, taken right away so that the control registers
+/// don't change in between
+pub struct ExceptionReport<'a> {
+    frame: &'a TrapFrame,
+    cr0: usize,
+    cr2: usize,
+    cr3: usize,
+    cr4: usize,
+    ds: u16,
+    es: u16,
+    fs: u16,
+    gs: u16,
+    fpu_status: u16,
+    mxcsr: u32,
+}
+
+impl<'a> ExceptionReport<'a> {
+    pub fn new(frame: &'a TrapFrame) -> Self {
+        ExceptionReport {
+            frame,
+            cr0: x86::read_cr0(),
+            cr2: x86::read_cr2(),
+            cr3: x86::read_cr3(),
+            cr4: x86::read_cr4(),
+            ds: x86::read_ds(),
+            es: x86::read_es(),
+            fs: x86::read_fs(),
+            gs: x86::read_gs(),
+            fpu_status: x86::read_fpu_status(),
+            mxcsr: x86::read_mxcsr(),
+        }
+    }
+
+    fn fmt_error_code(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vector = self.frame.vector as u8;
+        let error_code = self.frame.error_code;
+        write!(f, "Error code {:#x}", error_code)?;
+        match vector {
+            0x0A..=0x0D => write!(f, " : {}", SelectorError(error_code))?,
+            0x0E => write!(
+                f,
+                " : {}\nFaulting address (CR2) {:#x}",
+                PageFaultError::from_bits_retain(error_code),
+                self.cr2
+            )?,
+            0x15 => write!(f, " : {}", control_protection_cause(error_code))?,
+            _ => {}
+        }
+        writeln!(f)
+    }
+}
+
+impl fmt::Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = self.frame;
+        let vector = frame.vector as u8;
+        let (mnemonic, name) = exception_name(vector);
+        writeln!(
+            f,
+            "{} ({}, vector {:#x}) at address {:#x}",
+            name, mnemonic, vector, frame.rip
+        )?;
+        if has_error_code(vector) {
+            self.fmt_error_code(f)?;
+        }
+        // Overflowing a stack page faults on its guard page, and pushing the page fault faults again
+        if matches!(vector, PAGE_FAULT_VECTOR | DOUBLE_FAULT_VECTOR)
+            && let Some(stack) = stacks::guarded_stack(self.cr2)
+        {
+            writeln!(
+                f,
+                "Stack overflow : {:#x} is the guard page of the {} stack",
+                self.cr2, stack
+            )?;
+        }
+        // #MF and #XM have no error code, the cause is in the status registers
+        match vector {
+            X87_FPU_VECTOR => writeln!(
+                f,
+                "FPU status word {:#06x} : {}",
+                self.fpu_status,
+                x86::FpuExceptions::from_bits_truncate(self.fpu_status as u32)
+            )?,
+            SIMD_FPU_VECTOR => writeln!(
+                f,
+                "MXCSR {:#010x} : {}",
+                self.mxcsr,
+                x86::FpuExceptions::from_bits_truncate(self.mxcsr)
+            )?,
+            _ => {}
+        }
+
+        let registers = [
+            ("RAX", frame.rax),
+            ("RBX", frame.rbx),
+            ("RCX", frame.rcx),
+            ("RDX", frame.rdx),
+            ("RSI", frame.rsi),
+            ("RDI", frame.rdi),
+            ("RBP", frame.rbp),
+            ("RSP", frame.rsp),
+            ("R8", frame.r8),
+            ("R9", frame.r9),
+            ("R10", frame.r10),
+            ("R11", frame.r11),
+            ("R12", frame.r12),
+            ("R13", frame.r13),
+            ("R14", frame.r14),
+            ("R15", frame.r15),
+            ("RIP", frame.rip),
+            ("RFLAGS", frame.rflags),
+        ];
+        for line in registers.chunks(3) {
+            for (name, value) in line {
+                write!(f, "{:<6} {:016x}  ", name, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(
+            f,
+            "CS {:04x}  SS {:04x}  DS {:04x}  ES {:04x}  FS {:04x}  GS {:04x}",
+            frame.cs, frame.ss, self.ds, self.es, self.fs, self.gs
+        )?;
+        write!(
+            f,
+            "CR0 {:016x}  CR2 {:016x}  CR3 {:016x}  CR4 {:016x}",
+            self.cr0, self.cr2, self.cr3, self.cr4
+        )
+    }
+}
@@ -0,0 +1,87 @@
+// Exception table : instructions that are allowed to fault. When one of them raises a #GP or a #PF, the
+// exception handler resumes at its fixup address instead of panicking. Entries are put next to the
+// instruction with `exception_table_entry!` and gathered by linker.ld.
+
+use core::arch::asm;
+
+use super::TrapFrame;
+
+#[repr(C)]
+struct ExceptionTableEntry {
+    instruction: usize,
+    fixup: usize,
+}
+
+/// Assembly adding an entry to the exception table, for `asm!` :
+/// `"2:", "rdmsr", "3:", exception_table_entry!("2b", "3b")`.
+/// The fixup usually leaves a register saying whether the instruction faulted, see `probe_read`.
+#[macro_export]
+macro_rules! exception_table_entry {
+    ($instruction:literal, $fixup:literal) => {
+        concat!(
+            ".pushsection .exception_table, \"a\"\n",
+            ".balign 8\n",
+            ".quad ",
+            $instruction,
+            ", ",
+            $fixup,
+            "\n",
+            ".popsection"
+        )
+    };
+}
+
+unsafe extern "C" {
+    // Defined by linker.ld, only their addresses matter
+    static __exception_table_start: u8;
+    static __exception_table_end: u8;
+}
+
+fn exception_table() -> &'static [ExceptionTableEntry] {
+    unsafe {
+        let start = (&raw const __exception_table_start).cast::<ExceptionTableEntry>();
+        let end = (&raw const __exception_table_end).cast::<ExceptionTableEntry>();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Where to resume when the instruction at `rip` faults, if it's allowed to
+pub fn search_exception_table(rip: usize) -> Option<usize> {
+    // The linker doesn't sort the entries, but there are few of them
+    exception_table()
+        .iter()
+        .find(|entry| entry.instruction == rip)
+        .map(|entry| entry.fixup)
+}
+
+/// Resumes at the fixup of the faulting instruction, false if it has none
+pub(super) fn fixup_exception(frame: &mut TrapFrame) -> bool {
+    match search_exception_table(frame.rip as usize) {
+        Some(fixup) => {
+            frame.rip = fixup as u64;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Reads a byte that may not be mapped, None if reading it faulted
+pub fn probe_read(addr: usize) -> Option<u8> {
+    let value: u8;
+    let faulted: u32;
+    // `faulted` is only cleared if the read didn't jump to the fixup
+    unsafe {
+        asm!(
+            "2:",
+            "mov {value}, byte ptr [{addr}]",
+            "xor {faulted:e}, {faulted:e}",
+            "3:",
+            exception_table_entry!("2b", "3b"),
+            addr = in(reg) addr,
+            value = out(reg_byte) value,
+            faulted = inout(reg) 1u32 => faulted,
+            options(nostack, readonly),
+        );
+    }
+    (faulted == 0).then_some(value)
+}
@@ -0,0 +1,227 @@
+// Every gate of the IDT points to the trap stub of its vector, `trap_dispatch` then calls the handler
+// registered for it. Registering only changes the options of the gate, never its address, so an interrupt
+// can't see a half written gate.
+
+use core::{
+    arch::asm,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+
+use super::{DOUBLE_FAULT_VECTOR, MACHINE_CHECK_VECTOR, NMI_VECTOR, TrapFrame, trap_stub};
+use crate::{gdt::KERNEL_CODE_SELECTOR, stacks};
+
+/// Called with the registers of the interrupted code, changes are applied when it resumes
+pub type Handler = fn(&mut TrapFrame);
+
+#[derive(Debug, Clone, Copy)]
+pub enum IdtError {
+    InUse(u8),
+    NotRegistered(u8),
+    InvalidIst(u8),
+    InvalidDpl(u8),
+}
+
+impl fmt::Display for IdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdtError::InUse(vector) => write!(f, "vector {:#x} already has a handler", vector),
+            IdtError::NotRegistered(vector) => write!(f, "vector {:#x} has no handler", vector),
+            IdtError::InvalidIst(ist) => write!(f, "invalid IST index {}", ist),
+            IdtError::InvalidDpl(dpl) => write!(f, "invalid DPL {}", dpl),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateType {
+    Interrupt = 0b1110, // Clears IF
+    Trap = 0b1111,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GateOptions {
+    ist: u8,
+    dpl: u8,
+    gate_type: GateType,
+}
+
+impl GateOptions {
+    pub const fn new() -> Self {
+        GateOptions {
+            ist: 0,
+            dpl: 0,
+            gate_type: GateType::Interrupt,
+        }
+    }
+
+    /// Options of the gate when nothing is registered
+    pub const fn default_for(vector: u8) -> Self {
+        // These can happen on a stack that can't be trusted, so they get their own
+        match vector {
+            NMI_VECTOR => Self::new().ist(stacks::NMI_IST),
+            DOUBLE_FAULT_VECTOR => Self::new().ist(stacks::DOUBLE_FAULT_IST),
+            MACHINE_CHECK_VECTOR => Self::new().ist(stacks::MACHINE_CHECK_IST),
+            _ => Self::new(),
+        }
+    }
+
+    /// Index of the stack to switch to in the IST of the TSS (1-7), 0 keeps the current stack
+    pub const fn ist(mut self, ist: u8) -> Self {
+        self.ist = ist;
+        self
+    }
+
+    /// Lowest privilege that can raise the vector with `int`
+    pub const fn dpl(mut self, dpl: u8) -> Self {
+        self.dpl = dpl;
+        self
+    }
+
+    pub const fn gate_type(mut self, gate_type: GateType) -> Self {
+        self.gate_type = gate_type;
+        self
+    }
+}
+
+impl Default for GateOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Read by `trap_dispatch` without taking the lock of the IDT, 0 when there is no handler
+static HANDLERS: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+
+pub(super) fn handler(vector: u8) -> Option<Handler> {
+    let handler = HANDLERS[vector as usize].load(Ordering::Acquire);
+    (handler != 0).then(|| unsafe { core::mem::transmute::<usize, Handler>(handler) })
+}
+
+/// The table must outlive `lidt`, only its address is loaded
+pub static IDT: Mutex<Idt> = Mutex::new(Idt::new());
+
+pub struct Idt {
+    entries: [IDTEntry; 256],
+}
+
+impl Idt {
+    const fn new() -> Self {
+        Idt {
+            entries: [IDTEntry::null(); 256],
+        }
+    }
+
+    fn set_gate(&mut self, vector: u8, options: GateOptions) {
+        self.entries[vector as usize] = IDTEntry::new(
+            trap_stub(vector),
+            KERNEL_CODE_SELECTOR,
+            options.ist,
+            options.gate_type,
+            options.dpl,
+        );
+    }
+
+    /// Points every gate to its stub, with the default options
+    pub(super) fn init(&mut self) {
+        for vector in 0..=255 {
+            self.set_gate(vector, GateOptions::default_for(vector));
+        }
+    }
+
+    pub fn load(&self) {
+        let idtr = Idtr {
+            limit: (core::mem::size_of_val(&self.entries) - 1) as u16,
+            base: self.entries.as_ptr() as u64,
+        };
+        unsafe {
+            asm!("lidt [{}]", in(reg) &idtr);
+        }
+    }
+
+    /// Calls `handler` for `vector` instead of the default handling (the debugger or a panic for exceptions,
+    /// nothing for interrupts)
+    pub fn register(
+        &mut self,
+        vector: u8,
+        handler: Handler,
+        options: GateOptions,
+    ) -> Result<(), IdtError> {
+        if options.ist > 7 {
+            return Err(IdtError::InvalidIst(options.ist));
+        }
+        if options.dpl > 3 {
+            return Err(IdtError::InvalidDpl(options.dpl));
+        }
+        HANDLERS[vector as usize]
+            .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| IdtError::InUse(vector))?;
+        self.set_gate(vector, options);
+        Ok(())
+    }
+
+    /// Removes the handler of `vector` and gives it back
+    pub fn unregister(&mut self, vector: u8) -> Result<Handler, IdtError> {
+        let handler = handler(vector).ok_or(IdtError::NotRegistered(vector))?;
+        self.set_gate(vector, GateOptions::default_for(vector));
+        HANDLERS[vector as usize].store(0, Ordering::Release);
+        Ok(handler)
+    }
+
+    pub fn is_registered(&self, vector: u8) -> bool {
+        handler(vector).is_some()
+    }
+}
+
+// This struct represents what will be loaded into the IDTR register with `lidt`
+#[repr(C, packed)]
+struct Idtr {
+    limit: u16, // The size of the IDT - 1
+    base: u64,  // The address of the IDT
+}
+
+// TODO : use bitflags
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct IDTEntry {
+    offset_low: u16,
+    selector: u16,
+    ist: u8, // ist 3bit + reserved
+    flags: u8,
+    offset_mid: u16,
+    offset_high: u32,
+    _reserved: u32,
+}
+
+impl IDTEntry {
+    // offset : address of the interrupt handler
+    // selector : code segment selector
+    // ist : interrupt stack table offset
+    // gate_type : type of the gate (interrupt or trap)
+    // dpl : descriptor privilege level
+    fn new(offset: u64, selector: u16, ist: u8, gate_type: GateType, dpl: u8) -> Self {
+        IDTEntry {
+            offset_low: offset as u16,
+            selector,
+            ist,
+            flags: (1 << 7) | (dpl << 5) | (gate_type as u8),
+            offset_mid: (offset >> 16) as u16,
+            offset_high: (offset >> 32) as u32,
+            _reserved: 0,
+        }
+    }
+
+    const fn null() -> Self {
+        IDTEntry {
+            offset_low: 0,
+            selector: 0,
+            ist: 0,
+            flags: 0,
+            offset_mid: 0,
+            offset_high: 0,
+            _reserved: 0,
+        }
+    }
+}
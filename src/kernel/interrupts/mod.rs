@@ -0,0 +1,78 @@
+mod exception;
+mod fixup;
+mod idt;
+mod trap;
+
+pub use exception::*;
+pub use fixup::*;
+pub use idt::*;
+pub use trap::*;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    framebuffer::{self, CURSOR_BLINK_TICKS},
+    io::{keyboard, pic, pit, serial},
+    thread,
+};
+
+// The fact that this work is truly an example of the might humanity is capable of.
+
+// HANDLERS
+
+// 0x20: Timer (IRQ0)
+fn timer_handler(_frame: &mut TrapFrame) {
+    if pit::tick().is_multiple_of(CURSOR_BLINK_TICKS) {
+        framebuffer::blink_cursor();
+    }
+    pic::end_of_interrupt(pic::TIMER_IRQ);
+    thread::timer_tick(); // Last, it may switch to another thread
+}
+
+// 0x21: Keyboard (IRQ1)
+fn keyboard_handler(_frame: &mut TrapFrame) {
+    keyboard::handle_interrupt();
+    pic::end_of_interrupt(pic::KEYBOARD_IRQ);
+}
+
+// 0x23: COM2 and COM4 (IRQ3)
+fn com2_handler(_frame: &mut TrapFrame) {
+    serial::handle_interrupt(pic::COM2_IRQ);
+    pic::end_of_interrupt(pic::COM2_IRQ);
+}
+
+// 0x24: COM1 and COM3 (IRQ4)
+fn com1_handler(_frame: &mut TrapFrame) {
+    serial::handle_interrupt(pic::COM1_IRQ);
+    pic::end_of_interrupt(pic::COM1_IRQ);
+}
+
+pub fn init_idt() {
+    let mut idt = IDT.lock();
+    idt.init();
+    idt.load();
+
+    let irq_handlers: [(u8, Handler); 4] = [
+        (pic::TIMER_IRQ, timer_handler),
+        (pic::KEYBOARD_IRQ, keyboard_handler),
+        (pic::COM2_IRQ, com2_handler),
+        (pic::COM1_IRQ, com1_handler),
+    ];
+    for (irq, handler) in irq_handlers {
+        idt.register(pic::vector(irq), handler, GateOptions::new())
+            .expect("Couldn't register an IRQ handler");
+    }
+}
+
+///// COUNTERS
+
+static INTERRUPT_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn count_interrupt(vector: u8) {
+    INTERRUPT_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of times the interrupt was handled since boot
+pub fn interrupt_count(vector: u8) -> u64 {
+    INTERRUPT_COUNTS[vector as usize].load(Ordering::Relaxed)
+}
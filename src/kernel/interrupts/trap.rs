@@ -0,0 +1,167 @@
+// Entry of every interrupt and exception. The x86-interrupt ABI only gives the handlers the frame pushed by the
+// CPU, these stubs save every register in a `TrapFrame` so that they can be dumped, or changed by the debugger.
+
+use core::arch::global_asm;
+
+use super::{ExceptionReport, count_interrupt, fixup_exception, handler};
+use crate::gdb;
+
+global_asm!(
+    r#"
+// One stub of TRAP_STUB_SIZE bytes per vector, the biggest one is 12 bytes (two pushes and a near jump).
+// Vectors without an error code get a dummy one so that the frame is always the same, the list must match
+// `has_error_code`.
+.balign 16
+.global trap_stubs
+trap_stubs:
+.set vector, 0
+.rept 256
+    .balign 16
+    .if !(vector == 0x08 || (vector >= 0x0A && vector <= 0x0E) || vector == 0x11 || vector == 0x15 || vector == 0x1D || vector == 0x1E)
+    push 0
+    .endif
+    push vector
+    jmp trap_common
+    .set vector, vector + 1
+.endr
+
+// From ring 3 the GS base is the one of user space, swapgs gives the per-CPU one back and swaps again on the
+// way out. TODO : An NMI right after the swapgs of the way out sees the user one, check the MSR in the IST
+// handlers once they use per-CPU data.
+trap_common:
+    test byte ptr [rsp + 3 * 8], 3 // RPL of the CS of the frame
+    jz 2f
+    swapgs
+2:
+    push rax
+    push rcx
+    push rdx
+    push rbx
+    push rbp
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rdi, rsp
+    // Frame of a call from the interrupted instruction, so that backtraces go on through the interrupted code
+    push qword ptr [rsp + 17 * 8] // RIP
+    push rbp
+    mov rbp, rsp
+    // The stack is 16 bytes aligned here, the interrupted code may be using SSE registers
+    sub rsp, 512
+    fxsave [rsp]
+    cld
+    call trap_dispatch
+    fxrstor [rsp]
+    add rsp, 512 + 16
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rbp
+    pop rbx
+    pop rdx
+    pop rcx
+    pop rax
+    add rsp, 16 // Vector and error code
+    test byte ptr [rsp + 8], 3
+    jz 3f
+    swapgs
+3:
+    iretq
+"#
+);
+
+const TRAP_STUB_SIZE: usize = 16;
+
+unsafe extern "C" {
+    static trap_stubs: u8;
+}
+
+/// Address of the stub of a vector, for its gate
+pub(super) fn trap_stub(vector: u8) -> u64 {
+    (&raw const trap_stubs as usize + vector as usize * TRAP_STUB_SIZE) as u64
+}
+
+/// Registers of the interrupted code as pushed by the trap stubs, changes are applied when it resumes
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+pub const DEBUG_VECTOR: u8 = 0x1;
+pub const NMI_VECTOR: u8 = 0x2;
+pub const BREAKPOINT_VECTOR: u8 = 0x3;
+pub const DEVICE_NOT_AVAILABLE_VECTOR: u8 = 0x7;
+pub const DOUBLE_FAULT_VECTOR: u8 = 0x8;
+pub const GENERAL_PROTECTION_VECTOR: u8 = 0xD;
+pub const PAGE_FAULT_VECTOR: u8 = 0xE;
+pub const X87_FPU_VECTOR: u8 = 0x10;
+pub const MACHINE_CHECK_VECTOR: u8 = 0x12;
+pub const SIMD_FPU_VECTOR: u8 = 0x13;
+
+/// First vector that isn't a CPU exception
+pub const FIRST_INTERRUPT_VECTOR: u8 = 0x20;
+
+#[unsafe(no_mangle)]
+extern "C" fn trap_dispatch(frame: &mut TrapFrame) {
+    let vector = frame.vector as u8;
+    count_interrupt(vector);
+    if let Some(handler) = handler(vector) {
+        handler(frame);
+    } else if vector < FIRST_INTERRUPT_VECTOR {
+        handle_exception(frame);
+    }
+    // Interrupts nobody handles (spurious IRQs for instance) are only counted
+}
+
+fn handle_exception(frame: &mut TrapFrame) {
+    let vector = frame.vector as u8;
+    if matches!(vector, DEBUG_VECTOR | BREAKPOINT_VECTOR) && gdb::handle_trap(frame) {
+        return;
+    }
+    if matches!(vector, GENERAL_PROTECTION_VECTOR | PAGE_FAULT_VECTOR) && fixup_exception(frame) {
+        return;
+    }
+    // A process can't take the kernel down with it
+    if frame.cs & 3 == 3 {
+        crate::process::kill_current(frame);
+    }
+    // Nothing recovers from the others yet
+    panic!("{}", ExceptionReport::new(frame));
+}
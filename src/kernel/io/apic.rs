@@ -0,0 +1,157 @@
+// Local APIC of each CPU, through its registers in memory (xAPIC mode). It's what starts the other CPUs and
+// sends them interrupts, the IRQs still go through the 8259 PIC to the first one.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    paging::{self, PAGE_SIZE},
+    x86::{ApicBase, Feature, Ia32ApicBase, MemoryType, Msr, cpu_info},
+};
+
+// Register offsets
+const ID: usize = 0x20;
+const VERSION: usize = 0x30;
+const TASK_PRIORITY: usize = 0x80;
+const EOI: usize = 0xB0;
+const SPURIOUS_INTERRUPT: usize = 0xF0;
+const ERROR_STATUS: usize = 0x280;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+/// Vector of the interrupts the APIC drops, they need no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// Interrupt command register
+const DELIVERY_FIXED: u32 = 0b000 << 8;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const SHORTHAND_SELF: u32 = 0b01 << 18;
+const SHORTHAND_ALL: u32 = 0b10 << 18;
+const SHORTHAND_ALL_BUT_SELF: u32 = 0b11 << 18;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ApicError {
+    NotAvailable,
+    /// The firmware left it in x2APIC mode, where the registers are MSRs
+    X2ApicMode,
+}
+
+/// Where an interprocessor interrupt goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiDestination {
+    Apic(u32),
+    SelfOnly,
+    All,
+    AllButSelf,
+}
+
+// Base of the registers, 0 until `init_local_apic`. The same address on every CPU, each one sees its own.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn register(offset: usize) -> *mut u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    assert!(base != 0, "The local APIC isn't initialized");
+    (base + offset) as *mut u32
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { ptr::read_volatile(register(offset)) }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { ptr::write_volatile(register(offset), value) };
+}
+
+/// Enables the local APIC of the calling CPU
+pub fn init_local_apic() -> Result<(), ApicError> {
+    if !cpu_info().has(Feature::APIC) {
+        return Err(ApicError::NotAvailable);
+    }
+    let apic_base = Ia32ApicBase::read().map_err(|_| ApicError::NotAvailable)?;
+    if apic_base.contains(ApicBase::X2APIC_ENABLE) {
+        return Err(ApicError::X2ApicMode);
+    }
+    if !apic_base.contains(ApicBase::ENABLE) {
+        unsafe { Ia32ApicBase::write(apic_base | ApicBase::ENABLE) }
+            .map_err(|_| ApicError::NotAvailable)?;
+    }
+    let base = apic_base.address();
+    if BASE.swap(base, Ordering::Relaxed) != base {
+        // Registers are never cached, MTRRs usually say so already
+        if let Err(err) = paging::set_memory_type(base, PAGE_SIZE, MemoryType::Uncacheable) {
+            log::warn!("Local APIC registers may be cached : {:?}", err);
+        }
+    }
+
+    write(TASK_PRIORITY, 0);
+    write(SPURIOUS_INTERRUPT, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    // The error status is only updated by a write
+    write(ERROR_STATUS, 0);
+    Ok(())
+}
+
+pub fn is_initialized() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// ID of the local APIC of the calling CPU, what IPIs are sent to
+pub fn local_apic_id() -> u32 {
+    read(ID) >> 24
+}
+
+pub fn version() -> u8 {
+    read(VERSION) as u8
+}
+
+pub fn end_of_interrupt() {
+    write(EOI, 0);
+}
+
+fn send(destination: IpiDestination, command: u32) {
+    let (apic_id, shorthand) = match destination {
+        IpiDestination::Apic(apic_id) => (apic_id, 0),
+        IpiDestination::SelfOnly => (0, SHORTHAND_SELF),
+        IpiDestination::All => (0, SHORTHAND_ALL),
+        IpiDestination::AllButSelf => (0, SHORTHAND_ALL_BUT_SELF),
+    };
+    wait_for_delivery();
+    write(ICR_HIGH, apic_id << 24);
+    // Writing the low half sends it
+    write(ICR_LOW, command | shorthand);
+    wait_for_delivery();
+}
+
+fn wait_for_delivery() {
+    while read(ICR_LOW) & DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Sends an interrupt with `vector` to other CPUs, or to the calling one
+pub fn send_ipi(destination: IpiDestination, vector: u8) {
+    send(destination, DELIVERY_FIXED | LEVEL_ASSERT | vector as u32);
+}
+
+pub fn send_nmi(destination: IpiDestination) {
+    send(destination, DELIVERY_NMI | LEVEL_ASSERT);
+}
+
+/// Resets a CPU, it then waits for a startup IPI
+pub fn send_init(apic_id: u32) {
+    send(IpiDestination::Apic(apic_id), DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+/// Starts a CPU waiting after INIT in real mode at `page * 4096`
+pub fn send_startup(apic_id: u32, page: u8) {
+    send(
+        IpiDestination::Apic(apic_id),
+        DELIVERY_STARTUP | LEVEL_ASSERT | page as u32,
+    );
+}
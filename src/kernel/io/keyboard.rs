@@ -0,0 +1,259 @@
+// PS/2 keyboard through the i8042 controller, scancode set 1 with a US layout
+
+use bitflags::bitflags;
+use spin::Mutex;
+
+use super::{PS2_KEYBOARD_IN, PS2_KEYBOARD_OUT, inb};
+use crate::{framebuffer, sync::SpinLock};
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const EXTENDED_PREFIX: u8 = 0xE0;
+const RELEASE_BIT: u8 = 0x80;
+const KEY_QUEUE_SIZE: usize = 64;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Modifiers: u8 {
+        const SHIFT = 1 << 0;
+        const CTRL = 1 << 1;
+        const ALT = 1 << 2;
+        const CAPS_LOCK = 1 << 3;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+// (normal, shifted) characters of the scancodes 0x00 to 0x39
+const SCANCODE_CHARS: [(char, char); 0x3A] = [
+    ('\0', '\0'),
+    ('\0', '\0'), // Escape
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    ('0', ')'),
+    ('-', '_'),
+    ('=', '+'),
+    ('\0', '\0'), // Backspace
+    ('\0', '\0'), // Tab
+    ('q', 'Q'),
+    ('w', 'W'),
+    ('e', 'E'),
+    ('r', 'R'),
+    ('t', 'T'),
+    ('y', 'Y'),
+    ('u', 'U'),
+    ('i', 'I'),
+    ('o', 'O'),
+    ('p', 'P'),
+    ('[', '{'),
+    (']', '}'),
+    ('\0', '\0'), // Enter
+    ('\0', '\0'), // Left ctrl
+    ('a', 'A'),
+    ('s', 'S'),
+    ('d', 'D'),
+    ('f', 'F'),
+    ('g', 'G'),
+    ('h', 'H'),
+    ('j', 'J'),
+    ('k', 'K'),
+    ('l', 'L'),
+    (';', ':'),
+    ('\'', '"'),
+    ('`', '~'),
+    ('\0', '\0'), // Left shift
+    ('\\', '|'),
+    ('z', 'Z'),
+    ('x', 'X'),
+    ('c', 'C'),
+    ('v', 'V'),
+    ('b', 'B'),
+    ('n', 'N'),
+    ('m', 'M'),
+    (',', '<'),
+    ('.', '>'),
+    ('/', '?'),
+    ('\0', '\0'), // Right shift
+    ('*', '*'),   // Keypad
+    ('\0', '\0'), // Left alt
+    (' ', ' '),
+];
+
+struct KeyboardState {
+    modifiers: Modifiers,
+    extended: bool,
+}
+
+struct KeyQueue {
+    events: [Option<KeyEvent>; KEY_QUEUE_SIZE],
+    read: usize,
+    len: usize,
+}
+
+static STATE: Mutex<KeyboardState> = Mutex::new(KeyboardState {
+    modifiers: Modifiers::empty(),
+    extended: false,
+});
+
+// Filled by the interrupt handler
+static QUEUE: SpinLock<KeyQueue> = SpinLock::new(
+    "keyboard.queue",
+    KeyQueue {
+        events: [None; KEY_QUEUE_SIZE],
+        read: 0,
+        len: 0,
+    },
+);
+
+/// Drops whatever the controller already has in its output buffer
+pub fn init() {
+    unsafe {
+        while inb(PS2_KEYBOARD_OUT) & STATUS_OUTPUT_FULL != 0 {
+            inb(PS2_KEYBOARD_IN);
+        }
+    }
+}
+
+/// Called by the keyboard interrupt
+pub fn handle_interrupt() {
+    let scancode = unsafe { inb(PS2_KEYBOARD_IN) };
+    let Some(event) = STATE.lock().decode(scancode) else {
+        return;
+    };
+
+    // Scrollback is handled right away so that it works even when nobody reads the keyboard
+    match (event.key, event.modifiers.contains(Modifiers::SHIFT)) {
+        (Key::PageUp, true) => framebuffer::scroll_view(1),
+        (Key::PageDown, true) => framebuffer::scroll_view(-1),
+        _ => QUEUE.lock().push(event),
+    }
+}
+
+/// Next key pressed, if any
+pub fn read_key() -> Option<KeyEvent> {
+    QUEUE.lock().pop()
+}
+
+impl KeyboardState {
+    // Returns an event for key presses, releases only update the modifiers
+    fn decode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+            return None;
+        }
+        let extended = core::mem::take(&mut self.extended);
+        let released = scancode & RELEASE_BIT != 0;
+        let code = scancode & !RELEASE_BIT;
+
+        let modifier = match (extended, code) {
+            (false, 0x2A | 0x36) => Some(Modifiers::SHIFT),
+            (_, 0x1D) => Some(Modifiers::CTRL),
+            (_, 0x38) => Some(Modifiers::ALT),
+            _ => None,
+        };
+        if let Some(modifier) = modifier {
+            self.modifiers.set(modifier, !released);
+            return None;
+        }
+        if released {
+            return None;
+        }
+
+        let key = if extended {
+            match code {
+                0x1C => Key::Enter,
+                0x35 => Key::Char('/'),
+                0x47 => Key::Home,
+                0x48 => Key::Up,
+                0x49 => Key::PageUp,
+                0x4B => Key::Left,
+                0x4D => Key::Right,
+                0x4F => Key::End,
+                0x50 => Key::Down,
+                0x51 => Key::PageDown,
+                0x52 => Key::Insert,
+                0x53 => Key::Delete,
+                _ => return None,
+            }
+        } else {
+            match code {
+                0x01 => Key::Escape,
+                0x0E => Key::Backspace,
+                0x0F => Key::Tab,
+                0x1C => Key::Enter,
+                0x3A => {
+                    self.modifiers.toggle(Modifiers::CAPS_LOCK);
+                    return None;
+                }
+                0x3B..=0x44 => Key::F(code - 0x3B + 1),
+                0x57 => Key::F(11),
+                0x58 => Key::F(12),
+                _ => {
+                    let (normal, shifted) = *SCANCODE_CHARS.get(code as usize)?;
+                    let shift = self.modifiers.contains(Modifiers::SHIFT);
+                    let caps = self.modifiers.contains(Modifiers::CAPS_LOCK)
+                        && normal.is_ascii_alphabetic();
+                    match if shift != caps { shifted } else { normal } {
+                        '\0' => return None,
+                        char => Key::Char(char),
+                    }
+                }
+            }
+        };
+
+        Some(KeyEvent {
+            key,
+            modifiers: self.modifiers,
+        })
+    }
+}
+
+impl KeyQueue {
+    // Drops the event if the queue is full
+    fn push(&mut self, event: KeyEvent) {
+        if self.len < KEY_QUEUE_SIZE {
+            self.events[(self.read + self.len) % KEY_QUEUE_SIZE] = Some(event);
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<KeyEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.read].take();
+        self.read = (self.read + 1) % KEY_QUEUE_SIZE;
+        self.len -= 1;
+        event
+    }
+}
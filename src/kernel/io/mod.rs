@@ -1,3 +1,8 @@
+pub mod apic;
+pub mod keyboard;
+pub mod pci;
+pub mod pic;
+pub mod pit;
 pub mod ports;
 pub mod serial;
 
@@ -0,0 +1,126 @@
+// PCI configuration space through the legacy 0xCF8/0xCFC mechanism
+// TODO : PCIe ECAM once ACPI tables are parsed
+
+use super::{inl, outl};
+use crate::x86::without_interrupts;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+const NO_DEVICE: u16 = 0xFFFF;
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn read_config(&self, offset: u8) -> u32 {
+        let address = CONFIG_ENABLE
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xFC) as u32;
+        // Both ports are used in turn
+        without_interrupts(|| unsafe {
+            outl(CONFIG_ADDRESS, address);
+            inl(CONFIG_DATA)
+        })
+    }
+
+    fn vendor_id(&self) -> u16 {
+        self.read_config(0x00) as u16
+    }
+
+    fn header_type(&self) -> u8 {
+        (self.read_config(0x0C) >> 16) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+}
+
+impl PciDevice {
+    fn read(address: PciAddress) -> Option<Self> {
+        let id = address.read_config(0x00);
+        if id as u16 == NO_DEVICE {
+            return None;
+        }
+        let class = address.read_config(0x08);
+        Some(PciDevice {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+        })
+    }
+
+    /// Name of the class, `None` for the ones nobody meets
+    pub fn class_name(&self) -> Option<&'static str> {
+        Some(match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "Storage controller",
+            (0x02, _) => "Network controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia controller",
+            (0x05, _) => "Memory controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x07, _) => "Communication controller",
+            (0x08, _) => "System peripheral",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, 0x05) => "SMBus controller",
+            (0x0C, _) => "Serial bus controller",
+            _ => return None,
+        })
+    }
+}
+
+/// Calls `f` for every function of every device, by brute force over the 256 buses
+pub fn for_each_device(mut f: impl FnMut(&PciDevice)) {
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let address = PciAddress {
+                bus,
+                device,
+                function: 0,
+            };
+            if address.vendor_id() == NO_DEVICE {
+                continue;
+            }
+            let functions = if address.header_type() & HEADER_MULTIFUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+            for function in 0..functions {
+                let address = PciAddress {
+                    function,
+                    ..address
+                };
+                if let Some(device) = PciDevice::read(address) {
+                    f(&device);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// Legacy 8259 PIC pair, remapped above the CPU exceptions
+// TODO : PIC to APIC
+
+use super::{inb, outb};
+use crate::x86::without_interrupts;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+const ICW1_ICW4: u8 = 0x01; // ICW4 will be sent
+const ICW1_INIT: u8 = 0x10;
+const ICW4_8086: u8 = 0x01;
+const PIC_EOI: u8 = 0x20;
+
+pub const PIC1_OFFSET: u8 = 0x20;
+pub const PIC2_OFFSET: u8 = PIC1_OFFSET + 8;
+const CASCADE_IRQ: u8 = 2;
+
+pub const TIMER_IRQ: u8 = 0;
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const COM2_IRQ: u8 = 3;
+pub const COM1_IRQ: u8 = 4;
+
+// Gives the PIC some time between commands on old hardware
+fn io_wait() {
+    unsafe { outb(0x80, 0) };
+}
+
+/// Remaps IRQs 0-15 to the vectors 0x20-0x2F and masks all of them
+pub fn init() {
+    without_interrupts(|| unsafe {
+        outb(PIC1_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(PIC2_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(PIC1_DATA, PIC1_OFFSET);
+        io_wait();
+        outb(PIC2_DATA, PIC2_OFFSET);
+        io_wait();
+        outb(PIC1_DATA, 1 << CASCADE_IRQ); // Slave on IRQ2
+        io_wait();
+        outb(PIC2_DATA, CASCADE_IRQ); // Slave cascade identity
+        io_wait();
+        outb(PIC1_DATA, ICW4_8086);
+        io_wait();
+        outb(PIC2_DATA, ICW4_8086);
+        io_wait();
+
+        outb(PIC1_DATA, !(1 << CASCADE_IRQ));
+        outb(PIC2_DATA, 0xFF);
+    });
+}
+
+fn data_port(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    }
+}
+
+pub fn unmask(irq: u8) {
+    let (port, line) = data_port(irq);
+    without_interrupts(|| unsafe { outb(port, inb(port) & !(1 << line)) });
+}
+
+pub fn mask(irq: u8) {
+    let (port, line) = data_port(irq);
+    without_interrupts(|| unsafe { outb(port, inb(port) | (1 << line)) });
+}
+
+/// Vector the IRQ is delivered on
+pub const fn vector(irq: u8) -> u8 {
+    PIC1_OFFSET + irq
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(PIC2_COMMAND, PIC_EOI);
+        }
+        outb(PIC1_COMMAND, PIC_EOI);
+    }
+}
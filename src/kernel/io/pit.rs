@@ -0,0 +1,39 @@
+// 8253/8254 Programmable Interval Timer, channel 0 drives IRQ0
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::outb;
+use crate::x86::without_interrupts;
+
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+// Channel 0, lobyte/hibyte access, mode 3 (square wave), binary
+const PIT_MODE: u8 = 0b0011_0110;
+
+const PIT_BASE_FREQUENCY: u64 = 1_193_182;
+pub const PIT_FREQUENCY: u64 = 100; // Hz
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    let divisor = (PIT_BASE_FREQUENCY / PIT_FREQUENCY) as u16;
+    without_interrupts(|| unsafe {
+        outb(PIT_COMMAND, PIT_MODE);
+        outb(PIT_CHANNEL0, divisor as u8);
+        outb(PIT_CHANNEL0, (divisor >> 8) as u8);
+    });
+}
+
+/// Called by the timer interrupt, returns the new tick count
+pub fn tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Ticks since boot, at `PIT_FREQUENCY`
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / PIT_FREQUENCY
+}
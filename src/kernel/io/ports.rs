@@ -20,6 +20,16 @@ pub unsafe fn outw(port: u16, data: u16) {
     unsafe { asm!("out dx, ax", in("dx") port, in("ax") data) };
 }
 
+pub unsafe fn inl(port: u16) -> u32 {
+    let data;
+    unsafe { asm!("in eax, dx", in("dx") port, out("eax") data) };
+    data
+}
+
+pub unsafe fn outl(port: u16, data: u32) {
+    unsafe { asm!("out dx, eax", in("dx") port, in("eax") data) };
+}
+
 ///////////////////////////////
 
 pub const PS2_KEYBOARD_IN: u16 = 0x60;
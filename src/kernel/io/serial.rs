@@ -1,15 +1,514 @@
-use lazy_static::lazy_static;
+// 16550 UART driver for COM1-COM4. Writes go through a ring buffer emptied by the transmit interrupt,
+// received bytes are queued by the receive interrupt. Before interrupts are set up, and after a panic,
+// the ports are polled instead.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use spin::Mutex;
-use uart_16550::SerialPort;
 
-use crate::x86::without_interrupts;
+use super::{inb, outb, pic};
+use crate::{
+    params::{ParamError, ParamType},
+    x86::without_interrupts,
+};
+
+// Registers, offsets from the base port
+const DATA: u16 = 0; // Divisor low byte when DLAB is set
+const INTERRUPT_ENABLE: u16 = 1; // Divisor high byte when DLAB is set
+const FIFO_CONTROL: u16 = 2; // Interrupt identification when read
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+const IER_RECEIVED_DATA: u8 = 1 << 0;
+const IER_TRANSMIT_EMPTY: u8 = 1 << 1;
+
+const IIR_NO_INTERRUPT: u8 = 1 << 0;
+const IIR_ID_MASK: u8 = 0b1110;
+const IIR_TRANSMIT_EMPTY: u8 = 0b0010;
+const IIR_FIFO_ENABLED: u8 = 0b1100_0000;
+
+// Enable and clear both FIFOs, interrupt when 14 bytes are received
+const FCR_ENABLE_14: u8 = 0b1100_0111;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT2: u8 = 1 << 3; // Gates the IRQ line on PCs
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+const UART_CLOCK: usize = 115200;
+const FIFO_SIZE: usize = 16;
+
+// TODO : Bigger buffers once there is an allocator
+const TX_BUFFER_SIZE: usize = 4096;
+const RX_BUFFER_SIZE: usize = 256;
+
+/// Base port and IRQ of the 4 standard COM ports
+pub const COM_PORTS: [(u16, u8); 4] = [
+    (0x3F8, pic::COM1_IRQ),
+    (0x2F8, pic::COM2_IRQ),
+    (0x3E8, pic::COM1_IRQ),
+    (0x2E8, pic::COM2_IRQ),
+];
+
+crate::kernel_param!(
+    SERIAL_CONSOLE: usize = 1,
+    "serial.console",
+    "COM port used for the serial console (1-4)"
+);
+crate::kernel_param!(
+    SERIAL_BAUD: usize = UART_CLOCK,
+    "serial.baud",
+    "Baud rate of the serial ports"
+);
+crate::kernel_param!(
+    SERIAL_LINE: LineSettings = LineSettings::DEFAULT,
+    "serial.line",
+    "Data bits, parity and stop bits of the serial ports, e.g. 8N1"
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    pub data_bits: u8, // 5 to 8
+    pub parity: Parity,
+    pub stop_bits: u8, // 1 or 2
+}
 
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
+impl LineSettings {
+    pub const DEFAULT: LineSettings = LineSettings {
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
     };
+
+    fn line_control(&self) -> u8 {
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        };
+        (self.data_bits - 5) | ((self.stop_bits - 1) << 2) | (parity << 3)
+    }
+}
+
+impl fmt::Display for LineSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+            Parity::Mark => 'M',
+            Parity::Space => 'S',
+        };
+        write!(f, "{}{}{}", self.data_bits, parity, self.stop_bits)
+    }
+}
+
+impl ParamType for LineSettings {
+    fn parse(value: &'static str) -> Result<Self, ParamError> {
+        const EXPECTED: ParamError = ParamError::InvalidValue("<5-8><N|O|E|M|S><1|2>, e.g. 8N1");
+        let &[data_bits, parity, stop_bits] = value.as_bytes() else {
+            return Err(EXPECTED);
+        };
+        let parity = match parity.to_ascii_uppercase() {
+            b'N' => Parity::None,
+            b'O' => Parity::Odd,
+            b'E' => Parity::Even,
+            b'M' => Parity::Mark,
+            b'S' => Parity::Space,
+            _ => return Err(EXPECTED),
+        };
+        match (data_bits, stop_bits) {
+            (b'5'..=b'8', b'1'..=b'2') => Ok(LineSettings {
+                data_bits: data_bits - b'0',
+                parity,
+                stop_bits: stop_bits - b'0',
+            }),
+            _ => Err(EXPECTED),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SerialError {
+    NotPresent,
+    InvalidBaudRate(usize),
+    /// Used by the console or reserved by someone else
+    InUse,
+}
+
+////////////////////////////////
+
+struct ByteRing<const N: usize> {
+    bytes: [u8; N],
+    read: usize,
+    len: usize,
+}
+
+impl<const N: usize> ByteRing<N> {
+    const fn new() -> Self {
+        ByteRing {
+            bytes: [0; N],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    // Returns false if it's full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.bytes[(self.read + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.bytes[self.read];
+        self.read = (self.read + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+pub struct SerialPort {
+    base: u16,
+    irq: u8,
+    present: bool,
+    initialized: bool,
+    fifo_size: usize,
+    interrupt_driven: bool,
+    interrupt_enable: u8,
+    reserved: bool, // Always polled
+    tx: ByteRing<TX_BUFFER_SIZE>,
+    rx: ByteRing<RX_BUFFER_SIZE>,
+}
+
+impl SerialPort {
+    const fn new(com: usize) -> Self {
+        SerialPort {
+            base: COM_PORTS[com].0,
+            irq: COM_PORTS[com].1,
+            present: false,
+            initialized: false,
+            fifo_size: 1,
+            interrupt_driven: false,
+            interrupt_enable: 0,
+            reserved: false,
+            tx: ByteRing::new(),
+            rx: ByteRing::new(),
+        }
+    }
+
+    fn read_reg(&self, reg: u16) -> u8 {
+        unsafe { inb(self.base + reg) }
+    }
+
+    fn write_reg(&self, reg: u16, value: u8) {
+        unsafe { outb(self.base + reg, value) }
+    }
+
+    // The scratch register is missing on the oldest 8250s but no PC emulator has one of them
+    fn has_scratch(&self) -> bool {
+        self.write_reg(SCRATCH, 0xAE);
+        self.read_reg(SCRATCH) == 0xAE
+    }
+
+    // Checks that it really is a UART by sending a byte to itself, the line must be set
+    fn loopback_test(&self) -> bool {
+        self.write_reg(MODEM_CONTROL, MCR_LOOPBACK | MCR_RTS | MCR_OUT2);
+        while self.read_reg(LINE_STATUS) & LSR_DATA_READY != 0 {
+            self.read_reg(DATA);
+        }
+        self.write_reg(DATA, 0xAE);
+        let mut looped = false;
+        for _ in 0..10_000 {
+            if self.read_reg(LINE_STATUS) & LSR_DATA_READY != 0 {
+                looped = self.read_reg(DATA) == 0xAE;
+                break;
+            }
+        }
+        self.write_reg(MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+        looped
+    }
+
+    /// Probes the port and sets its line, the port stays polled until `enable_interrupts`
+    pub fn init(&mut self, baud: usize, line: LineSettings) -> Result<(), SerialError> {
+        if baud == 0 || !UART_CLOCK.is_multiple_of(baud) {
+            return Err(SerialError::InvalidBaudRate(baud));
+        }
+        let divisor = (UART_CLOCK / baud) as u16;
+
+        self.initialized = true;
+        self.present = false;
+        if !self.has_scratch() {
+            return Err(SerialError::NotPresent);
+        }
+
+        self.write_reg(INTERRUPT_ENABLE, 0);
+        self.write_reg(LINE_CONTROL, LCR_DLAB);
+        self.write_reg(DATA, divisor as u8);
+        self.write_reg(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+        self.write_reg(LINE_CONTROL, line.line_control());
+        self.write_reg(FIFO_CONTROL, FCR_ENABLE_14);
+        // Both FIFO bits are only set by 16550A and later, the 16550 FIFO is broken and the 8250 has none
+        self.fifo_size = if self.read_reg(FIFO_CONTROL) & IIR_FIFO_ENABLED == IIR_FIFO_ENABLED {
+            FIFO_SIZE
+        } else {
+            1
+        };
+
+        if !self.loopback_test() {
+            return Err(SerialError::NotPresent);
+        }
+        self.present = true;
+        self.write_reg(INTERRUPT_ENABLE, self.interrupt_enable);
+        Ok(())
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    fn set_interrupt_enable(&mut self, bits: u8, enabled: bool) {
+        if enabled {
+            self.interrupt_enable |= bits;
+        } else {
+            self.interrupt_enable &= !bits;
+        }
+        self.write_reg(INTERRUPT_ENABLE, self.interrupt_enable);
+    }
+
+    fn transmit_empty(&self) -> bool {
+        self.read_reg(LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0
+    }
+
+    fn write_polled(&mut self, byte: u8) {
+        while !self.transmit_empty() {
+            core::hint::spin_loop();
+        }
+        self.write_reg(DATA, byte);
+    }
+
+    // Moves what fits from the ring into the FIFO, which must be empty
+    fn fill_fifo(&mut self) {
+        for _ in 0..self.fifo_size {
+            match self.tx.pop() {
+                Some(byte) => self.write_reg(DATA, byte),
+                None => break,
+            }
+        }
+        let pending = !self.tx.is_empty();
+        self.set_interrupt_enable(IER_TRANSMIT_EMPTY, pending);
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+        if !self.interrupt_driven {
+            self.write_polled(byte);
+            return;
+        }
+        // Full when interrupts are off for too long, the ring is emptied by hand then
+        while !self.tx.push(byte) {
+            let oldest = self.tx.pop().unwrap_or(byte);
+            self.write_polled(oldest);
+        }
+        if self.transmit_empty() {
+            self.fill_fifo();
+        } else if self.interrupt_enable & IER_TRANSMIT_EMPTY == 0 {
+            // The FIFO is still draining what was there before, the interrupt takes the rest once it's empty
+            self.set_interrupt_enable(IER_TRANSMIT_EMPTY, true);
+        }
+    }
+
+    /// Received byte, if any
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if self.present && !self.interrupt_driven {
+            return (self.read_reg(LINE_STATUS) & LSR_DATA_READY != 0).then(|| self.read_reg(DATA));
+        }
+        self.rx.pop()
+    }
+
+    /// Sends everything buffered and goes back to polling, so that output still works with interrupts off
+    pub fn switch_to_polling(&mut self) {
+        if !self.present {
+            return;
+        }
+        self.set_interrupt_enable(IER_TRANSMIT_EMPTY | IER_RECEIVED_DATA, false);
+        self.interrupt_driven = false;
+        while let Some(byte) = self.tx.pop() {
+            self.write_polled(byte);
+        }
+    }
+
+    fn enable_interrupts(&mut self) {
+        if self.present && !self.reserved {
+            self.interrupt_driven = true;
+            self.set_interrupt_enable(IER_RECEIVED_DATA, true);
+        }
+    }
+
+    fn handle_interrupt(&mut self) {
+        if !self.present || self.reserved {
+            return;
+        }
+        loop {
+            let identification = self.read_reg(FIFO_CONTROL);
+            if identification & IIR_NO_INTERRUPT != 0 {
+                break;
+            }
+            if identification & IIR_ID_MASK == IIR_TRANSMIT_EMPTY {
+                self.fill_fifo();
+            }
+            // Received data, receive timeout and line status are all cleared by reading what's there
+            while self.read_reg(LINE_STATUS) & LSR_DATA_READY != 0 {
+                let byte = self.read_reg(DATA);
+                self.rx.push(byte); // Dropped if nobody reads
+            }
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.write_byte(byte));
+        Ok(())
+    }
+}
+
+pub static SERIAL_PORTS: [Mutex<SerialPort>; 4] = [
+    Mutex::new(SerialPort::new(0)),
+    Mutex::new(SerialPort::new(1)),
+    Mutex::new(SerialPort::new(2)),
+    Mutex::new(SerialPort::new(3)),
+];
+
+// Index in SERIAL_PORTS
+static CONSOLE_PORT: AtomicUsize = AtomicUsize::new(0);
+
+/// Probes every COM port with the settings of the kernel parameters
+pub fn init() {
+    let com = SERIAL_CONSOLE.get();
+    if (1..=4).contains(&com) {
+        CONSOLE_PORT.store(com - 1, Ordering::Relaxed);
+    } else {
+        log::warn!("No COM{} port, the serial console stays on COM1", com);
+    }
+
+    for (i, port) in SERIAL_PORTS.iter().enumerate() {
+        let result = without_interrupts(|| port.lock().init(SERIAL_BAUD.get(), SERIAL_LINE.get()));
+        match result {
+            Ok(()) => log::info!(
+                "COM{} : {} bauds, {}",
+                i + 1,
+                SERIAL_BAUD.get(),
+                SERIAL_LINE.get()
+            ),
+            Err(SerialError::NotPresent) => {}
+            Err(err) => log::warn!("COM{} : {:?}", i + 1, err),
+        }
+    }
+}
+
+/// Switches the ports found to interrupts, the PIC has to be initialized
+pub fn enable_interrupts() {
+    for port in &SERIAL_PORTS {
+        let irq = without_interrupts(|| {
+            let mut port = port.lock();
+            port.enable_interrupts();
+            port.is_present().then_some(port.irq)
+        });
+        if let Some(irq) = irq {
+            pic::unmask(irq);
+        }
+    }
+}
+
+/// Takes a port away from the console and from interrupts, for code that polls it by itself
+pub fn reserve(com: usize) -> Result<&'static Mutex<SerialPort>, SerialError> {
+    if !(1..=4).contains(&com) {
+        return Err(SerialError::NotPresent);
+    }
+    if com - 1 == CONSOLE_PORT.load(Ordering::Relaxed) {
+        return Err(SerialError::InUse);
+    }
+    let port = &SERIAL_PORTS[com - 1];
+    without_interrupts(|| {
+        let mut port = port.lock();
+        if !port.present {
+            return Err(SerialError::NotPresent);
+        }
+        if port.reserved {
+            return Err(SerialError::InUse);
+        }
+        port.reserved = true;
+        port.switch_to_polling();
+        Ok(())
+    })?;
+    Ok(port)
+}
+
+/// Called by the IRQ3 and IRQ4 interrupts, every port on the line is serviced
+pub fn handle_interrupt(irq: u8) {
+    for port in &SERIAL_PORTS {
+        // Never locked by the interrupted code, which holds it with interrupts off
+        let mut port = port.lock();
+        if port.irq == irq {
+            port.handle_interrupt();
+        }
+    }
+}
+
+/// Byte received on the serial console, if any
+pub fn read_byte() -> Option<u8> {
+    without_interrupts(|| console_port().lock().read_byte())
+}
+
+/// Flushes the buffered output and polls from now on, for when interrupts can't be trusted anymore
+pub fn switch_to_polling() {
+    for port in &SERIAL_PORTS {
+        // Forced as it may have been locked by the code that panicked
+        unsafe { port.force_unlock() };
+        port.lock().switch_to_polling();
+    }
+}
+
+fn console_port() -> &'static Mutex<SerialPort> {
+    &SERIAL_PORTS[CONSOLE_PORT.load(Ordering::Relaxed)]
 }
 
 // MACROS
@@ -20,10 +519,12 @@ pub fn _serial_print(args: ::core::fmt::Arguments) {
 
     // Deactivating interrupts to avoid deadlocks
     without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
+        let mut port = console_port().lock();
+        // Used before `init` by the early logs, with the default settings
+        if !port.initialized {
+            let _ = port.init(UART_CLOCK, LineSettings::DEFAULT);
+        }
+        port.write_fmt(args).expect("Printing to serial failed");
     });
 }
 
@@ -0,0 +1,166 @@
+use core::{fmt, str::FromStr};
+
+use log::LevelFilter;
+
+// TODO : Use a Vec and Strings once there is an allocator
+const MAX_DIRECTIVES: usize = 16;
+const MAX_TARGET_LEN: usize = 48;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FilterError {
+    UnknownLevel,
+    TooManyDirectives,
+    TargetTooLong,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::UnknownLevel => write!(f, "unknown log level"),
+            FilterError::TooManyDirectives => {
+                write!(f, "more than {} directives", MAX_DIRECTIVES)
+            }
+            FilterError::TargetTooLong => {
+                write!(f, "target longer than {} bytes", MAX_TARGET_LEN)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Directive {
+    target: [u8; MAX_TARGET_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl Directive {
+    const EMPTY: Directive = Directive {
+        target: [0; MAX_TARGET_LEN],
+        len: 0,
+        level: LevelFilter::Off,
+    };
+
+    fn target(&self) -> &str {
+        // Copied from a whole &str
+        core::str::from_utf8(&self.target[..self.len]).unwrap_or("")
+    }
+
+    // `x86` matches `x86` and `x86::msr` but not `x86_64`
+    fn matches(&self, target: &str) -> bool {
+        let prefix = self.target();
+        target
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+/// Level filter in the env_logger syntax : `info,framebuffer=debug,x86::msr=trace`.
+/// Targets are written without the `ab_os_bel::kernel::` prefix and the most specific one wins.
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    default: LevelFilter,
+    directives: [Directive; MAX_DIRECTIVES],
+    count: usize,
+}
+
+impl Filter {
+    pub const fn new(default: LevelFilter) -> Self {
+        Filter {
+            default,
+            directives: [Directive::EMPTY; MAX_DIRECTIVES],
+            count: 0,
+        }
+    }
+
+    /// Parses a filter, the level of anything not listed stays at `default` unless the spec has a bare level.
+    /// A bare target (`framebuffer`) enables everything for it.
+    pub fn parse(spec: &str, default: LevelFilter) -> Result<Self, FilterError> {
+        let mut filter = Filter::new(default);
+        for part in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            match part.split_once('=') {
+                Some((target, level)) => {
+                    let level = LevelFilter::from_str(level.trim())
+                        .map_err(|_| FilterError::UnknownLevel)?;
+                    filter.push(target.trim(), level)?;
+                }
+                None => match LevelFilter::from_str(part) {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.push(part, LevelFilter::Trace)?,
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    fn push(&mut self, target: &str, level: LevelFilter) -> Result<(), FilterError> {
+        if self.count == MAX_DIRECTIVES {
+            return Err(FilterError::TooManyDirectives);
+        }
+        if target.len() > MAX_TARGET_LEN {
+            return Err(FilterError::TargetTooLong);
+        }
+        let directive = &mut self.directives[self.count];
+        directive.target[..target.len()].copy_from_slice(target.as_bytes());
+        directive.len = target.len();
+        directive.level = level;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn directives(&self) -> &[Directive] {
+        &self.directives[..self.count]
+    }
+
+    /// Level enabled for a target, as given by `log` (full module path) or already shortened
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let target = short_target(target);
+        self.directives()
+            .iter()
+            .filter(|directive| directive.matches(target))
+            .max_by_key(|directive| directive.len)
+            .map_or(self.default, |directive| directive.level)
+    }
+
+    /// Most verbose level any target can have
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives()
+            .iter()
+            .map(|directive| directive.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", level_name(self.default))?;
+        for directive in self.directives() {
+            write!(f, ",{}={}", directive.target(), level_name(directive.level))?;
+        }
+        Ok(())
+    }
+}
+
+// Lowercase like in filter strings
+fn level_name(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+/// Module path without the crate and `kernel` prefixes, as displayed and filtered
+pub fn short_target(target: &str) -> &str {
+    target
+        .strip_prefix("ab_os_bel::kernel::")
+        .or_else(|| target.strip_prefix("ab_os_bel::"))
+        .unwrap_or(target)
+}
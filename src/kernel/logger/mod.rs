@@ -0,0 +1,223 @@
+// Kernel logger behind the `log` macros, every record goes to the serial port, the framebuffer console
+// and an in memory ring, each sink with its own filter.
+//
+// Filters come from the kernel parameters : `log=<filter>` for every sink, `log.serial=`, `log.console=`
+// and `log.ring=` for a single one, see `Filter` for the syntax.
+
+mod filter;
+mod ring;
+
+pub use filter::*;
+pub use ring::*;
+
+use core::fmt::Write;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+
+use crate::{
+    framebuffer::{self, WRITER},
+    io::pit,
+    serial_print,
+    x86::without_interrupts,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Serial,
+    Console,
+    Ring,
+}
+
+impl Sink {
+    pub const ALL: [Sink; 3] = [Sink::Serial, Sink::Console, Sink::Ring];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Sink::Serial => "serial",
+            Sink::Console => "console",
+            Sink::Ring => "ring",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Sink::ALL.into_iter().find(|sink| sink.name() == name)
+    }
+
+    // The ring keeps more so that it can be looked at after the fact
+    const fn default_level(self) -> LevelFilter {
+        match self {
+            Sink::Serial | Sink::Console => LevelFilter::Info,
+            Sink::Ring => LevelFilter::Debug,
+        }
+    }
+}
+
+struct KernelLogger {
+    filters: Mutex<[Filter; Sink::ALL.len()]>,
+}
+
+static LOGGER: KernelLogger = KernelLogger {
+    filters: Mutex::new([
+        Filter::new(Sink::Serial.default_level()),
+        Filter::new(Sink::Console.default_level()),
+        Filter::new(Sink::Ring.default_level()),
+    ]),
+};
+
+/// Installs the logger, records are dropped until then
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        update_max_level(&LOGGER.filters.lock()[..]);
+    }
+}
+
+crate::kernel_param!(LOG_FILTER: &'static str = "", "log", "Log filter of every sink");
+crate::kernel_param!(LOG_SERIAL_FILTER: &'static str = "", "log.serial", "Log filter of the serial port");
+crate::kernel_param!(LOG_CONSOLE_FILTER: &'static str = "", "log.console", "Log filter of the framebuffer console");
+crate::kernel_param!(LOG_RING_FILTER: &'static str = "", "log.ring", "Log filter of the dmesg ring");
+
+/// Applies the `log` and `log.<sink>` kernel parameters, the per sink ones win
+pub fn apply_cmdline_filters() {
+    let params = [
+        (&LOG_FILTER, &Sink::ALL[..]),
+        (&LOG_SERIAL_FILTER, &[Sink::Serial][..]),
+        (&LOG_CONSOLE_FILTER, &[Sink::Console][..]),
+        (&LOG_RING_FILTER, &[Sink::Ring][..]),
+    ];
+    for (param, sinks) in params.into_iter().filter(|(param, _)| param.is_set()) {
+        for &sink in sinks {
+            if let Err(err) = set_filter(sink, param.get()) {
+                log::warn!(
+                    "Ignoring the {} log filter {} : {}",
+                    sink.name(),
+                    param.get(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// Replaces the filter of a sink, the levels not given in `spec` go back to the sink default
+pub fn set_filter(sink: Sink, spec: &str) -> Result<(), FilterError> {
+    let filter = Filter::parse(spec, sink.default_level())?;
+    without_interrupts(|| {
+        let mut filters = LOGGER.filters.lock();
+        filters[sink as usize] = filter;
+        update_max_level(&filters[..]);
+    });
+    Ok(())
+}
+
+pub fn filter(sink: Sink) -> Filter {
+    without_interrupts(|| LOGGER.filters.lock()[sink as usize])
+}
+
+/// Writes the content of the log ring, oldest line first
+pub fn dmesg(writer: &mut (impl Write + ?Sized)) -> core::fmt::Result {
+    without_interrupts(|| LOG_RING.lock().read(writer))
+}
+
+// `log` drops anything above it before even calling the logger
+fn update_max_level(filters: &[Filter]) {
+    let max_level = filters
+        .iter()
+        .map(Filter::max_level)
+        .max()
+        .unwrap_or(LevelFilter::Off);
+    log::set_max_level(max_level);
+}
+
+////////////////////////////////
+
+// SGR color of the level on the serial port and the console
+fn level_color(level: Level) -> u8 {
+    match level {
+        Level::Error => 31, // Red
+        Level::Warn => 33,  // Yellow
+        Level::Info => 32,  // Green
+        Level::Debug => 34, // Blue
+        Level::Trace => 90, // Gray
+    }
+}
+
+// `[    1.234] WARN  framebuffer::utils: message`
+fn write_record(writer: &mut impl Write, record: &Record, uptime_ms: u64, colored: bool) {
+    let level = record.level();
+    let _ = if colored {
+        writeln!(
+            writer,
+            "[{:>5}.{:03}] \x1b[{}m{:<5}\x1b[0m {}: {}",
+            uptime_ms / 1000,
+            uptime_ms % 1000,
+            level_color(level),
+            level.as_str(),
+            short_target(record.target()),
+            record.args()
+        )
+    } else {
+        writeln!(
+            writer,
+            "[{:>5}.{:03}] {:<5} {}: {}",
+            uptime_ms / 1000,
+            uptime_ms % 1000,
+            level.as_str(),
+            short_target(record.target()),
+            record.args()
+        )
+    };
+}
+
+// Goes through the printing functions so that their locks are shared with the rest of the kernel
+struct SerialWriter;
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        serial_print!("{}", s);
+        Ok(())
+    }
+}
+
+struct ConsoleWriter;
+
+impl Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        framebuffer::print_framebuffer(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        without_interrupts(|| {
+            let filters = self.filters.lock();
+            filters
+                .iter()
+                .any(|filter| metadata.level() <= filter.level_for(metadata.target()))
+        })
+    }
+
+    fn log(&self, record: &Record) {
+        without_interrupts(|| {
+            let enabled = {
+                let filters = self.filters.lock();
+                Sink::ALL
+                    .map(|sink| record.level() <= filters[sink as usize].level_for(record.target()))
+            };
+            let uptime_ms = pit::uptime_ms();
+
+            if enabled[Sink::Serial as usize] {
+                write_record(&mut SerialWriter, record, uptime_ms, true);
+            }
+            if enabled[Sink::Console as usize] && WRITER.get().is_some() {
+                write_record(&mut ConsoleWriter, record, uptime_ms, true);
+            }
+            if enabled[Sink::Ring as usize] {
+                write_record(&mut *LOG_RING.lock(), record, uptime_ms, false);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
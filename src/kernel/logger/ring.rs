@@ -0,0 +1,100 @@
+// In memory copy of the log, like the kernel ring buffer behind dmesg
+
+use core::fmt;
+
+use spin::Mutex;
+
+// TODO : Grow it once there is an allocator
+pub const LOG_RING_SIZE: usize = 64 * 1024;
+
+/// Text of the most recent log lines, the oldest whole lines are dropped when it's full
+pub struct LogRing {
+    bytes: [u8; LOG_RING_SIZE],
+    start: usize,
+    len: usize,
+}
+
+pub static LOG_RING: Mutex<LogRing> = Mutex::new(LogRing {
+    bytes: [0; LOG_RING_SIZE],
+    start: 0,
+    len: 0,
+});
+
+impl LogRing {
+    fn byte(&self, i: usize) -> u8 {
+        self.bytes[(self.start + i) % LOG_RING_SIZE]
+    }
+
+    // A line longer than the whole ring loses its beginning
+    fn drop_oldest_line(&mut self) {
+        let line_len = (0..self.len)
+            .find(|&i| self.byte(i) == b'\n')
+            .map_or(self.len, |i| i + 1);
+        self.start = (self.start + line_len) % LOG_RING_SIZE;
+        self.len -= line_len;
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == LOG_RING_SIZE {
+            self.drop_oldest_line();
+        }
+        self.bytes[(self.start + self.len) % LOG_RING_SIZE] = byte;
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Writes the whole content to `writer`, oldest line first
+    pub fn read(&self, writer: &mut (impl fmt::Write + ?Sized)) -> fmt::Result {
+        // Characters can be split by the end of the array, so it goes through a small buffer
+        let mut chunk = [0; 256];
+        let mut used = 0;
+        for i in 0..self.len {
+            chunk[used] = self.byte(i);
+            used += 1;
+            if used == chunk.len() {
+                used = write_utf8(&mut chunk, used, writer)?;
+            }
+        }
+        write_utf8(&mut chunk, used, writer)?;
+        Ok(())
+    }
+}
+
+// Writes the valid UTF-8 part of `chunk[..used]` and moves what's left at the start, returning its length.
+// Only a line that lost its beginning can hold invalid bytes, they are replaced.
+fn write_utf8(
+    chunk: &mut [u8],
+    used: usize,
+    writer: &mut (impl fmt::Write + ?Sized),
+) -> Result<usize, fmt::Error> {
+    let (valid, invalid) = match core::str::from_utf8(&chunk[..used]) {
+        Ok(str) => (str.len(), 0),
+        Err(err) => (err.valid_up_to(), err.error_len().unwrap_or(0)),
+    };
+    // Can't fail, it was just checked
+    writer.write_str(core::str::from_utf8(&chunk[..valid]).unwrap_or(""))?;
+    if invalid > 0 {
+        writer.write_char(char::REPLACEMENT_CHARACTER)?;
+    }
+    chunk.copy_within(valid + invalid..used, 0);
+    Ok(used - valid - invalid)
+}
+
+impl fmt::Write for LogRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
@@ -1,5 +1,17 @@
+pub mod acpi;
+pub mod backtrace;
 pub mod framebuffer; // TODO : Change this whole mess when we have an allocator
+pub mod gdb;
 pub mod gdt;
 pub mod interrupts;
 pub mod io;
+pub mod logger;
+pub mod paging;
+pub mod params;
+pub mod process;
+pub mod shell;
+pub mod smp;
+pub mod stacks;
+pub mod sync;
+pub mod thread;
 pub mod x86;
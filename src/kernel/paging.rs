@@ -0,0 +1,238 @@
+// Paging through the recursive mapping set up in boot.s (last P4 entry points to the P4 itself)
+
+use bitflags::bitflags;
+
+use crate::{
+    smp,
+    sync::SpinLock,
+    x86::{MemoryType, invlpg, pat_index},
+};
+
+////////////////////////////////
+
+pub const PAGE_SIZE: usize = 0x1000;
+pub const HUGE_PAGE_SIZE: usize = 0x200000;
+pub const ENTRY_COUNT: usize = 512;
+
+// Virtual address of the P4 table through the recursive entry
+const P4_TABLE_ADDR: usize = 0xFFFF_FFFF_FFFF_F000;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageTableFlags: u64 {
+        const PRESENT = 1 << 0;
+        const WRITABLE = 1 << 1;
+        const USER_ACCESSIBLE = 1 << 2;
+        const WRITE_THROUGH = 1 << 3; // PWT, bit 0 of the PAT index
+        const NO_CACHE = 1 << 4; // PCD, bit 1 of the PAT index
+        const ACCESSED = 1 << 5;
+        const DIRTY = 1 << 6;
+        const HUGE_PAGE = 1 << 7; // Also PAT (bit 2 of the index) in a P1 entry
+        const GLOBAL = 1 << 8;
+        const HUGE_PAT = 1 << 12; // PAT bit (bit 2 of the index) in a huge page entry
+        const NO_EXECUTE = 1 << 63;
+    }
+}
+
+impl PageTableFlags {
+    /// Flags selecting the given PAT entry, for a 4KiB page or a huge page
+    pub fn from_pat_index(index: usize, huge: bool) -> Self {
+        let mut flags = PageTableFlags::empty();
+        flags.set(PageTableFlags::WRITE_THROUGH, index & 0b001 != 0);
+        flags.set(PageTableFlags::NO_CACHE, index & 0b010 != 0);
+        let pat_bit = if huge {
+            PageTableFlags::HUGE_PAT
+        } else {
+            PageTableFlags::HUGE_PAGE
+        };
+        flags.set(pat_bit, index & 0b100 != 0);
+        flags
+    }
+
+    fn pat_mask(huge: bool) -> Self {
+        Self::from_pat_index(0b111, huge)
+    }
+}
+
+#[derive(Debug)]
+pub enum PagingError {
+    NotMapped(usize),
+    NoPatEntry(MemoryType),
+    NoFreeTable,
+}
+
+////////////////////////////////
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+    pub fn flags(&self) -> PageTableFlags {
+        PageTableFlags::from_bits_retain(self.0 & !Self::ADDR_MASK)
+    }
+
+    pub fn addr(&self) -> usize {
+        (self.0 & Self::ADDR_MASK) as usize
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.flags().contains(PageTableFlags::PRESENT)
+    }
+
+    pub fn set(&mut self, addr: usize, flags: PageTableFlags) {
+        self.0 = (addr as u64 & Self::ADDR_MASK) | flags.bits();
+    }
+
+    pub fn set_flags(&mut self, flags: PageTableFlags) {
+        self.set(self.addr(), flags);
+    }
+}
+
+#[repr(C, align(4096))]
+pub struct PageTable {
+    entries: [PageTableEntry; ENTRY_COUNT],
+}
+
+impl PageTable {
+    const fn empty() -> Self {
+        PageTable {
+            entries: [PageTableEntry(0); ENTRY_COUNT],
+        }
+    }
+
+    pub fn entry(&mut self, index: usize) -> &mut PageTableEntry {
+        &mut self.entries[index]
+    }
+
+    // Only valid for tables accessed through the recursive mapping
+    fn next_table_address(&self, index: usize) -> Option<usize> {
+        let flags = self.entries[index].flags();
+        if flags.contains(PageTableFlags::PRESENT) && !flags.contains(PageTableFlags::HUGE_PAGE) {
+            Some(((self as *const _ as usize) << 9) | (index << 12))
+        } else {
+            None
+        }
+    }
+
+    pub fn next_table(&mut self, index: usize) -> Option<&mut PageTable> {
+        self.next_table_address(index)
+            .map(|addr| unsafe { &mut *(addr as *mut PageTable) })
+    }
+}
+
+/// The currently active P4 table, through the recursive mapping
+pub fn p4_table() -> &'static mut PageTable {
+    unsafe { &mut *(P4_TABLE_ADDR as *mut PageTable) }
+}
+
+/// Index of the entry for `addr` in a table of `level` (4 for the P4)
+pub fn table_index(addr: usize, level: usize) -> usize {
+    (addr >> (12 + 9 * (level - 1))) & (ENTRY_COUNT - 1)
+}
+
+/// Finds the entry mapping `addr`, returning it along with the size of the page it maps
+pub fn leaf_entry(addr: usize) -> Option<(&'static mut PageTableEntry, usize)> {
+    let p3 = p4_table().next_table(table_index(addr, 4))?;
+    let p2 = p3.next_table(table_index(addr, 3))?;
+    let p2_index = table_index(addr, 2);
+    if p2
+        .entry(p2_index)
+        .flags()
+        .contains(PageTableFlags::HUGE_PAGE)
+    {
+        let entry = p2.entry(p2_index);
+        return entry.is_present().then_some((entry, HUGE_PAGE_SIZE));
+    }
+    let p1 = p2.next_table(p2_index)?;
+    let entry = p1.entry(table_index(addr, 1));
+    entry.is_present().then_some((entry, PAGE_SIZE))
+}
+
+////////////////////////////////
+
+/// Changes the memory type of the pages covering `[addr, addr + size)` through their PAT bits.
+/// The PAT must have been programmed with `x86::init_pat` beforehand.
+/// Huge pages are changed as a whole, so the range is effectively rounded to 2MiB when it's huge mapped.
+pub fn set_memory_type(
+    addr: usize,
+    size: usize,
+    memory_type: MemoryType,
+) -> Result<(), PagingError> {
+    let index = pat_index(memory_type).ok_or(PagingError::NoPatEntry(memory_type))?;
+
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < addr + size {
+        let (entry, page_size) = leaf_entry(page).ok_or(PagingError::NotMapped(page))?;
+        let huge = page_size == HUGE_PAGE_SIZE;
+        let flags = (entry.flags() - PageTableFlags::pat_mask(huge))
+            | PageTableFlags::from_pat_index(index, huge);
+        entry.set_flags(flags);
+        invlpg(page);
+
+        page = (page & !(page_size - 1)) + page_size;
+    }
+    smp::shootdown_tlb(addr, size);
+    Ok(())
+}
+
+// TODO : Take them from a frame allocator once there is one
+const SPLIT_TABLE_COUNT: usize = 8;
+static mut SPLIT_TABLES: [PageTable; SPLIT_TABLE_COUNT] =
+    [const { PageTable::empty() }; SPLIT_TABLE_COUNT];
+// How many of them are used, and splits are done one at a time with it
+static SPLIT_TABLES_USED: SpinLock<usize> = SpinLock::new("paging.split", 0);
+
+/// Maps the huge page containing `addr` with 4KiB pages instead, with the same flags.
+/// Does nothing if it already is.
+pub fn split_huge_page(addr: usize) -> Result<(), PagingError> {
+    let start = addr & !(HUGE_PAGE_SIZE - 1);
+    {
+        // Another CPU could be splitting the same page
+        let mut used = SPLIT_TABLES_USED.lock();
+        let (entry, page_size) = leaf_entry(addr).ok_or(PagingError::NotMapped(addr))?;
+        if page_size != HUGE_PAGE_SIZE {
+            return Ok(());
+        }
+        if *used >= SPLIT_TABLE_COUNT {
+            return Err(PagingError::NoFreeTable);
+        }
+        let table = unsafe { &mut *(&raw mut SPLIT_TABLES).cast::<PageTable>().add(*used) };
+        *used += 1;
+        split_into(entry, table);
+        // A CR3 reload would keep the huge entry if it's global
+        for page in (start..start + HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+            invlpg(page);
+        }
+    }
+    smp::shootdown_tlb(start, HUGE_PAGE_SIZE);
+    Ok(())
+}
+
+// Points the huge page `entry` to `table`, filled with its 4KiB pages
+fn split_into(entry: &mut PageTableEntry, table: &mut PageTable) {
+    // The PAT bit of a huge page is bit 12, which `addr` keeps, it's bit 7 in a P1 entry
+    let base = entry.addr() & !(HUGE_PAGE_SIZE - 1);
+    let pat = entry.addr() & (1 << 12) != 0;
+    let mut flags = entry.flags() - PageTableFlags::HUGE_PAGE;
+    flags.set(PageTableFlags::HUGE_PAGE, pat);
+    for i in 0..ENTRY_COUNT {
+        table.entry(i).set(base + i * PAGE_SIZE, flags);
+    }
+
+    // The kernel is identity mapped, so the address of the table is its physical address
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    entry.set(table as *const PageTable as usize, table_flags);
+}
+
+/// Unmaps the 4KiB page at `addr` so that touching it faults, huge pages are split first
+pub fn unmap_guard_page(addr: usize) -> Result<(), PagingError> {
+    split_huge_page(addr)?;
+    let (entry, _) = leaf_entry(addr).ok_or(PagingError::NotMapped(addr))?;
+    entry.set_flags(entry.flags() - PageTableFlags::PRESENT);
+    invlpg(addr);
+    smp::shootdown_tlb(addr, PAGE_SIZE);
+    Ok(())
+}
@@ -0,0 +1,268 @@
+// Kernel parameters given on the GRUB command line (`multiboot2 /boot/grub/ab-os-bel log=debug fb.scale=2`).
+// Modules declare them with `kernel_param!` next to the code using them, the declarations are gathered
+// in the `.kernel_params` section by the linker.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::MULTIBOOT2_INFO;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ParamError {
+    /// The value doesn't parse as the type of the parameter
+    InvalidValue(&'static str),
+    /// A parameter without `=value` which isn't a boolean
+    MissingValue,
+    UnknownKey,
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::InvalidValue(expected) => write!(f, "expected {}", expected),
+            ParamError::MissingValue => write!(f, "missing value"),
+            ParamError::UnknownKey => write!(f, "unknown parameter"),
+        }
+    }
+}
+
+/// Type a kernel parameter can have. The command line lives as long as the kernel so strings are borrowed from it.
+pub trait ParamType: Copy + Send + fmt::Display + 'static {
+    fn parse(value: &'static str) -> Result<Self, ParamError>;
+
+    /// Value of a key given without `=value`, only booleans have one
+    fn bare() -> Option<Self> {
+        None
+    }
+}
+
+impl ParamType for bool {
+    fn parse(value: &'static str) -> Result<Self, ParamError> {
+        match value {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(ParamError::InvalidValue("a boolean")),
+        }
+    }
+
+    fn bare() -> Option<Self> {
+        Some(true)
+    }
+}
+
+impl ParamType for usize {
+    fn parse(value: &'static str) -> Result<Self, ParamError> {
+        let parsed = match value.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        parsed.map_err(|_| ParamError::InvalidValue("an integer"))
+    }
+}
+
+impl ParamType for &'static str {
+    fn parse(value: &'static str) -> Result<Self, ParamError> {
+        Ok(value)
+    }
+}
+
+/// Value of a kernel parameter, the default until the command line is parsed
+pub struct Param<T: ParamType> {
+    value: Mutex<T>,
+    set: AtomicBool,
+}
+
+impl<T: ParamType> Param<T> {
+    pub const fn new(default: T) -> Self {
+        Param {
+            value: Mutex::new(default),
+            set: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        *self.value.lock()
+    }
+
+    /// Whether the value comes from the command line
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Relaxed)
+    }
+}
+
+/// Type erased access to a `Param`, for the registry
+pub trait ParamSlot: Sync {
+    fn set(&self, value: Option<&'static str>) -> Result<(), ParamError>;
+    fn is_set(&self) -> bool;
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl<T: ParamType> ParamSlot for Param<T> {
+    fn set(&self, value: Option<&'static str>) -> Result<(), ParamError> {
+        let value = match value {
+            Some(value) => T::parse(value)?,
+            None => T::bare().ok_or(ParamError::MissingValue)?,
+        };
+        *self.value.lock() = value;
+        self.set.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn is_set(&self) -> bool {
+        Param::is_set(self)
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
+/// Entry of the `.kernel_params` section, made by `kernel_param!`
+pub struct KernelParam {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub slot: &'static dyn ParamSlot,
+}
+
+impl fmt::Display for KernelParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name)?;
+        self.slot.fmt_value(f)?;
+        if !self.slot.is_set() {
+            write!(f, " (default)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Declares a kernel parameter : `kernel_param!(pub FB_SCALE: usize = 1, "fb.scale", "Console font scale");`
+#[macro_export]
+macro_rules! kernel_param {
+    ($vis:vis $ident:ident : $ty:ty = $default:expr, $name:literal, $description:literal) => {
+        $vis static $ident: $crate::params::Param<$ty> = $crate::params::Param::new($default);
+
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".kernel_params")]
+            static ENTRY: $crate::params::KernelParam = $crate::params::KernelParam {
+                name: $name,
+                description: $description,
+                slot: &$ident,
+            };
+        };
+    };
+}
+
+unsafe extern "C" {
+    // Defined by linker.ld, only their addresses matter
+    static __kernel_params_start: u8;
+    static __kernel_params_end: u8;
+}
+
+/// Every parameter declared in the kernel
+pub fn params() -> &'static [KernelParam] {
+    unsafe {
+        let start = (&raw const __kernel_params_start).cast::<KernelParam>();
+        let end = (&raw const __kernel_params_end).cast::<KernelParam>();
+        // The section only holds `KernelParam`s, placed one after the other
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+pub fn find_param(name: &str) -> Option<&'static KernelParam> {
+    params().iter().find(|param| param.name == name)
+}
+
+/// Sets a parameter by name, `None` for a key given without `=value`
+pub fn set_param(name: &str, value: Option<&'static str>) -> Result<(), ParamError> {
+    find_param(name)
+        .ok_or(ParamError::UnknownKey)?
+        .slot
+        .set(value)
+}
+
+/// Command line given by GRUB
+pub fn cmdline() -> Option<&'static str> {
+    MULTIBOOT2_INFO.get()?.command_line_tag()?.cmdline().ok()
+}
+
+/// Sets the parameters from the command line, warning about anything that doesn't fit
+pub fn init() {
+    let Some(cmdline) = cmdline() else {
+        return;
+    };
+    log::info!("Kernel command line : {}", cmdline);
+
+    for (key, value) in CmdlineArgs::new(cmdline) {
+        if let Err(err) = set_param(key, value) {
+            log::warn!("Ignoring kernel parameter {} : {}", key, err);
+        }
+    }
+}
+
+/// Writes every parameter with its effective value, one per line
+pub fn dump(writer: &mut (impl fmt::Write + ?Sized)) -> fmt::Result {
+    for param in params() {
+        writeln!(writer, "{} - {}", param, param.description)?;
+    }
+    Ok(())
+}
+
+////////////////////////////////
+
+/// `key=value` and `key` arguments of a command line, values can be quoted to hold spaces (`key="a b"`)
+struct CmdlineArgs {
+    rest: &'static str,
+}
+
+impl CmdlineArgs {
+    fn new(cmdline: &'static str) -> Self {
+        let cmdline = cmdline.trim_start();
+        // GRUB starts it with the path of the kernel
+        let rest = if cmdline.starts_with('/') {
+            cmdline.split_once(' ').map_or("", |(_, rest)| rest)
+        } else {
+            cmdline
+        };
+        CmdlineArgs { rest }
+    }
+}
+
+impl Iterator for CmdlineArgs {
+    type Item = (&'static str, Option<&'static str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let arg = self.rest.trim_start();
+        if arg.is_empty() {
+            return None;
+        }
+
+        let mut in_quotes = false;
+        let end = arg
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .map_or(arg.len(), |(i, _)| i);
+        self.rest = &arg[end..];
+
+        let arg = &arg[..end];
+        Some(match arg.split_once('=') {
+            Some((key, value)) => {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (key, Some(value))
+            }
+            None => (arg, None),
+        })
+    }
+}
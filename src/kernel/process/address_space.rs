@@ -0,0 +1,106 @@
+// Page tables of a process. The kernel half (the identity mapping of the first P4 entry and the recursive
+// entry) is shared with every other address space, and only reachable from ring 0. The rest is the process'.
+
+use super::{
+    ProcessError,
+    frames::{alloc_frame, free_frame},
+};
+use crate::{
+    paging::{ENTRY_COUNT, PageTable, PageTableFlags, p4_table, table_index},
+    x86::read_cr3,
+};
+
+/// Where user space starts and ends, the first P4 entry is the kernel one
+pub const USER_START: usize = 0x0000_0080_0000_0000;
+pub const USER_END: usize = 0x0000_8000_0000_0000;
+
+// Entries the kernel half is made of
+const KERNEL_P4_ENTRY: usize = 0;
+const RECURSIVE_P4_ENTRY: usize = ENTRY_COUNT - 1;
+
+pub struct AddressSpace {
+    p4: usize,
+}
+
+// Frames are identity mapped, so tables are used through their physical address
+fn table(frame: usize) -> &'static mut PageTable {
+    unsafe { &mut *(frame as *mut PageTable) }
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, ProcessError> {
+        let p4 = alloc_frame().ok_or(ProcessError::OutOfFrames)?;
+        let kernel_entry = *p4_table().entry(KERNEL_P4_ENTRY);
+        let new = table(p4);
+        *new.entry(KERNEL_P4_ENTRY) = kernel_entry;
+        new.entry(RECURSIVE_P4_ENTRY)
+            .set(p4, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        Ok(AddressSpace { p4 })
+    }
+
+    /// Physical address of the P4 table, what CR3 gets
+    pub fn p4(&self) -> usize {
+        self.p4
+    }
+
+    /// Maps a zeroed page at `addr` for ring 3, returns its frame so that the kernel can fill it
+    pub fn map_user_page(&mut self, addr: usize, writable: bool) -> Result<usize, ProcessError> {
+        if !(USER_START..USER_END).contains(&addr) {
+            return Err(ProcessError::NotUserAddress(addr));
+        }
+        // Tables let everything through, the last level decides
+        let table_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let mut current = self.p4;
+        for level in (2..=4).rev() {
+            let entry = table(current).entry(table_index(addr, level));
+            if !entry.is_present() {
+                let frame = alloc_frame().ok_or(ProcessError::OutOfFrames)?;
+                entry.set(frame, table_flags);
+            }
+            current = entry.addr();
+        }
+        let entry = table(current).entry(table_index(addr, 1));
+        if entry.is_present() {
+            return Err(ProcessError::AlreadyMapped(addr));
+        }
+        let frame = alloc_frame().ok_or(ProcessError::OutOfFrames)?;
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        flags.set(PageTableFlags::WRITABLE, writable);
+        entry.set(frame, flags);
+        Ok(frame)
+    }
+}
+
+// Frees every table and page of user space, from the lowest level up
+fn free_tables(frame: usize, level: usize) {
+    let table = table(frame);
+    // The kernel entries of the P4 aren't the process'
+    let entries = if level == 4 {
+        table_index(USER_START, 4)..=table_index(USER_END - 1, 4)
+    } else {
+        0..=ENTRY_COUNT - 1
+    };
+    for i in entries {
+        let entry = table.entry(i);
+        if !entry.is_present() {
+            continue;
+        }
+        if level == 1 {
+            free_frame(entry.addr());
+        } else {
+            free_tables(entry.addr(), level - 1);
+        }
+    }
+    free_frame(frame);
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(
+            read_cr3() != self.p4,
+            "An address space can't be freed while it's in use"
+        );
+        free_tables(self.p4, 4);
+    }
+}
@@ -0,0 +1,54 @@
+// Physical pages for the processes : their page tables and their memory. They come from a fixed pool in the
+// kernel, which is identity mapped, so a frame is also reachable at its physical address.
+
+use core::ptr;
+
+use crate::{paging::PAGE_SIZE, sync::SpinLock};
+
+// TODO : Take them from a frame allocator once there is one
+const FRAME_COUNT: usize = 256;
+
+#[repr(C, align(4096))]
+struct Frame([u8; PAGE_SIZE]);
+
+static mut FRAMES: [Frame; FRAME_COUNT] = [const { Frame([0; PAGE_SIZE]) }; FRAME_COUNT];
+
+// One bit per frame, set when it's used
+static USED: SpinLock<[u64; FRAME_COUNT / 64]> =
+    SpinLock::new("process.frames", [0; FRAME_COUNT / 64]);
+
+fn frames_start() -> usize {
+    &raw const FRAMES as usize
+}
+
+/// A zeroed frame, None when the pool is empty
+pub(super) fn alloc_frame() -> Option<usize> {
+    let index = {
+        let mut used = USED.lock();
+        let index = (0..FRAME_COUNT).find(|&i| used[i / 64] & (1 << (i % 64)) == 0)?;
+        used[index / 64] |= 1 << (index % 64);
+        index
+    };
+    let frame = frames_start() + index * PAGE_SIZE;
+    unsafe { ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE) };
+    Some(frame)
+}
+
+pub(super) fn free_frame(frame: usize) {
+    let index = (frame - frames_start()) / PAGE_SIZE;
+    assert!(index < FRAME_COUNT, "{:#x} isn't a process frame", frame);
+    let mut used = USED.lock();
+    assert!(
+        used[index / 64] & (1 << (index % 64)) != 0,
+        "Frame {:#x} freed twice",
+        frame
+    );
+    used[index / 64] &= !(1 << (index % 64));
+}
+
+/// Frames in use and in the pool
+pub fn frame_usage() -> (usize, usize) {
+    let used = USED.lock();
+    let count = used.iter().map(|bits| bits.count_ones() as usize).sum();
+    (count, FRAME_COUNT)
+}
@@ -0,0 +1,348 @@
+// User processes : a kernel thread with its own address space that runs a program in ring 3. It gets there
+// through `iretq`, comes back to the kernel through system calls (see syscall.rs) and interrupts, and is
+// killed by any exception it causes. Without an allocator there is a fixed number of them, and a program is
+// flat code loaded at `USER_CODE_ADDR` with a stack below `USER_STACK_TOP`.
+
+mod address_space;
+mod frames;
+mod programs;
+mod syscall;
+
+pub use address_space::*;
+pub use frames::frame_usage;
+pub use programs::*;
+pub use syscall::*;
+
+use core::{arch::asm, fmt, ptr};
+
+use crate::{
+    find_module,
+    gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR},
+    interrupts::{PAGE_FAULT_VECTOR, TrapFrame, exception_name},
+    paging::PAGE_SIZE,
+    shell::{CommandError, FnCommand},
+    shell_command,
+    sync::SpinLock,
+    thread::{self, JoinHandle, Priority, ThreadError, ThreadId},
+    x86::read_cr2,
+};
+
+pub const MAX_PROCESSES: usize = 8;
+
+/// Where programs are loaded, and where they start
+pub const USER_CODE_ADDR: usize = USER_START;
+pub const USER_STACK_TOP: usize = USER_END - PAGE_SIZE; // The page below the end is left unmapped
+const USER_STACK_PAGES: usize = 4;
+const MAX_PROGRAM_PAGES: usize = 16;
+
+const RFLAGS_IF: u64 = 1 << 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessId(pub u64);
+
+impl fmt::Display for ProcessId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProcessError {
+    NoFreeSlot,
+    OutOfFrames,
+    ProgramTooBig,
+    NotUserAddress(usize),
+    AlreadyMapped(usize),
+    Thread(ThreadError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i64),
+    Killed { vector: u8 }, // By the exception it caused
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitStatus::Exited(code) => write!(f, "exited with {}", code),
+            ExitStatus::Killed { vector } => write!(f, "killed by {}", exception_name(*vector).0),
+        }
+    }
+}
+
+struct Process {
+    pid: ProcessId,
+    name: &'static str,
+    thread: Option<ThreadId>, // Set by the thread itself once it runs
+    address_space: Option<AddressSpace>,
+    status: Option<ExitStatus>,
+    detached: bool, // Nobody will wait for it, its slot is free once it exits
+}
+
+struct ProcessTable {
+    processes: [Option<Process>; MAX_PROCESSES],
+    next_pid: u64,
+}
+
+impl ProcessTable {
+    fn current(&mut self) -> Option<&mut Process> {
+        let thread = thread::current_id();
+        self.processes
+            .iter_mut()
+            .flatten()
+            .find(|process| process.thread == Some(thread))
+    }
+}
+
+static PROCESSES: SpinLock<ProcessTable> = SpinLock::new(
+    "process.table",
+    ProcessTable {
+        processes: [const { None }; MAX_PROCESSES],
+        next_pid: 1,
+    },
+);
+
+/// Enables system calls on the calling CPU
+pub fn init() {
+    init_syscalls();
+}
+
+// LOADING ///
+
+fn load(image: &[u8]) -> Result<AddressSpace, ProcessError> {
+    if image.len() > MAX_PROGRAM_PAGES * PAGE_SIZE {
+        return Err(ProcessError::ProgramTooBig);
+    }
+    let mut address_space = AddressSpace::new()?;
+    // The code can't be changed by the process
+    for (i, chunk) in image.chunks(PAGE_SIZE).enumerate() {
+        let frame = address_space.map_user_page(USER_CODE_ADDR + i * PAGE_SIZE, false)?;
+        unsafe { ptr::copy_nonoverlapping(chunk.as_ptr(), frame as *mut u8, chunk.len()) };
+    }
+    for i in 1..=USER_STACK_PAGES {
+        address_space.map_user_page(USER_STACK_TOP - i * PAGE_SIZE, true)?;
+    }
+    Ok(address_space)
+}
+
+/// Waits for a process to exit, dropping it instead detaches the process
+pub struct ProcessHandle {
+    pid: ProcessId,
+    slot: usize,
+    thread: Option<JoinHandle>, // Taken by `wait`
+}
+
+impl ProcessHandle {
+    pub fn pid(&self) -> ProcessId {
+        self.pid
+    }
+
+    /// Blocks until the process exits
+    pub fn wait(mut self) -> ExitStatus {
+        if let Some(thread) = self.thread.take() {
+            thread.join();
+        }
+        // Removing it leaves nothing for the drop to do
+        let mut table = PROCESSES.lock();
+        let process = table.processes[self.slot]
+            .take_if(|process| process.pid == self.pid)
+            .expect("A process that isn't detached stays until waited for");
+        process.status.expect("The thread of the process exited")
+    }
+}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        let mut table = PROCESSES.lock();
+        if let Some(process) = table.processes[self.slot]
+            .as_mut()
+            .filter(|process| process.pid == self.pid)
+        {
+            if process.status.is_some() {
+                table.processes[self.slot] = None;
+            } else {
+                process.detached = true;
+            }
+        }
+    }
+}
+
+/// Starts a process running `image` in ring 3
+pub fn spawn(name: &'static str, image: &[u8]) -> Result<ProcessHandle, ProcessError> {
+    let address_space = load(image)?;
+    let (slot, pid) = {
+        let mut table = PROCESSES.lock();
+        let slot = table
+            .processes
+            .iter()
+            .position(Option::is_none)
+            .ok_or(ProcessError::NoFreeSlot)?;
+        let pid = ProcessId(table.next_pid);
+        table.next_pid += 1;
+        table.processes[slot] = Some(Process {
+            pid,
+            name,
+            thread: None,
+            address_space: Some(address_space),
+            status: None,
+            detached: false,
+        });
+        (slot, pid)
+    };
+    match thread::spawn_with_arg(name, Priority::Normal, process_main, slot) {
+        Ok(thread) => Ok(ProcessHandle {
+            pid,
+            slot,
+            thread: Some(thread),
+        }),
+        Err(err) => {
+            PROCESSES.lock().processes[slot] = None;
+            Err(ProcessError::Thread(err))
+        }
+    }
+}
+
+// RUNNING ///
+
+// First thing the thread of a process runs, in the kernel address space
+fn process_main(slot: usize) {
+    let p4 = {
+        let mut table = PROCESSES.lock();
+        let process = table.processes[slot]
+            .as_mut()
+            .expect("The process was removed before it ran");
+        process.thread = Some(thread::current_id());
+        process
+            .address_space
+            .as_ref()
+            .expect("The process exited before it ran")
+            .p4()
+    };
+    thread::set_address_space(Some(p4));
+    unsafe { enter_user(USER_CODE_ADDR, USER_STACK_TOP) }
+}
+
+// Goes to ring 3 by returning from an interrupt that never happened, with every register cleared so that
+// nothing of the kernel leaks
+unsafe fn enter_user(rip: usize, rsp: usize) -> ! {
+    unsafe {
+        asm!(
+            "cli",
+            "swapgs", // The per-CPU GS base waits in KERNEL_GS_BASE until the next entry
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            ss = in(reg) USER_DATA_SELECTOR as u64,
+            rsp = in(reg) rsp as u64,
+            rflags = in(reg) RFLAGS_IF,
+            cs = in(reg) USER_CODE_SELECTOR as u64,
+            rip = in(reg) rip as u64,
+            options(noreturn)
+        )
+    }
+}
+
+pub fn current_pid() -> Option<ProcessId> {
+    PROCESSES.lock().current().map(|process| process.pid)
+}
+
+/// Ends the current process, freeing its memory
+pub(crate) fn exit(status: ExitStatus) -> ! {
+    // The page tables can't be freed while they are in use
+    thread::set_address_space(None);
+    let address_space = {
+        let mut table = PROCESSES.lock();
+        let slot = table
+            .processes
+            .iter()
+            .position(|process| {
+                process
+                    .as_ref()
+                    .is_some_and(|process| process.thread == Some(thread::current_id()))
+            })
+            .expect("The current thread isn't a process");
+        let process = table.processes[slot].as_mut().expect("Found above");
+        let address_space = process.address_space.take();
+        if process.detached {
+            table.processes[slot] = None;
+        } else {
+            process.status = Some(status);
+        }
+        address_space
+    };
+    drop(address_space);
+    thread::exit()
+}
+
+/// Kills the current process for an exception it caused in ring 3, instead of panicking
+pub fn kill_current(frame: &TrapFrame) -> ! {
+    let vector = frame.vector as u8;
+    let (mnemonic, _) = exception_name(vector);
+    let (pid, name) = {
+        let mut table = PROCESSES.lock();
+        let process = table.current().expect("Only processes run in ring 3");
+        (process.pid, process.name)
+    };
+    if vector == PAGE_FAULT_VECTOR {
+        log::warn!(
+            "Process {} ({}) killed : {} at {:#x} accessing {:#x}",
+            pid,
+            name,
+            mnemonic,
+            frame.rip,
+            read_cr2()
+        );
+    } else {
+        log::warn!(
+            "Process {} ({}) killed : {} at {:#x}",
+            pid,
+            name,
+            mnemonic,
+            frame.rip
+        );
+    }
+    exit(ExitStatus::Killed { vector })
+}
+
+////////////////////////////////
+
+fn run(args: &[&str], out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    let name = *args.first().ok_or(CommandError::Usage)?;
+    let image = builtin_program(name)
+        .or_else(|| find_module(name))
+        .ok_or(CommandError::Failed("no such built-in program or module"))?;
+    let process = spawn("process", image).map_err(|err| {
+        let _ = writeln!(out, "{:?}", err);
+        CommandError::Failed("couldn't start the process")
+    })?;
+    let pid = process.pid();
+    let status = process.wait();
+    let _ = writeln!(out, "Process {} ({}) {}", pid, name, status);
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "run",
+    usage: "<program>",
+    help: "Runs a built-in program (hello, fault) or a module in ring 3 and waits for it",
+    run,
+});
@@ -0,0 +1,70 @@
+// Small programs built into the kernel to try processes without a module : flat position independent code,
+// run from its first byte.
+
+use core::{arch::global_asm, slice};
+
+global_asm!(
+    r#"
+.pushsection .rodata.user_programs, "a"
+
+// Says hello and exits with 0
+.global user_hello_start
+user_hello_start:
+    mov eax, {write}
+    lea rdi, [rip + 2f]
+    lea rsi, [rip + 3f]
+    sub rsi, rdi // The length
+    syscall
+    mov eax, {exit}
+    xor edi, edi
+    syscall
+    ud2
+2:
+    .ascii "Hello from ring 3\n"
+3:
+.global user_hello_end
+user_hello_end:
+
+// Reads kernel memory, the page fault kills it
+.global user_fault_start
+user_fault_start:
+    mov rax, qword ptr [0x100000]
+    mov eax, {exit}
+    xor edi, edi
+    syscall
+.global user_fault_end
+user_fault_end:
+
+.popsection
+"#,
+    write = const super::SYS_WRITE,
+    exit = const super::SYS_EXIT,
+);
+
+unsafe extern "C" {
+    static user_hello_start: u8;
+    static user_hello_end: u8;
+    static user_fault_start: u8;
+    static user_fault_end: u8;
+}
+
+fn program(start: *const u8, end: *const u8) -> &'static [u8] {
+    unsafe { slice::from_raw_parts(start, end as usize - start as usize) }
+}
+
+/// Names of the built-in programs
+pub const BUILTIN_PROGRAMS: [&str; 2] = ["hello", "fault"];
+
+pub fn builtin_program(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "hello" => Some(program(
+            &raw const user_hello_start,
+            &raw const user_hello_end,
+        )),
+        "fault" => Some(program(
+            &raw const user_fault_start,
+            &raw const user_fault_end,
+        )),
+        _ => None,
+    }
+}
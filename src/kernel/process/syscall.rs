@@ -0,0 +1,160 @@
+// System calls, through SYSCALL : the number in RAX, the arguments in RDI, RSI and RDX, the result back in RAX.
+// SYSCALL neither switches stacks nor saves anything but RIP (in RCX) and RFLAGS (in R11), the entry does it
+// with the per-CPU data, and SYSRET goes back.
+
+use core::{arch::global_asm, fmt::Write};
+
+use super::{ExitStatus, USER_END, USER_START, current_pid, exit};
+use crate::{
+    gdt::{KERNEL_CODE_SELECTOR, USER_DATA_SELECTOR},
+    paging::{PAGE_SIZE, PageTableFlags, leaf_entry},
+    shell::Terminal,
+    smp::{SYSCALL_STACK_OFFSET, USER_RSP_OFFSET},
+    thread,
+    x86::{self, Efer, Ia32Efer, Ia32Fmask, Ia32Lstar, Ia32Star, Msr, RFLAGS_TF, Star},
+};
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_YIELD: u64 = 2;
+pub const SYS_SLEEP: u64 = 3;
+pub const SYS_GETPID: u64 = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SyscallError {
+    NoSuchSyscall = 1,
+    BadAddress = 2,
+}
+
+// Interrupts (IF) stay disabled until the entry is on the kernel stack, DF is what the ABI expects
+const SYSCALL_CLEARED_FLAGS: usize = (1 << 9) | (1 << 10) | RFLAGS_TF as usize;
+
+global_asm!(
+    r#"
+.global syscall_entry
+syscall_entry:
+    swapgs
+    mov gs:[{user_rsp}], rsp
+    mov rsp, gs:[{syscall_stack}]
+    push qword ptr gs:[{user_rsp}]
+    push r11 // RFLAGS
+    push rcx // RIP
+    push rax
+    push rdi
+    push rsi
+    push rdx
+    push r10
+    push r8
+    push r9
+    mov rdi, rsp
+    // The user SSE registers are still there
+    sub rsp, 512
+    fxsave [rsp]
+    cld
+    call syscall_dispatch
+    fxrstor [rsp]
+    add rsp, 512
+    cli
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
+    pop rax
+    pop rcx
+    pop r11
+    pop rsp
+    swapgs
+    sysretq
+"#,
+    user_rsp = const USER_RSP_OFFSET,
+    syscall_stack = const SYSCALL_STACK_OFFSET,
+);
+
+unsafe extern "C" {
+    fn syscall_entry();
+}
+
+/// Registers of the process as pushed by `syscall_entry`, the result goes in `rax`
+#[derive(Debug)]
+#[repr(C)]
+pub struct SyscallFrame {
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+}
+
+/// Enables SYSCALL on the calling CPU
+pub fn init_syscalls() {
+    unsafe {
+        let efer = Ia32Efer::read().expect("No EFER");
+        Ia32Efer::write(efer | Efer::SYSCALL_ENABLE).expect("No EFER");
+        // The GDT puts the user segments in the order SYSRET expects
+        Ia32Star::write(Star {
+            syscall_cs: KERNEL_CODE_SELECTOR,
+            sysret_base: USER_DATA_SELECTOR - 8,
+        })
+        .expect("No SYSCALL");
+        Ia32Lstar::write(syscall_entry as *const () as usize).expect("No SYSCALL");
+        Ia32Fmask::write(SYSCALL_CLEARED_FLAGS).expect("No SYSCALL");
+    }
+}
+
+// Bytes of the process at `[addr, addr + len)`, if they are all mapped for ring 3
+fn user_bytes(addr: u64, len: u64) -> Result<&'static [u8], SyscallError> {
+    let (addr, len) = (addr as usize, len as usize);
+    let end = addr.checked_add(len).ok_or(SyscallError::BadAddress)?;
+    if addr < USER_START || end > USER_END {
+        return Err(SyscallError::BadAddress);
+    }
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < end {
+        let (entry, _) = leaf_entry(page).ok_or(SyscallError::BadAddress)?;
+        if !entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) {
+            return Err(SyscallError::BadAddress);
+        }
+        page += PAGE_SIZE;
+    }
+    Ok(unsafe { core::slice::from_raw_parts(addr as *const u8, len) })
+}
+
+fn write(addr: u64, len: u64) -> Result<u64, SyscallError> {
+    let bytes = user_bytes(addr, len)?;
+    for chunk in bytes.utf8_chunks() {
+        let _ = Terminal.write_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            let _ = Terminal.write_char(char::REPLACEMENT_CHARACTER);
+        }
+    }
+    Ok(len)
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    // The process can be preempted in the kernel too
+    x86::enable_interrupts();
+    let result = match frame.rax {
+        SYS_EXIT => exit(ExitStatus::Exited(frame.rdi as i64)),
+        SYS_WRITE => write(frame.rdi, frame.rsi),
+        SYS_YIELD => {
+            thread::yield_now();
+            Ok(0)
+        }
+        SYS_SLEEP => {
+            thread::sleep(frame.rdi);
+            Ok(0)
+        }
+        SYS_GETPID => Ok(current_pid().map_or(0, |pid| pid.0)),
+        _ => Err(SyscallError::NoSuchSyscall),
+    };
+    // Errors are negative
+    frame.rax = result.unwrap_or_else(|err| (err as i64).wrapping_neg() as u64);
+}
@@ -0,0 +1,381 @@
+// Commands of the shell that don't belong to any subsystem
+
+use core::fmt::Write;
+
+use multiboot2::MemoryAreaType;
+
+use super::{CommandError, FnCommand, commands, parse_number};
+use crate::{
+    MULTIBOOT2_INFO,
+    interrupts::interrupt_count,
+    io::{self, pci, pic, pit},
+    logger::{self, Sink},
+    paging::{self, PAGE_SIZE},
+    params, shell_command, x86,
+};
+
+const MAX_DUMP: u64 = 4096;
+
+fn arg(args: &[&str], i: usize) -> Result<u64, CommandError> {
+    parse_number(args.get(i).ok_or(CommandError::Usage)?)
+}
+
+fn port_arg(args: &[&str], i: usize) -> Result<u16, CommandError> {
+    u16::try_from(arg(args, i)?).map_err(|_| CommandError::InvalidNumber)
+}
+
+// HELP
+
+fn help(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    // Sorted by name without a buffer : each time the smallest one after the last printed, the index
+    // breaks ties
+    let mut last = None;
+    while let Some((index, command)) = commands()
+        .iter()
+        .enumerate()
+        .filter(|&(index, command)| last.is_none_or(|last| (command.name(), index) > last))
+        .min_by_key(|&(index, command)| (command.name(), index))
+    {
+        last = Some((command.name(), index));
+        let _ = writeln!(
+            out,
+            "{:<8} {:<20} {}",
+            command.name(),
+            command.usage(),
+            command.help()
+        );
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "help",
+    usage: "",
+    help: "Lists the commands",
+    run: help,
+});
+
+// MEMORY
+
+fn is_mapped(addr: u64, len: u64) -> bool {
+    let start = addr & !(PAGE_SIZE as u64 - 1);
+    (start..addr + len)
+        .step_by(PAGE_SIZE)
+        .all(|page| paging::leaf_entry(page as usize).is_some())
+}
+
+fn mem(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let addr = arg(args, 0)?;
+    let len = if args.len() > 1 { arg(args, 1)? } else { 64 }.min(MAX_DUMP);
+    if addr.checked_add(len).is_none() || !is_mapped(addr, len) {
+        return Err(CommandError::Failed("range isn't mapped"));
+    }
+
+    for line in (addr..addr + len).step_by(16) {
+        let bytes = (line..(line + 16).min(addr + len)).map(|addr| unsafe { *(addr as *const u8) });
+        let _ = write!(out, "{:016x}  ", line);
+        for (i, byte) in bytes.clone().enumerate() {
+            let _ = write!(out, "{:02x}{}", byte, if i == 7 { "  " } else { " " });
+        }
+        let printable = bytes.map(|byte| {
+            if byte.is_ascii_graphic() {
+                byte as char
+            } else {
+                '.'
+            }
+        });
+        let _ = write!(out, " |");
+        for c in printable {
+            let _ = write!(out, "{}", c);
+        }
+        let _ = writeln!(out, "|");
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "mem",
+    usage: "<addr> [len]",
+    help: "Dumps memory, only mapped pages are read",
+    run: mem,
+});
+
+fn memmap(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let memory_map = MULTIBOOT2_INFO
+        .get()
+        .and_then(|boot_info| boot_info.memory_map_tag())
+        .ok_or(CommandError::Failed("no memory map from the bootloader"))?;
+    for area in memory_map.memory_areas() {
+        let _ = writeln!(
+            out,
+            "{:#014x} - {:#014x}  {:>10} KiB  {:?}",
+            area.start_address(),
+            area.end_address(),
+            area.size() / 1024,
+            MemoryAreaType::from(area.typ())
+        );
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "memmap",
+    usage: "",
+    help: "Shows the memory map given by the bootloader",
+    run: memmap,
+});
+
+fn tags(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let boot_info = MULTIBOOT2_INFO
+        .get()
+        .ok_or(CommandError::Failed("no multiboot information"))?;
+    let _ = writeln!(out, "{:#?}", boot_info);
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "tags",
+    usage: "",
+    help: "Shows the Multiboot2 tags",
+    run: tags,
+});
+
+// PORTS AND MSRS
+
+fn port_in(args: &[&str], out: &mut dyn Write, width: u8) -> Result<(), CommandError> {
+    let port = port_arg(args, 0)?;
+    let value = unsafe {
+        match width {
+            8 => io::inb(port) as u32,
+            16 => io::inw(port) as u32,
+            _ => io::inl(port),
+        }
+    };
+    let _ = writeln!(out, "{:#06x} : {:#x}", port, value);
+    Ok(())
+}
+
+fn port_out(args: &[&str], width: u8) -> Result<(), CommandError> {
+    let port = port_arg(args, 0)?;
+    let value = arg(args, 1)?;
+    if value >> width != 0 {
+        return Err(CommandError::InvalidNumber);
+    }
+    unsafe {
+        match width {
+            8 => io::outb(port, value as u8),
+            16 => io::outw(port, value as u16),
+            _ => io::outl(port, value as u32),
+        }
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "inb",
+    usage: "<port>",
+    help: "Reads a byte from an I/O port",
+    run: |args, out| port_in(args, out, 8),
+});
+
+shell_command!(FnCommand {
+    name: "inw",
+    usage: "<port>",
+    help: "Reads a word from an I/O port",
+    run: |args, out| port_in(args, out, 16),
+});
+
+shell_command!(FnCommand {
+    name: "inl",
+    usage: "<port>",
+    help: "Reads a dword from an I/O port",
+    run: |args, out| port_in(args, out, 32),
+});
+
+shell_command!(FnCommand {
+    name: "outb",
+    usage: "<port> <value>",
+    help: "Writes a byte to an I/O port",
+    run: |args, _| port_out(args, 8),
+});
+
+shell_command!(FnCommand {
+    name: "outw",
+    usage: "<port> <value>",
+    help: "Writes a word to an I/O port",
+    run: |args, _| port_out(args, 16),
+});
+
+shell_command!(FnCommand {
+    name: "outl",
+    usage: "<port> <value>",
+    help: "Writes a dword to an I/O port",
+    run: |args, _| port_out(args, 32),
+});
+
+fn rdmsr(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let reg = arg(args, 0)? as usize;
+    let value = x86::rdmsr(reg).map_err(|_| CommandError::Failed("no such MSR"))?;
+    let _ = writeln!(out, "{:#x} : {:#018x}", reg, value);
+    Ok(())
+}
+
+fn wrmsr(args: &[&str], _out: &mut dyn Write) -> Result<(), CommandError> {
+    let reg = arg(args, 0)? as usize;
+    let value = arg(args, 1)? as usize;
+    unsafe { x86::wrmsr(reg, value) }.map_err(|_| CommandError::Failed("the CPU refused the write"))
+}
+
+shell_command!(FnCommand {
+    name: "rdmsr",
+    usage: "<msr>",
+    help: "Reads a model specific register",
+    run: rdmsr,
+});
+
+shell_command!(FnCommand {
+    name: "wrmsr",
+    usage: "<msr> <value>",
+    help: "Writes a model specific register",
+    run: wrmsr,
+});
+
+// DEVICES AND INTERRUPTS
+
+fn lspci(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    pci::for_each_device(|device| {
+        let address = device.address;
+        let _ = writeln!(
+            out,
+            "{:02x}:{:02x}.{}  {:04x}:{:04x}  {:02x}{:02x}{:02x}  {}",
+            address.bus,
+            address.device,
+            address.function,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if,
+            device.class_name().unwrap_or("Unknown device")
+        );
+    });
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "pci",
+    usage: "",
+    help: "Lists the PCI devices",
+    run: lspci,
+});
+
+fn irqs(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    for vector in 0..=255 {
+        let count = interrupt_count(vector);
+        if count == 0 {
+            continue;
+        }
+        let _ = write!(out, "{:#04x}", vector);
+        if let Some(irq) = vector.checked_sub(pic::vector(0)).filter(|irq| *irq < 16) {
+            let _ = write!(out, " (IRQ{})", irq);
+        }
+        let _ = writeln!(out, " : {}", count);
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "irqs",
+    usage: "",
+    help: "Shows how many times each interrupt was handled",
+    run: irqs,
+});
+
+// KERNEL
+
+fn uptime(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let ms = pit::uptime_ms();
+    let _ = writeln!(out, "{}.{:03}s", ms / 1000, ms % 1000);
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "uptime",
+    usage: "",
+    help: "Time since boot",
+    run: uptime,
+});
+
+shell_command!(FnCommand {
+    name: "params",
+    usage: "",
+    help: "Shows the kernel parameters",
+    run: |_, out| params::dump(out).map_err(|_| CommandError::Failed("write failed")),
+});
+
+shell_command!(FnCommand {
+    name: "dmesg",
+    usage: "",
+    help: "Shows the log ring",
+    run: |_, out| logger::dmesg(out).map_err(|_| CommandError::Failed("write failed")),
+});
+
+fn log_filter(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    match args {
+        [] => {
+            for sink in Sink::ALL {
+                let _ = writeln!(out, "{:<8} {}", sink.name(), logger::filter(sink));
+            }
+        }
+        [sink, filter] => {
+            let sinks = match *sink {
+                "all" => &Sink::ALL[..],
+                name => &[Sink::from_name(name).ok_or(CommandError::Usage)?][..],
+            };
+            for &sink in sinks {
+                logger::set_filter(sink, filter)
+                    .map_err(|_| CommandError::Failed("invalid filter"))?;
+            }
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "log",
+    usage: "[<sink|all> <filter>]",
+    help: "Shows or sets the log filters",
+    run: log_filter,
+});
+
+shell_command!(FnCommand {
+    name: "clear",
+    usage: "",
+    help: "Clears the screen",
+    run: |_, out| {
+        let _ = write!(out, "\x1b[2J\x1b[H");
+        Ok(())
+    },
+});
+
+fn panic(args: &[&str], _out: &mut dyn Write) -> Result<(), CommandError> {
+    match args.first() {
+        Some(message) => panic!("{}", message),
+        None => panic!("Panic requested from the shell"),
+    }
+}
+
+shell_command!(FnCommand {
+    name: "panic",
+    usage: "[message]",
+    help: "Panics the kernel",
+    run: panic,
+});
+
+shell_command!(FnCommand {
+    name: "reboot",
+    usage: "",
+    help: "Resets the machine",
+    run: |_, _| x86::reboot(),
+});
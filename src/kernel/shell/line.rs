@@ -0,0 +1,282 @@
+// Line editing with history, drawn with the VT100 sequences both terminals understand
+
+use core::fmt::Write;
+
+use super::{PROMPT, commands};
+
+// TODO : Strings and a Vec once there is an allocator
+const MAX_LINE: usize = 256;
+const HISTORY_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Ctrl+C, drops the line
+    Interrupt,
+    /// Ctrl+U
+    KillLine,
+    /// Ctrl+L
+    ClearScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    Ground,
+    Escape,
+    Csi(u8), // Numeric parameter so far
+}
+
+/// Turns the bytes of a serial terminal into inputs
+#[derive(Debug)]
+pub struct SerialDecoder {
+    state: DecoderState,
+}
+
+impl Default for SerialDecoder {
+    fn default() -> Self {
+        SerialDecoder {
+            state: DecoderState::Ground,
+        }
+    }
+}
+
+impl SerialDecoder {
+    pub fn feed(&mut self, byte: u8) -> Option<Input> {
+        match self.state {
+            DecoderState::Ground => match byte {
+                0x1B => {
+                    self.state = DecoderState::Escape;
+                    None
+                }
+                b'\r' | b'\n' => Some(Input::Enter),
+                0x08 | 0x7F => Some(Input::Backspace),
+                b'\t' => Some(Input::Tab),
+                0x01 => Some(Input::Home),
+                0x03 => Some(Input::Interrupt),
+                0x05 => Some(Input::End),
+                0x0C => Some(Input::ClearScreen),
+                0x15 => Some(Input::KillLine),
+                b' '..=b'~' => Some(Input::Char(byte as char)),
+                _ => None, // The line is ASCII only
+            },
+            DecoderState::Escape => {
+                // `ESC O x` is what some terminals send for the arrows
+                self.state = match byte {
+                    b'[' | b'O' => DecoderState::Csi(0),
+                    _ => DecoderState::Ground,
+                };
+                None
+            }
+            DecoderState::Csi(param) => {
+                if byte.is_ascii_digit() {
+                    self.state =
+                        DecoderState::Csi(param.saturating_mul(10).saturating_add(byte - b'0'));
+                    return None;
+                }
+                self.state = DecoderState::Ground;
+                match (byte, param) {
+                    (b'A', _) => Some(Input::Up),
+                    (b'B', _) => Some(Input::Down),
+                    (b'C', _) => Some(Input::Right),
+                    (b'D', _) => Some(Input::Left),
+                    (b'H', _) | (b'~', 1 | 7) => Some(Input::Home),
+                    (b'F', _) | (b'~', 4 | 8) => Some(Input::End),
+                    (b'~', 3) => Some(Input::Delete),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////
+
+#[derive(Clone, Copy)]
+struct Line {
+    bytes: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        bytes: [0; MAX_LINE],
+        len: 0,
+    };
+
+    fn as_str(&self) -> &str {
+        // Only ASCII is ever inserted
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+pub struct LineEditor {
+    line: Line,
+    cursor: usize,
+    history: [Line; HISTORY_SIZE],
+    history_len: usize,
+    history_next: usize, // Ring index of the next entry
+    browsing: usize,     // How far back in the history the line comes from, 0 for a new line
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor {
+            line: Line::EMPTY,
+            cursor: 0,
+            history: [Line::EMPTY; HISTORY_SIZE],
+            history_len: 0,
+            history_next: 0,
+            browsing: 0,
+        }
+    }
+
+    /// The line, once `feed` returned true
+    pub fn line(&self) -> &str {
+        self.line.as_str()
+    }
+
+    /// Starts a new line and prints the prompt
+    pub fn start(&mut self, out: &mut dyn Write) {
+        self.line = Line::EMPTY;
+        self.cursor = 0;
+        self.browsing = 0;
+        let _ = write!(out, "{}", PROMPT);
+    }
+
+    // Line from the history, 1 is the last one
+    fn history_entry(&self, back: usize) -> Line {
+        let index = (self.history_next + HISTORY_SIZE - back) % HISTORY_SIZE;
+        self.history[index]
+    }
+
+    fn push_history(&mut self) {
+        let is_repeat = self.history_len > 0 && self.history_entry(1).as_str() == self.line();
+        if self.line().trim().is_empty() || is_repeat {
+            return;
+        }
+        self.history[self.history_next] = self.line;
+        self.history_next = (self.history_next + 1) % HISTORY_SIZE;
+        self.history_len = (self.history_len + 1).min(HISTORY_SIZE);
+    }
+
+    fn browse(&mut self, back: usize) {
+        self.browsing = back;
+        self.line = if back == 0 {
+            Line::EMPTY
+        } else {
+            self.history_entry(back)
+        };
+        self.cursor = self.line.len;
+    }
+
+    fn insert(&mut self, c: char) {
+        if !c.is_ascii() || self.line.len == MAX_LINE {
+            return;
+        }
+        self.line
+            .bytes
+            .copy_within(self.cursor..self.line.len, self.cursor + 1);
+        self.line.bytes[self.cursor] = c as u8;
+        self.line.len += 1;
+        self.cursor += 1;
+    }
+
+    fn remove(&mut self, at: usize) {
+        self.line.bytes.copy_within(at + 1..self.line.len, at);
+        self.line.len -= 1;
+    }
+
+    // Completes the command name, or lists the candidates if there are several
+    fn complete(&mut self, out: &mut dyn Write) {
+        let prefix = self.line();
+        if prefix.contains(' ') {
+            return;
+        }
+        let mut candidates = commands()
+            .iter()
+            .filter(|command| command.name().starts_with(prefix));
+        match (candidates.next(), candidates.next()) {
+            (Some(command), None) => {
+                let rest = &command.name()[prefix.len()..];
+                rest.chars()
+                    .chain(core::iter::once(' '))
+                    .for_each(|c| self.insert(c));
+            }
+            (Some(_), Some(_)) => {
+                let _ = writeln!(out);
+                for command in commands()
+                    .iter()
+                    .filter(|command| command.name().starts_with(prefix))
+                {
+                    let _ = write!(out, "{}  ", command.name());
+                }
+                let _ = writeln!(out);
+            }
+            _ => {}
+        }
+    }
+
+    // Prompt and line from the start, with the cursor put back in place
+    fn redraw(&self, out: &mut dyn Write) {
+        let _ = write!(out, "\r{}{}\x1b[K", PROMPT, self.line());
+        let back = self.line.len - self.cursor;
+        if back > 0 {
+            let _ = write!(out, "\x1b[{}D", back);
+        }
+    }
+
+    /// Handles an input, returns true when the line is entered
+    pub fn feed(&mut self, input: Input, out: &mut dyn Write) -> bool {
+        match input {
+            Input::Enter => {
+                let _ = writeln!(out);
+                self.push_history();
+                return true;
+            }
+            Input::Interrupt => {
+                let _ = writeln!(out, "^C");
+                self.start(out);
+                return false;
+            }
+            Input::ClearScreen => {
+                let _ = write!(out, "\x1b[2J\x1b[H");
+            }
+            Input::Char(c) => self.insert(c),
+            Input::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.remove(self.cursor);
+            }
+            Input::Delete if self.cursor < self.line.len => self.remove(self.cursor),
+            Input::Tab => self.complete(out),
+            Input::Left => self.cursor = self.cursor.saturating_sub(1),
+            Input::Right => self.cursor = (self.cursor + 1).min(self.line.len),
+            Input::Home => self.cursor = 0,
+            Input::End => self.cursor = self.line.len,
+            Input::Up if self.browsing < self.history_len => self.browse(self.browsing + 1),
+            Input::Down if self.browsing > 0 => self.browse(self.browsing - 1),
+            Input::KillLine => {
+                self.line = Line::EMPTY;
+                self.cursor = 0;
+            }
+            _ => return false,
+        }
+        self.redraw(out);
+        false
+    }
+}
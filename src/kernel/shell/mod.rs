@@ -0,0 +1,241 @@
+// Kernel monitor on the serial console and the keyboard, started once the kernel is done booting.
+// Commands are declared with `shell_command!` next to the code they poke at, the declarations are
+// gathered in the `.shell_commands` section by the linker like kernel parameters.
+
+mod commands;
+mod line;
+
+pub use line::*;
+
+use core::fmt::{self, Write};
+
+use crate::{
+    framebuffer::{self, WRITER},
+    io::{
+        keyboard::{self, Key, Modifiers},
+        serial,
+    },
+    x86,
+};
+
+const PROMPT: &str = "abosbel> ";
+const MAX_ARGS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub enum CommandError {
+    /// Wrong arguments, the usage of the command is printed
+    Usage,
+    InvalidNumber,
+    Failed(&'static str),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Usage => write!(f, "wrong arguments"),
+            CommandError::InvalidNumber => write!(f, "invalid number"),
+            CommandError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+pub trait Command: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Arguments, e.g. `<addr> [len]`
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// One line description
+    fn help(&self) -> &'static str;
+
+    /// `args` doesn't hold the name of the command
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> Result<(), CommandError>;
+}
+
+/// Command made of a plain function, for the ones that don't need any state
+pub struct FnCommand {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: fn(&[&str], &mut dyn Write) -> Result<(), CommandError>,
+}
+
+impl Command for FnCommand {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+        (self.run)(args, out)
+    }
+}
+
+/// Registers a `&'static dyn Command` : `shell_command!(MyCommand);`
+#[macro_export]
+macro_rules! shell_command {
+    ($command:expr) => {
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".shell_commands")]
+            static COMMAND: &'static dyn $crate::shell::Command = &$command;
+        };
+    };
+}
+
+unsafe extern "C" {
+    // Defined by linker.ld, only their addresses matter
+    static __shell_commands_start: u8;
+    static __shell_commands_end: u8;
+}
+
+/// Every command registered in the kernel
+pub fn commands() -> &'static [&'static dyn Command] {
+    unsafe {
+        let start = (&raw const __shell_commands_start).cast::<&'static dyn Command>();
+        let end = (&raw const __shell_commands_end).cast::<&'static dyn Command>();
+        // The section only holds command references, placed one after the other
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+pub fn find_command(name: &str) -> Option<&'static dyn Command> {
+    commands()
+        .iter()
+        .find(|command| command.name() == name)
+        .copied()
+}
+
+/// Parses `0x` prefixed hexadecimal or decimal numbers
+pub fn parse_number(arg: &str) -> Result<u64, CommandError> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| CommandError::InvalidNumber)
+}
+
+/// Runs a command line, printing what went wrong
+pub fn execute(line: &str, out: &mut dyn Write) {
+    let mut args = [""; MAX_ARGS];
+    let mut count = 0;
+    for arg in line.split_whitespace() {
+        if count == MAX_ARGS {
+            let _ = writeln!(out, "More than {} arguments", MAX_ARGS);
+            return;
+        }
+        args[count] = arg;
+        count += 1;
+    }
+    let Some((&name, args)) = args[..count].split_first() else {
+        return;
+    };
+
+    let Some(command) = find_command(name) else {
+        let _ = writeln!(out, "{} : unknown command, try `help`", name);
+        return;
+    };
+    match command.run(args, out) {
+        Ok(()) => {}
+        Err(CommandError::Usage) => {
+            let _ = writeln!(out, "usage : {} {}", command.name(), command.usage());
+        }
+        Err(err) => {
+            let _ = writeln!(out, "{} : {}", command.name(), err);
+        }
+    }
+}
+
+////////////////////////////////
+
+/// Output of the shell, mirrored on the serial console and the framebuffer
+pub struct Terminal;
+
+impl Write for Terminal {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Raw serial terminals don't go back to the start of the line by themselves
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                serial::_serial_print(format_args!("\r\n"));
+            }
+            serial::_serial_print(format_args!("{}", part));
+        }
+        if WRITER.get().is_some() {
+            framebuffer::print_framebuffer(format_args!("{}", s));
+        }
+        Ok(())
+    }
+}
+
+// Next input from either side, the serial one needs decoding of its escape sequences
+fn next_input(serial_decoder: &mut SerialDecoder) -> Option<Input> {
+    if let Some(event) = keyboard::read_key() {
+        return Input::from_key(event.key, event.modifiers.contains(Modifiers::CTRL));
+    }
+    while let Some(byte) = serial::read_byte() {
+        if let Some(input) = serial_decoder.feed(byte) {
+            return Some(input);
+        }
+    }
+    None
+}
+
+/// Reads and executes commands forever
+pub fn run() -> ! {
+    let mut out = Terminal;
+    let mut editor = LineEditor::new();
+    let mut serial_decoder = SerialDecoder::default();
+
+    let _ = writeln!(out, "\nAbOSbel kernel monitor, `help` lists the commands");
+    loop {
+        editor.start(&mut out);
+        loop {
+            match next_input(&mut serial_decoder) {
+                Some(input) => {
+                    if editor.feed(input, &mut out) {
+                        break;
+                    }
+                }
+                // Woken up by the next keyboard, serial or timer interrupt
+                None => x86::halt(),
+            }
+        }
+        execute(editor.line(), &mut out);
+    }
+}
+
+impl Input {
+    fn from_key(key: Key, ctrl: bool) -> Option<Self> {
+        Some(match key {
+            Key::Char(c) if ctrl => match c.to_ascii_lowercase() {
+                'a' => Input::Home,
+                'c' => Input::Interrupt,
+                'e' => Input::End,
+                'l' => Input::ClearScreen,
+                'u' => Input::KillLine,
+                _ => return None,
+            },
+            Key::Char(c) => Input::Char(c),
+            Key::Enter => Input::Enter,
+            Key::Backspace => Input::Backspace,
+            Key::Delete => Input::Delete,
+            Key::Tab => Input::Tab,
+            Key::Left => Input::Left,
+            Key::Right => Input::Right,
+            Key::Up => Input::Up,
+            Key::Down => Input::Down,
+            Key::Home => Input::Home,
+            Key::End => Input::End,
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,130 @@
+// Data of each CPU, found through GS_BASE : it points to the `PerCpu` of the CPU, which starts with its own
+// address so that a single `gs:` load gives the whole structure. Variables declared with `percpu!` are found
+// through its `percpu_offset` (see percpu.rs).
+
+use core::{
+    arch::asm,
+    mem::offset_of,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
+
+use super::MAX_CPUS;
+use crate::x86::{Ia32GsBase, Ia32KernelGsBase, Msr};
+
+#[repr(C)]
+pub struct PerCpu {
+    this: *const PerCpu, // Must stay first
+    index: usize,
+    apic_id: AtomicU32,
+    percpu_offset: AtomicUsize,
+    syscall_stack: AtomicUsize, // Loaded by the SYSCALL entry, which doesn't switch stacks by itself
+    user_rsp: AtomicUsize,      // Where the SYSCALL entry keeps the user stack pointer meanwhile
+}
+
+/// Offsets of the fields the SYSCALL entry reaches through `gs:`
+pub const SYSCALL_STACK_OFFSET: usize = offset_of!(PerCpu, syscall_stack);
+pub const USER_RSP_OFFSET: usize = offset_of!(PerCpu, user_rsp);
+
+impl PerCpu {
+    const fn new() -> Self {
+        PerCpu {
+            this: core::ptr::null(),
+            index: 0,
+            apic_id: AtomicU32::new(0),
+            percpu_offset: AtomicUsize::new(0),
+            syscall_stack: AtomicUsize::new(0),
+            user_rsp: AtomicUsize::new(0),
+        }
+    }
+
+    /// 0 for the CPU the kernel booted on
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// ID of the local APIC, what IPIs are sent to
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_apic_id(&self, apic_id: u32) {
+        self.apic_id.store(apic_id, Ordering::Relaxed);
+    }
+
+    /// From the `percpu!` variables to the copy of this CPU, 0 for the first one which uses them in place
+    pub fn percpu_offset(&self) -> usize {
+        self.percpu_offset.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_percpu_offset(&self, offset: usize) {
+        self.percpu_offset.store(offset, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_syscall_stack(&self, rsp: usize) {
+        self.syscall_stack.store(rsp, Ordering::Relaxed);
+    }
+}
+
+// Only written by `init_cpu_data`, before the CPU uses it
+static mut PER_CPU: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+
+// GS_BASE is 0 until the first CPU sets it, everything before runs on that CPU
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Points GS_BASE of the calling CPU to its data, after its GDT is loaded since that resets it
+pub(super) fn init_cpu_data(cpu: usize) {
+    assert!(cpu < MAX_CPUS, "No data for CPU {}", cpu);
+    let data = unsafe { &raw mut PER_CPU[cpu] };
+    unsafe {
+        (*data).this = data;
+        (*data).index = cpu;
+        Ia32GsBase::write(data as usize).expect("No GS_BASE MSR");
+        // What `swapgs` gives back when entering ring 3, until a process sets its own
+        Ia32KernelGsBase::write(0).expect("No KERNEL_GS_BASE MSR");
+    }
+    READY.store(true, Ordering::Release);
+}
+
+/// Data of the calling CPU
+pub fn this_cpu() -> &'static PerCpu {
+    if !READY.load(Ordering::Acquire) {
+        return cpu_data(0);
+    }
+    let data: *const PerCpu;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{}]",
+            out(reg) data,
+            const offset_of!(PerCpu, this),
+            options(nostack, readonly, preserves_flags)
+        )
+    };
+    unsafe { &*data }
+}
+
+/// Index of the calling CPU, 0 for the one the kernel booted on
+pub fn cpu_index() -> usize {
+    this_cpu().index()
+}
+
+/// `percpu_offset` of the calling CPU, with a single `gs:` load
+pub(super) fn this_percpu_offset() -> usize {
+    if !READY.load(Ordering::Acquire) {
+        return 0;
+    }
+    let offset: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{}]",
+            out(reg) offset,
+            const offset_of!(PerCpu, percpu_offset),
+            options(nostack, readonly, preserves_flags)
+        )
+    };
+    offset
+}
+
+pub fn cpu_data(cpu: usize) -> &'static PerCpu {
+    let data = unsafe { &raw const PER_CPU[cpu] };
+    unsafe { &*data }
+}
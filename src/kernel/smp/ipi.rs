@@ -0,0 +1,170 @@
+// What the CPUs ask each other through interprocessor interrupts : running a function (TLB shootdowns are one)
+// and stopping on a panic, through an NMI since it gets through disabled interrupts.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use spin::{Mutex, MutexGuard};
+
+use super::{MAX_CPUS, cpu_count, cpu_data, cpu_index, is_online};
+use crate::{
+    interrupts::{ExceptionReport, GateOptions, IDT, NMI_VECTOR, TrapFrame},
+    io::apic::{self, IpiDestination},
+    paging::PAGE_SIZE,
+    percpu, x86,
+};
+
+/// Vector of the requests to run a function
+pub const CALL_FUNCTION_VECTOR: u8 = 0xF0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum IpiError {
+    Offline(usize),
+}
+
+// Function the CPU has to run, 0 when there is none
+percpu!(CALL_REQUEST: AtomicUsize = AtomicUsize::new(0));
+
+// One request at a time, its CPUs that haven't run it yet
+static CALL_LOCK: Mutex<()> = Mutex::new(());
+static CALL_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+// Set by the first CPU that panics
+static HALTING: AtomicBool = AtomicBool::new(false);
+
+fn run_call_request() {
+    let request = CALL_REQUEST.get().swap(0, Ordering::AcqRel);
+    if request != 0 {
+        let f: fn() = unsafe { core::mem::transmute(request) };
+        f();
+        CALL_PENDING.fetch_sub(1, Ordering::Release);
+    }
+}
+
+fn call_function_handler(_frame: &mut TrapFrame) {
+    run_call_request();
+    apic::end_of_interrupt();
+}
+
+fn nmi_handler(frame: &mut TrapFrame) {
+    if HALTING.load(Ordering::Acquire) {
+        crate::hlt_loop();
+    }
+    panic!("{}", ExceptionReport::new(frame));
+}
+
+/// Handlers of the IPIs, once the local APIC is initialized
+pub(super) fn init_ipis() {
+    let mut idt = IDT.lock();
+    idt.register(
+        CALL_FUNCTION_VECTOR,
+        call_function_handler,
+        GateOptions::new(),
+    )
+    .expect("Couldn't register the call function IPI handler");
+    idt.register(
+        NMI_VECTOR,
+        nmi_handler,
+        GateOptions::default_for(NMI_VECTOR),
+    )
+    .expect("Couldn't register the NMI handler");
+}
+
+// A CPU waiting for the lock may be one the holder waits for, so it runs its requests meanwhile
+fn lock_calls() -> MutexGuard<'static, ()> {
+    loop {
+        if let Some(guard) = CALL_LOCK.try_lock() {
+            return guard;
+        }
+        run_call_request();
+        spin_loop();
+    }
+}
+
+// Runs `f` on the online CPUs of `cpus` except the calling one, and waits for all of them
+fn call_locked(_lock: &MutexGuard<'static, ()>, cpus: impl Iterator<Item = usize>, f: fn()) {
+    let this = cpu_index();
+    for cpu in cpus.filter(|&cpu| cpu != this && is_online(cpu)) {
+        CALL_PENDING.fetch_add(1, Ordering::AcqRel);
+        CALL_REQUEST
+            .on_cpu(cpu)
+            .store(f as usize, Ordering::Release);
+        apic::send_ipi(
+            IpiDestination::Apic(cpu_data(cpu).apic_id()),
+            CALL_FUNCTION_VECTOR,
+        );
+    }
+    while CALL_PENDING.load(Ordering::Acquire) != 0 {
+        spin_loop();
+    }
+}
+
+/// Runs `f` on a CPU, in its interrupt handler, and waits until it's done
+pub fn call_on_cpu(cpu: usize, f: fn()) -> Result<(), IpiError> {
+    if cpu == cpu_index() {
+        x86::without_interrupts(f);
+        return Ok(());
+    }
+    if !is_online(cpu) {
+        return Err(IpiError::Offline(cpu));
+    }
+    let lock = lock_calls();
+    call_locked(&lock, core::iter::once(cpu), f);
+    Ok(())
+}
+
+/// Runs `f` on every other online CPU and waits until they are all done
+pub fn call_on_others(f: fn()) {
+    if cpu_count() <= 1 {
+        return;
+    }
+    let lock = lock_calls();
+    call_locked(&lock, 0..MAX_CPUS, f);
+}
+
+// TLB SHOOTDOWN ///
+
+// Range the other CPUs flush, only changed with the call lock held
+static SHOOTDOWN_START: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_END: AtomicUsize = AtomicUsize::new(0);
+
+// Past this many pages, the whole TLB is flushed instead
+const SHOOTDOWN_MAX_PAGES: usize = 32;
+
+fn flush_shootdown_range() {
+    let start = SHOOTDOWN_START.load(Ordering::Acquire);
+    let end = SHOOTDOWN_END.load(Ordering::Acquire);
+    if (end - start) / PAGE_SIZE > SHOOTDOWN_MAX_PAGES {
+        x86::flush_tlb_global();
+        return;
+    }
+    for page in (start..end).step_by(PAGE_SIZE) {
+        x86::invlpg(page);
+    }
+}
+
+/// Flushes `[addr, addr + size)` from the TLBs of the other CPUs after a page table change, the calling CPU
+/// flushes its own
+pub fn shootdown_tlb(addr: usize, size: usize) {
+    if cpu_count() <= 1 {
+        return;
+    }
+    let lock = lock_calls();
+    SHOOTDOWN_START.store(addr & !(PAGE_SIZE - 1), Ordering::Release);
+    SHOOTDOWN_END.store(addr + size, Ordering::Release);
+    call_locked(&lock, 0..MAX_CPUS, flush_shootdown_range);
+}
+
+// PANIC ///
+
+/// Stops the other CPUs for good, so that a panic is the last thing that happens
+pub fn halt_others() {
+    if HALTING.swap(true, Ordering::AcqRel) {
+        return; // Another CPU panicked first and already sent them
+    }
+    if apic::is_initialized() && cpu_count() > 1 {
+        apic::send_nmi(IpiDestination::AllButSelf);
+    }
+}
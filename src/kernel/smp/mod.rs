@@ -0,0 +1,164 @@
+// The other CPUs (application processors) : the MADT lists their local APICs, each one is started with
+// INIT then startup IPIs on the trampoline, and ends up in `ap_main` with its own GDT, TSS and stacks.
+// TODO : Run threads on them once the scheduler has a run queue per CPU, for now they only halt.
+
+mod cpu;
+mod ipi;
+mod percpu;
+mod trampoline;
+
+pub use cpu::*;
+pub use ipi::*;
+pub use percpu::*;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    acpi::Madt,
+    gdt,
+    interrupts::IDT,
+    io::apic,
+    shell::{CommandError, FnCommand},
+    shell_command, stacks, thread, x86,
+};
+
+pub const MAX_CPUS: usize = 8;
+
+// How long a CPU gets to answer a startup IPI, it's sent twice
+const STARTUP_TIMEOUT_MS: u64 = 100;
+
+static ONLINE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_online(cpu: usize) -> bool {
+    ONLINE
+        .get(cpu)
+        .is_some_and(|online| online.load(Ordering::Acquire))
+}
+
+pub fn cpu_count() -> usize {
+    (0..MAX_CPUS).filter(|&cpu| is_online(cpu)).count()
+}
+
+/// Whether the other CPUs have been started, or are being started. What is copied on each one from the first
+/// one is set before this.
+pub fn started() -> bool {
+    STARTED.load(Ordering::Acquire)
+}
+
+/// Per-CPU data of the first CPU, right after its GDT
+pub fn init_bsp() {
+    init_percpu_areas();
+    init_cpu_data(0);
+    ONLINE[0].store(true, Ordering::Release);
+}
+
+/// Starts every CPU the MADT lists, needs the timer and interrupts to wait for them
+pub fn start_aps() {
+    STARTED.store(true, Ordering::Release);
+    let madt = match Madt::get() {
+        Ok(madt) => madt,
+        Err(err) => {
+            log::warn!("Only one CPU : {}", err);
+            return;
+        }
+    };
+    if let Err(err) = apic::init_local_apic() {
+        log::warn!("Only one CPU : local APIC {:?}", err);
+        return;
+    }
+    let bsp_apic_id = apic::local_apic_id();
+    this_cpu().set_apic_id(bsp_apic_id);
+    init_ipis();
+    if let Err(err) = trampoline::install() {
+        log::warn!("Only one CPU : {}", err);
+        return;
+    }
+
+    let mut next = 1;
+    for processor in madt.processors() {
+        if !processor.enabled || processor.apic_id == bsp_apic_id {
+            continue;
+        }
+        if next == MAX_CPUS {
+            log::warn!("More than {} CPUs, ignoring the others", MAX_CPUS);
+            break;
+        }
+        // Startup IPIs only reach 8 bits IDs in xAPIC mode
+        if processor.apic_id > 0xFF {
+            log::warn!("CPU with APIC ID {} needs x2APIC", processor.apic_id);
+            continue;
+        }
+        if !start_cpu(next, processor.apic_id) {
+            // It may still start later, on the trampoline data of the next one
+            log::warn!(
+                "CPU with APIC ID {} didn't start, not starting others",
+                processor.apic_id
+            );
+            break;
+        }
+        next += 1;
+    }
+    log::info!("{} CPUs online", cpu_count());
+}
+
+// Intel SDM Vol. 3A 9.4.4.1 : INIT, 10ms, then up to two startup IPIs
+fn start_cpu(cpu: usize, apic_id: u32) -> bool {
+    trampoline::prepare(cpu, stacks::cpu_stack_top(cpu), ap_main);
+    cpu_data(cpu).set_apic_id(apic_id);
+    apic::send_init(apic_id);
+    thread::sleep(10);
+    for _ in 0..2 {
+        apic::send_startup(apic_id, (trampoline::TRAMPOLINE_ADDR / 4096) as u8);
+        for _ in 0..STARTUP_TIMEOUT_MS / 10 {
+            thread::sleep(10);
+            if is_online(cpu) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Where the trampoline jumps, interrupts are disabled
+extern "C" fn ap_main(cpu: usize) -> ! {
+    gdt::init_cpu(cpu);
+    init_cpu_data(cpu);
+    IDT.lock().load();
+    x86::init_fpu_ap();
+    crate::process::init();
+    let _ = x86::init_pat(); // Same error as on the first CPU
+    if let Err(err) = x86::sync_mtrrs() {
+        log::warn!("CPU {} : MTRRs not synced : {:?}", cpu, err);
+    }
+    if let Err(err) = apic::init_local_apic() {
+        log::warn!("CPU {} : local APIC {:?}", cpu, err);
+    }
+
+    log::info!("CPU {} online (APIC ID {})", cpu, this_cpu().apic_id());
+    ONLINE[cpu].store(true, Ordering::Release);
+
+    x86::enable_interrupts();
+    loop {
+        x86::halt();
+    }
+}
+
+////////////////////////////////
+
+fn cpus(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    for cpu in (0..MAX_CPUS).filter(|&cpu| is_online(cpu)) {
+        let _ = writeln!(out, "CPU {}  APIC ID {}", cpu, cpu_data(cpu).apic_id());
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "cpus",
+    usage: "",
+    help: "Lists the CPUs that are online",
+    run: cpus,
+});
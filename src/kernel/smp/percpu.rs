@@ -0,0 +1,92 @@
+// Variables with one instance per CPU, declared with `percpu!`. They are all put in the `.percpu` section,
+// which the first CPU uses in place. The other CPUs get a copy of it, taken by `init_bsp` before anything
+// changes them, and reach theirs by adding their `percpu_offset` (read through GS) to the address of the variable.
+
+use core::{cell::UnsafeCell, ptr};
+
+use super::{MAX_CPUS, cpu_data, this_percpu_offset};
+use crate::{paging::PAGE_SIZE, x86::without_interrupts};
+
+/// Declares a variable with one instance per CPU, all starting with the same value
+#[macro_export]
+macro_rules! percpu {
+    ($vis:vis $ident:ident : $ty:ty = $init:expr) => {
+        #[unsafe(link_section = ".percpu")]
+        $vis static $ident: $crate::smp::PerCpuVar<$ty> = $crate::smp::PerCpuVar::new($init);
+    };
+}
+
+/// Space for the copies of the `.percpu` section
+const PERCPU_AREA_SIZE: usize = 4 * PAGE_SIZE;
+
+#[repr(C, align(64))]
+struct PerCpuArea([u8; PERCPU_AREA_SIZE]);
+
+// The first CPU has the section itself
+// TODO : Allocate them once there is a frame allocator
+static mut PERCPU_AREAS: [PerCpuArea; MAX_CPUS - 1] =
+    [const { PerCpuArea([0; PERCPU_AREA_SIZE]) }; MAX_CPUS - 1];
+
+unsafe extern "C" {
+    // Defined by linker.ld, only their addresses matter
+    static __percpu_start: u8;
+    static __percpu_end: u8;
+}
+
+/// Copies the initial values of the variables for the other CPUs
+pub(super) fn init_percpu_areas() {
+    let start = &raw const __percpu_start;
+    let size = &raw const __percpu_end as usize - start as usize;
+    assert!(
+        size <= PERCPU_AREA_SIZE,
+        "The per-CPU variables take {} bytes, only {} fit",
+        size,
+        PERCPU_AREA_SIZE
+    );
+    for cpu in 1..MAX_CPUS {
+        let area = unsafe { &raw mut PERCPU_AREAS[cpu - 1] }.cast::<u8>();
+        unsafe { ptr::copy_nonoverlapping(start, area, size) };
+        cpu_data(cpu).set_percpu_offset(area as usize - start as usize);
+    }
+}
+
+/// A variable declared with `percpu!`. Each CPU only uses its own instance with `with`, the ones that are `Sync`
+/// (atomics for instance) can also be read by the others.
+#[repr(transparent)]
+pub struct PerCpuVar<T> {
+    value: UnsafeCell<T>,
+}
+
+// Instances that aren't `Sync` are only touched by their CPU, with interrupts disabled
+unsafe impl<T: Send> Sync for PerCpuVar<T> {}
+
+impl<T> PerCpuVar<T> {
+    pub const fn new(value: T) -> Self {
+        PerCpuVar {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn instance(&'static self, offset: usize) -> &'static T {
+        unsafe { &*self.value.get().byte_add(offset) }
+    }
+
+    /// Runs `f` on the instance of the calling CPU, with interrupts disabled so that nothing else on this CPU
+    /// uses it meanwhile. Changing it takes interior mutability (`Cell`, `RefCell`...).
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        without_interrupts(|| f(self.instance(this_percpu_offset())))
+    }
+}
+
+impl<T: Sync> PerCpuVar<T> {
+    /// Instance of the calling CPU
+    pub fn get(&'static self) -> &'static T {
+        self.instance(this_percpu_offset())
+    }
+
+    /// Instance of any CPU
+    pub fn on_cpu(&'static self, cpu: usize) -> &'static T {
+        assert!(cpu < MAX_CPUS, "No CPU {}", cpu);
+        self.instance(cpu_data(cpu).percpu_offset())
+    }
+}
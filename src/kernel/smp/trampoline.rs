@@ -0,0 +1,179 @@
+// Code the other CPUs start on : a startup IPI starts them in real mode at a page below 1MiB, so this is copied
+// there. It goes through protected mode to long mode with the page tables of the kernel, then calls `entry`
+// on its own stack. What changes for each CPU is in `TrampolineData`, at the end of the copy.
+
+use core::{arch::global_asm, mem::offset_of, ptr};
+
+use multiboot2::MemoryAreaType;
+
+use crate::{
+    MULTIBOOT2_INFO,
+    paging::PAGE_SIZE,
+    x86::{Efer, Ia32Efer, Msr, read_cr3},
+};
+
+/// Where the trampoline is copied, the startup IPI gives its page number
+pub const TRAMPOLINE_ADDR: usize = 0x8000;
+
+// Mirrors the data block at the end of the trampoline
+#[repr(C)]
+struct TrampolineData {
+    cr3: u32, // Loaded in protected mode, the page tables must be below 4GiB
+    efer: u32,
+    stack: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+// The code runs at TRAMPOLINE_ADDR rather than where it's linked, so every address is computed from there.
+// The temporary GDT has a 32 bits code segment (0x08), a data segment (0x10) and a 64 bits code segment (0x18).
+global_asm!(
+    r#"
+    .pushsection .rodata.ap_trampoline, "a"
+    .balign 16
+    .global ap_trampoline_start
+ap_trampoline_start:
+    .code16
+    cli
+    cld
+    xorw %ax, %ax
+    movw %ax, %ds
+    lgdtl (ap_gdt_pointer - ap_trampoline_start + {base})
+
+    /* protected mode */
+    movl %cr0, %eax
+    orl $1, %eax
+    movl %eax, %cr0
+    ljmpl $0x08, $(ap_trampoline_32 - ap_trampoline_start + {base})
+
+    .code32
+ap_trampoline_32:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+
+    /* PAE, and SSE which the compiler uses everywhere */
+    movl %cr4, %eax
+    orl $((1 << 5) | (1 << 9) | (1 << 10)), %eax
+    movl %eax, %cr4
+
+    movl (ap_trampoline_data + {cr3} - ap_trampoline_start + {base}), %eax
+    movl %eax, %cr3
+
+    /* long mode and the other EFER bits of the first CPU */
+    movl $0xC0000080, %ecx
+    movl (ap_trampoline_data + {efer} - ap_trampoline_start + {base}), %eax
+    xorl %edx, %edx
+    wrmsr
+
+    /* paging, and no x87 emulation */
+    movl %cr0, %eax
+    andl $~(1 << 2), %eax
+    orl $((1 << 31) | (1 << 1)), %eax
+    movl %eax, %cr0
+    ljmpl $0x18, $(ap_trampoline_64 - ap_trampoline_start + {base})
+
+    .code64
+ap_trampoline_64:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %fs
+    movw %ax, %gs
+    movw %ax, %ss
+
+    movq (ap_trampoline_data + {stack} - ap_trampoline_start + {base}), %rsp
+    movq (ap_trampoline_data + {cpu} - ap_trampoline_start + {base}), %rdi
+    /* ends the chain of frame pointers walked by backtraces */
+    xorl %ebp, %ebp
+    call *(ap_trampoline_data + {entry} - ap_trampoline_start + {base})
+    ud2
+
+    .balign 8
+ap_gdt:
+    .quad 0
+    .quad 0x00CF9A000000FFFF
+    .quad 0x00CF92000000FFFF
+    .quad 0x00AF9A000000FFFF
+ap_gdt_pointer:
+    .word ap_gdt_pointer - ap_gdt - 1
+    .long ap_gdt - ap_trampoline_start + {base}
+
+    .balign 8
+    .global ap_trampoline_data
+ap_trampoline_data:
+    .space {data_size}
+    .global ap_trampoline_end
+ap_trampoline_end:
+    .popsection
+    "#,
+    base = const TRAMPOLINE_ADDR,
+    cr3 = const offset_of!(TrampolineData, cr3),
+    efer = const offset_of!(TrampolineData, efer),
+    stack = const offset_of!(TrampolineData, stack),
+    entry = const offset_of!(TrampolineData, entry),
+    cpu = const offset_of!(TrampolineData, cpu),
+    data_size = const size_of::<TrampolineData>(),
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+fn trampoline_size() -> usize {
+    &raw const ap_trampoline_end as usize - &raw const ap_trampoline_start as usize
+}
+
+fn data() -> *mut TrampolineData {
+    let offset = &raw const ap_trampoline_data as usize - &raw const ap_trampoline_start as usize;
+    (TRAMPOLINE_ADDR + offset) as *mut TrampolineData
+}
+
+/// Copies the trampoline below 1MiB, if the memory map says nothing uses that memory
+pub(super) fn install() -> Result<(), &'static str> {
+    let size = trampoline_size();
+    assert!(size <= PAGE_SIZE, "The trampoline doesn't fit in a page");
+    let memory_map = MULTIBOOT2_INFO
+        .get()
+        .and_then(|boot_info| boot_info.memory_map_tag())
+        .ok_or("no memory map from the bootloader")?;
+    let (start, end) = (TRAMPOLINE_ADDR as u64, (TRAMPOLINE_ADDR + size) as u64);
+    if !memory_map.memory_areas().iter().any(|area| {
+        MemoryAreaType::from(area.typ()) == MemoryAreaType::Available
+            && area.start_address() <= start
+            && end <= area.end_address()
+    }) {
+        return Err("the trampoline page isn't free memory");
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(
+            &raw const ap_trampoline_start,
+            TRAMPOLINE_ADDR as *mut u8,
+            size,
+        )
+    };
+    Ok(())
+}
+
+/// Sets what the next CPU started gets : its index, its stack and the function it calls
+pub(super) fn prepare(cpu: usize, stack_top: usize, entry: extern "C" fn(usize) -> !) {
+    let efer = Ia32Efer::read().map_or(Efer::LONG_MODE_ENABLE, |efer| {
+        efer & (Efer::LONG_MODE_ENABLE | Efer::NO_EXECUTE_ENABLE | Efer::SYSCALL_ENABLE)
+    });
+    let cr3 = read_cr3();
+    assert!(cr3 <= u32::MAX as usize, "The page tables are above 4GiB");
+    unsafe {
+        data().write_volatile(TrampolineData {
+            cr3: cr3 as u32,
+            efer: efer.bits() as u32,
+            stack: stack_top as u64,
+            entry: entry as usize as u64,
+            cpu: cpu as u64,
+        })
+    };
+}
@@ -0,0 +1,134 @@
+// Stacks of the exceptions that can't trust the stack they interrupted, the CPU switches to them through
+// the IST of its TSS, stacks the other CPUs start on, and stacks of the kernel threads. Every stack, the boot
+// one included, sits above an unmapped guard page so that overflowing it faults instead of silently
+// overwriting whatever is below.
+
+use crate::{
+    paging::{self, PAGE_SIZE},
+    smp::MAX_CPUS,
+    thread::MAX_THREADS,
+};
+
+/// Index in the IST of the TSS (1-7), 0 keeps the current stack
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+
+const IST_COUNT: usize = 3;
+const IST_STACK_SIZE: usize = 8 * PAGE_SIZE;
+
+// The first CPU keeps the boot stack
+const CPU_STACK_COUNT: usize = MAX_CPUS - 1;
+const CPU_STACK_SIZE: usize = 16 * PAGE_SIZE;
+
+// The boot thread keeps the boot stack
+const THREAD_STACK_COUNT: usize = MAX_THREADS - 1;
+const THREAD_STACK_SIZE: usize = 16 * PAGE_SIZE;
+
+#[repr(C, align(4096))]
+struct GuardedStack<const SIZE: usize> {
+    guard: [u8; PAGE_SIZE],
+    stack: [u8; SIZE],
+}
+
+impl<const SIZE: usize> GuardedStack<SIZE> {
+    const fn new() -> Self {
+        GuardedStack {
+            guard: [0; PAGE_SIZE],
+            stack: [0; SIZE],
+        }
+    }
+}
+
+// TODO : Allocate them once there is a frame allocator
+static mut IST_STACKS: [[GuardedStack<IST_STACK_SIZE>; IST_COUNT]; MAX_CPUS] =
+    [const { [const { GuardedStack::new() }; IST_COUNT] }; MAX_CPUS];
+static mut CPU_STACKS: [GuardedStack<CPU_STACK_SIZE>; CPU_STACK_COUNT] =
+    [const { GuardedStack::new() }; CPU_STACK_COUNT];
+static mut THREAD_STACKS: [GuardedStack<THREAD_STACK_SIZE>; THREAD_STACK_COUNT] =
+    [const { GuardedStack::new() }; THREAD_STACK_COUNT];
+
+unsafe extern "C" {
+    // Page below the boot stack, defined by boot.s
+    static stack_guard: u8;
+}
+
+fn boot_stack_guard() -> usize {
+    &raw const stack_guard as usize
+}
+
+fn ist_stack(cpu: usize, ist: u8) -> *mut GuardedStack<IST_STACK_SIZE> {
+    assert!(cpu < MAX_CPUS, "No IST stacks for CPU {}", cpu);
+    assert!((1..=IST_COUNT as u8).contains(&ist), "No IST stack {}", ist);
+    unsafe { (&raw mut IST_STACKS[cpu][ist as usize - 1]).cast() }
+}
+
+fn cpu_stack(cpu: usize) -> *mut GuardedStack<CPU_STACK_SIZE> {
+    assert!(
+        (1..=CPU_STACK_COUNT).contains(&cpu),
+        "No stack for CPU {}",
+        cpu
+    );
+    unsafe { (&raw mut CPU_STACKS[cpu - 1]).cast() }
+}
+
+fn thread_stack(slot: usize) -> *mut GuardedStack<THREAD_STACK_SIZE> {
+    assert!(
+        (1..=THREAD_STACK_COUNT).contains(&slot),
+        "No stack for thread slot {}",
+        slot
+    );
+    unsafe { (&raw mut THREAD_STACKS[slot - 1]).cast() }
+}
+
+/// Top of the stack of an IST entry of a CPU, for its TSS
+pub fn ist_stack_top(cpu: usize, ist: u8) -> u64 {
+    let stack = ist_stack(cpu, ist);
+    (stack as usize + size_of::<GuardedStack<IST_STACK_SIZE>>()) as u64
+}
+
+/// Top of the stack a CPU starts on, the first one (CPU 0) has the boot stack
+pub fn cpu_stack_top(cpu: usize) -> usize {
+    cpu_stack(cpu) as usize + size_of::<GuardedStack<CPU_STACK_SIZE>>()
+}
+
+/// Top of the stack of a thread slot, the boot thread (slot 0) has none
+pub fn thread_stack_top(slot: usize) -> usize {
+    thread_stack(slot) as usize + size_of::<GuardedStack<THREAD_STACK_SIZE>>()
+}
+
+fn ist_guards() -> impl Iterator<Item = (usize, &'static str)> {
+    (0..MAX_CPUS).flat_map(|cpu| {
+        [
+            (DOUBLE_FAULT_IST, "double fault"),
+            (NMI_IST, "NMI"),
+            (MACHINE_CHECK_IST, "machine check"),
+        ]
+        .map(|(ist, name)| (ist_stack(cpu, ist) as usize, name))
+    })
+}
+
+// Guard pages and the name of their stack
+fn guards() -> impl Iterator<Item = (usize, &'static str)> {
+    ist_guards()
+        .chain((1..=CPU_STACK_COUNT).map(|cpu| (cpu_stack(cpu) as usize, "CPU boot")))
+        .chain((1..=THREAD_STACK_COUNT).map(|slot| (thread_stack(slot) as usize, "kernel thread")))
+        .chain([(boot_stack_guard(), "boot")])
+}
+
+/// Unmaps the guard pages
+pub fn init() {
+    for (guard, _) in guards() {
+        if let Err(err) = paging::unmap_guard_page(guard) {
+            log::warn!("No guard page at {:#x} : {:?}", guard, err);
+        }
+    }
+}
+
+/// Name of the stack whose guard page contains `addr`
+pub fn guarded_stack(addr: usize) -> Option<&'static str> {
+    let page = addr & !(PAGE_SIZE - 1);
+    guards()
+        .find(|&(guard, _)| guard == page)
+        .map(|(_, name)| name)
+}
@@ -0,0 +1,45 @@
+// Condition variable for the sleeping `Mutex`
+
+use super::{MutexGuard, WaitQueue};
+
+#[derive(Default)]
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex and sleeps until notified, then locks it again. It can wake up without being
+    /// notified, the condition must be checked in a loop (see `wait_while`).
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // Unlocked once queued, a notification sent right after the unlock isn't lost
+        self.waiters.wait_with(|| drop(guard));
+        mutex.lock()
+    }
+
+    /// Waits as long as `condition` is true
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
@@ -0,0 +1,243 @@
+// Lock debugging. Every lock belongs to a class (its name), and the classes taken while another one is
+// held give the order they must always be taken in : taking them in the opposite order later is reported
+// before it can deadlock. Sleeping locks taken with interrupts disabled are reported too, sleeping there
+// would let the interrupt handlers they were protecting against run.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    backtrace::Backtrace,
+    thread::{self, MAX_THREADS, ThreadId},
+    x86::{self, without_interrupts},
+};
+
+const MAX_LOCK_CLASSES: usize = 64;
+const MAX_HELD_LOCKS: usize = 16;
+const MAX_REPORTS: usize = 16;
+
+const NO_CLASS: u8 = u8::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Spin,     // Interrupts are disabled while it's held
+    Sleeping, // Waiting for it parks the thread
+}
+
+/// What a lock tells lockdep about itself, its class is found from its name on first use
+pub struct LockDep {
+    name: &'static str,
+    class: AtomicU8,
+}
+
+impl LockDep {
+    pub const fn new(name: &'static str) -> Self {
+        LockDep {
+            name,
+            class: AtomicU8::new(NO_CLASS),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Checks that the lock can be taken now and marks it held by the current thread
+    pub fn acquire(&self, kind: LockKind) {
+        if !is_enabled() {
+            return;
+        }
+        if kind == LockKind::Sleeping && thread::is_started() && !x86::interrupts_enabled() {
+            report(format_args!(
+                "sleeping lock `{}` taken with interrupts disabled",
+                self.name
+            ));
+        }
+        let Some(class) = self.class() else {
+            return;
+        };
+        let thread = thread::current();
+        let inversion = without_interrupts(|| {
+            let mut state = STATE.lock();
+            let held = state.held(thread.slot(), thread.id());
+            let mut inversion = None;
+            for &other in held.classes() {
+                if other == class {
+                    continue;
+                }
+                // `class` after `other` is fine unless `other` was already taken after `class`
+                if reachable(class, other) {
+                    inversion = Some(other);
+                } else {
+                    ORDER[other as usize].fetch_or(1 << class, Ordering::Relaxed);
+                }
+            }
+            held.push(class);
+            inversion
+        });
+        if let Some(other) = inversion {
+            let other = class_name(other);
+            report(format_args!(
+                "lock order inversion : `{}` taken while holding `{}`, but `{}` was taken while holding `{}` before",
+                self.name, other, other, self.name
+            ));
+        }
+    }
+
+    /// Marks the lock released by the current thread
+    pub fn release(&self) {
+        if !is_enabled() {
+            return;
+        }
+        let class = self.class.load(Ordering::Relaxed);
+        if class == NO_CLASS {
+            return;
+        }
+        let thread = thread::current();
+        without_interrupts(|| STATE.lock().held(thread.slot(), thread.id()).remove(class));
+    }
+
+    fn class(&self) -> Option<u8> {
+        let class = self.class.load(Ordering::Relaxed);
+        if class != NO_CLASS {
+            return Some(class);
+        }
+        let class = without_interrupts(|| STATE.lock().register(self.name))?;
+        self.class.store(class, Ordering::Relaxed);
+        Some(class)
+    }
+}
+
+// STATE
+
+#[derive(Clone, Copy)]
+struct HeldLocks {
+    owner: ThreadId,
+    classes: [u8; MAX_HELD_LOCKS],
+    len: usize,
+}
+
+impl HeldLocks {
+    fn classes(&self) -> &[u8] {
+        &self.classes[..self.len]
+    }
+
+    fn push(&mut self, class: u8) {
+        // Deeper nesting than that isn't checked
+        if self.len < MAX_HELD_LOCKS {
+            self.classes[self.len] = class;
+            self.len += 1;
+        }
+    }
+
+    // Locks aren't always released in the order they were taken
+    fn remove(&mut self, class: u8) {
+        if let Some(index) = self.classes().iter().rposition(|&held| held == class) {
+            self.classes.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        }
+    }
+}
+
+struct LockDepState {
+    names: [&'static str; MAX_LOCK_CLASSES],
+    class_count: usize,
+    held: [HeldLocks; MAX_THREADS],
+}
+
+impl LockDepState {
+    // Classes are shared by the locks with the same name
+    fn register(&mut self, name: &'static str) -> Option<u8> {
+        if let Some(class) = self.names[..self.class_count]
+            .iter()
+            .position(|&known| known == name)
+        {
+            return Some(class as u8);
+        }
+        if self.class_count == MAX_LOCK_CLASSES {
+            return None;
+        }
+        self.names[self.class_count] = name;
+        self.class_count += 1;
+        Some(self.class_count as u8 - 1)
+    }
+
+    // Held locks of a thread, forgotten when its slot gets a new thread
+    fn held(&mut self, slot: usize, owner: ThreadId) -> &mut HeldLocks {
+        let held = &mut self.held[slot];
+        if held.owner != owner {
+            held.owner = owner;
+            held.len = 0;
+        }
+        held
+    }
+}
+
+static STATE: Mutex<LockDepState> = Mutex::new(LockDepState {
+    names: [""; MAX_LOCK_CLASSES],
+    class_count: 0,
+    held: [HeldLocks {
+        owner: ThreadId(0),
+        classes: [NO_CLASS; MAX_HELD_LOCKS],
+        len: 0,
+    }; MAX_THREADS],
+});
+
+// Bit `b` of `ORDER[a]` : class `b` was taken while holding class `a`
+static ORDER: [AtomicU64; MAX_LOCK_CLASSES] = [const { AtomicU64::new(0) }; MAX_LOCK_CLASSES];
+
+// Whether `to` was taken, maybe indirectly, while holding `from`
+fn reachable(from: u8, to: u8) -> bool {
+    let mut seen = 1u64 << from;
+    let mut frontier = seen;
+    while frontier != 0 {
+        let mut next = 0;
+        for (class, order) in ORDER.iter().enumerate() {
+            if frontier & (1 << class) != 0 {
+                next |= order.load(Ordering::Relaxed);
+            }
+        }
+        if next & (1 << to) != 0 {
+            return true;
+        }
+        frontier = next & !seen;
+        seen |= next;
+    }
+    false
+}
+
+fn class_name(class: u8) -> &'static str {
+    without_interrupts(|| STATE.lock().names[class as usize])
+}
+
+// REPORTS
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+crate::kernel_param!(LOCKDEP: bool = cfg!(debug_assertions), "lockdep", "Check the order and interrupt state of the kernel locks");
+
+fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Applies the `lockdep` parameter, locks taken before aren't checked
+pub fn init() {
+    ENABLED.store(LOCKDEP.get(), Ordering::Relaxed);
+}
+
+// Only the first reports are printed, the same bug tends to come back on every lock
+fn report(args: core::fmt::Arguments) {
+    if REPORTS.fetch_add(1, Ordering::Relaxed) >= MAX_REPORTS {
+        return;
+    }
+    log::error!("lockdep : {}\n{}", args, Backtrace::current());
+}
+
+/// Reports a thread about to sleep with interrupts disabled, for the primitives that aren't locks
+pub fn check_sleep() {
+    if is_enabled() && thread::is_started() && !x86::interrupts_enabled() {
+        report(format_args!("sleeping with interrupts disabled"));
+    }
+}
@@ -0,0 +1,18 @@
+// Locks for threads. The sleeping ones (Mutex, RwLock, Condvar, Semaphore) park the waiting threads on a
+// `WaitQueue`, `SpinLock` is for what interrupt handlers share. `lockdep` checks how they are used.
+
+mod condvar;
+mod lockdep;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod spinlock;
+mod wait_queue;
+
+pub use condvar::*;
+pub use lockdep::*;
+pub use mutex::*;
+pub use rwlock::*;
+pub use semaphore::*;
+pub use spinlock::*;
+pub use wait_queue::*;
@@ -0,0 +1,147 @@
+// Reader-writer lock that parks its waiters. Readers can keep a writer waiting for as long as there are
+// new ones coming.
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{LockDep, LockKind, WaitQueue};
+
+// `state` is the number of readers, or WRITER
+const WRITER: usize = usize::MAX;
+
+pub struct RwLock<T: ?Sized> {
+    dep: LockDep,
+    state: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        RwLock {
+            dep: LockDep::new(name),
+            state: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn try_acquire_read(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state != WRITER
+            && state != WRITER - 1
+            && self
+                .state
+                .compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    fn try_acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Sleeps until there is no writer
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.dep.acquire(LockKind::Sleeping);
+        self.waiters.wait_until(|| self.try_acquire_read());
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Sleeps until there is no reader nor writer
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.dep.acquire(LockKind::Sleeping);
+        self.waiters.wait_until(|| self.try_acquire_write());
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        if !self.try_acquire_read() {
+            return None;
+        }
+        self.dep.acquire(LockKind::Sleeping);
+        Some(RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        if !self.try_acquire_write() {
+            return None;
+        }
+        self.dep.acquire(LockKind::Sleeping);
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn read_unlock(&self) {
+        self.dep.release();
+        // The last reader lets the writers in
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.waiters.wake_all();
+        }
+    }
+
+    fn write_unlock(&self) {
+        self.dep.release();
+        self.state.store(0, Ordering::Release);
+        // Every reader can go, or one of the writers
+        self.waiters.wake_all();
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
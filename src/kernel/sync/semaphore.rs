@@ -0,0 +1,44 @@
+// Counting semaphore. It's not owned by whoever acquired it, so lockdep doesn't track its order.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{WaitQueue, lockdep};
+
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a unit if there is one left
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Sleeps until a unit can be taken
+    pub fn acquire(&self) {
+        lockdep::check_sleep();
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    /// Gives back a unit, waking up a waiter
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
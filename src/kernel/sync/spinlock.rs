@@ -0,0 +1,97 @@
+// Spinlock that keeps interrupts disabled while it's held, for data shared with interrupt handlers :
+// an interrupt can't come and spin forever on a lock the code it interrupted holds.
+
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+use super::{LockDep, LockKind};
+use crate::x86;
+
+pub struct SpinLock<T: ?Sized> {
+    dep: LockDep,
+    inner: spin::Mutex<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        SpinLock {
+            dep: LockDep::new(name),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let interrupts_were_enabled = x86::interrupts_enabled();
+        x86::disable_interrupts();
+        self.dep.acquire(LockKind::Spin);
+        SpinLockGuard {
+            lock: self,
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_were_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let interrupts_were_enabled = x86::interrupts_enabled();
+        x86::disable_interrupts();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                self.dep.acquire(LockKind::Spin);
+                Some(SpinLockGuard {
+                    lock: self,
+                    guard: ManuallyDrop::new(guard),
+                    interrupts_were_enabled,
+                })
+            }
+            None => {
+                if interrupts_were_enabled {
+                    x86::enable_interrupts();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlocked before interrupts come back
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.lock.dep.release();
+        if self.interrupts_were_enabled {
+            x86::enable_interrupts();
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Threads waiting for something, what every sleeping primitive is built on. A waiter checks its condition and
+// queues itself with the queue locked, and wakers take the same lock to dequeue, so a wake up can't come in
+// between, even from another CPU.
+
+use core::hint;
+
+use spin::Mutex;
+
+use super::lockdep;
+use crate::{
+    thread::{self, MAX_THREADS, ThreadRef},
+    x86::without_interrupts,
+};
+
+// A thread waits on one queue at a time, so a queue never has more waiters than there are threads
+struct Waiters {
+    threads: [Option<ThreadRef>; MAX_THREADS],
+    read: usize,
+    len: usize,
+}
+
+impl Waiters {
+    fn push(&mut self, thread: ThreadRef) {
+        assert!(self.len < MAX_THREADS, "Wait queue full");
+        self.threads[(self.read + self.len) % MAX_THREADS] = Some(thread);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<ThreadRef> {
+        if self.len == 0 {
+            return None;
+        }
+        let thread = self.threads[self.read].take();
+        self.read = (self.read + 1) % MAX_THREADS;
+        self.len -= 1;
+        thread
+    }
+}
+
+/// Threads sleeping until another one wakes them up, first come first woken
+pub struct WaitQueue {
+    waiters: Mutex<Waiters>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: Mutex::new(Waiters {
+                threads: [None; MAX_THREADS],
+                read: 0,
+                len: 0,
+            }),
+        }
+    }
+
+    /// Sleeps until `condition` is true, it's checked again after every wake up. `condition` runs with
+    /// interrupts disabled. The caller checks that it can sleep, see `lockdep`.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let done = without_interrupts(|| {
+                // Before the threads there is no one to switch to, just spin
+                if !thread::is_started() {
+                    return condition();
+                }
+                self.sleep_unless(&mut condition)
+            });
+            if done {
+                return;
+            }
+            if !thread::is_started() {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// Queues the current thread, runs `before_sleep` and sleeps until woken up once. It can't miss
+    /// a wake up caused by `before_sleep`, which is how a condition variable releases its mutex.
+    pub fn wait_with(&self, before_sleep: impl FnOnce()) {
+        lockdep::check_sleep();
+        without_interrupts(|| {
+            if !thread::is_started() {
+                before_sleep();
+                return;
+            }
+            thread::block(|| {
+                self.waiters.lock().push(thread::current());
+                before_sleep();
+            });
+        });
+    }
+
+    // Sleeps once unless `condition` is true, checked with the queue locked. Interrupts must be disabled.
+    fn sleep_unless(&self, condition: &mut impl FnMut() -> bool) -> bool {
+        let mut waiters = self.waiters.lock();
+        if condition() {
+            return true;
+        }
+        thread::block(|| {
+            waiters.push(thread::current());
+            drop(waiters);
+        });
+        false
+    }
+
+    /// Wakes up the thread that has been waiting the longest, false if there was none
+    pub fn wake_one(&self) -> bool {
+        let thread = without_interrupts(|| self.waiters.lock().pop());
+        match thread {
+            Some(thread) => {
+                thread::unblock(thread);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes up every waiting thread, returns how many there were
+    pub fn wake_all(&self) -> usize {
+        let mut count = 0;
+        while self.wake_one() {
+            count += 1;
+        }
+        count
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
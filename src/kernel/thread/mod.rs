@@ -0,0 +1,359 @@
+// Kernel threads : each one has its own stack and FPU state, the timer preempts them (see scheduler.rs).
+// Without an allocator there is a fixed number of slots, and a thread runs a plain `fn()`.
+
+mod scheduler;
+mod switch;
+
+pub use scheduler::MAX_THREADS;
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use scheduler::{Entry, SCHEDULER, schedule};
+
+use crate::{
+    io::pit,
+    shell::{CommandError, FnCommand},
+    shell_command,
+    x86::{self, without_interrupts},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(pub u64);
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Ready threads of a higher priority always run first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Idle, // Only for the idle thread
+    Low,
+    Normal,
+    High,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Priority::Idle => "idle",
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Sleeping { until: u64 }, // Timer tick
+    Blocked,
+    Exited,
+}
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThreadState::Ready => write!(f, "ready"),
+            ThreadState::Running => write!(f, "running"),
+            ThreadState::Sleeping { until } => {
+                let left = until.saturating_sub(pit::ticks()) * 1000 / pit::PIT_FREQUENCY;
+                write!(f, "sleeping ({} ms left)", left)
+            }
+            ThreadState::Blocked => write!(f, "blocked"),
+            ThreadState::Exited => write!(f, "exited"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ThreadError {
+    NoFreeSlot,
+}
+
+// Set once the boot code is a thread, before that there is nothing to switch to
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn is_started() -> bool {
+    STARTED.load(Ordering::Acquire)
+}
+
+// Runs when nothing else is ready
+fn idle() {
+    loop {
+        x86::halt();
+    }
+}
+
+/// Turns the boot code into the `main` thread and starts the idle thread, needs the FPU
+pub fn init() {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.adopt_boot_thread("main");
+        scheduler
+            .add("idle", Priority::Idle, Entry::Plain(idle))
+            .expect("Couldn't create the idle thread");
+    });
+    STARTED.store(true, Ordering::Release);
+}
+
+// API
+
+/// Waits for a thread to exit, dropping it instead detaches the thread
+pub struct JoinHandle {
+    id: ThreadId,
+    slot: usize,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Blocks until the thread exits
+    pub fn join(self) {
+        loop {
+            let exited = without_interrupts(|| {
+                let mut scheduler = SCHEDULER.lock();
+                let current = scheduler.current_slot();
+                let Some(thread) = scheduler.find(self.slot, self.id) else {
+                    return true;
+                };
+                if thread.state == ThreadState::Exited {
+                    scheduler.remove(self.slot);
+                    return true;
+                }
+                thread.joiner = Some(current);
+                scheduler.current().state = ThreadState::Blocked;
+                drop(scheduler);
+                schedule();
+                false
+            });
+            if exited {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        // Nothing to do if it was joined, its slot is free or someone else's
+        without_interrupts(|| {
+            if let Some(thread) = SCHEDULER.lock().find(self.slot, self.id) {
+                thread.detached = true;
+            }
+        });
+    }
+}
+
+/// Starts a thread running `entry`, it exits when `entry` returns
+pub fn spawn(
+    name: &'static str,
+    priority: Priority,
+    entry: fn(),
+) -> Result<JoinHandle, ThreadError> {
+    spawn_entry(name, priority, Entry::Plain(entry))
+}
+
+/// Same as `spawn`, with an argument for `entry`
+pub fn spawn_with_arg(
+    name: &'static str,
+    priority: Priority,
+    entry: fn(usize),
+    arg: usize,
+) -> Result<JoinHandle, ThreadError> {
+    spawn_entry(name, priority, Entry::WithArg(entry, arg))
+}
+
+fn spawn_entry(
+    name: &'static str,
+    priority: Priority,
+    entry: Entry,
+) -> Result<JoinHandle, ThreadError> {
+    assert!(is_started(), "Threads aren't initialized");
+    let (slot, id, preempt) = without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let (slot, id) = scheduler.add(name, priority, entry)?;
+        let preempt = priority > scheduler.current().priority;
+        Ok((slot, id, preempt))
+    })?;
+    if preempt {
+        yield_now();
+    }
+    Ok(JoinHandle { id, slot })
+}
+
+pub fn current_id() -> ThreadId {
+    current().id
+}
+
+/// A thread that can be woken up, what wait queues keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadRef {
+    slot: usize,
+    id: ThreadId,
+}
+
+impl ThreadRef {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Index of its slot, below `MAX_THREADS`
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+}
+
+pub fn current() -> ThreadRef {
+    if !is_started() {
+        return ThreadRef {
+            slot: 0,
+            id: ThreadId(0),
+        };
+    }
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let slot = scheduler.current_slot();
+        ThreadRef {
+            slot,
+            id: scheduler.current().id,
+        }
+    })
+}
+
+/// Puts the current thread to sleep until `unblock`. `publish` makes it known to whoever unblocks it, the
+/// thread is already blocked by then so that a wake up from another CPU can't come first and be lost.
+/// Interrupts must be disabled.
+pub(crate) fn block(publish: impl FnOnce()) {
+    SCHEDULER.lock().current().state = ThreadState::Blocked;
+    publish();
+    schedule();
+}
+
+/// Makes a thread blocked by `block` ready again, does nothing if it isn't blocked anymore
+pub(crate) fn unblock(thread: ThreadRef) {
+    without_interrupts(|| {
+        if let Some(thread) = SCHEDULER.lock().find(thread.slot, thread.id)
+            && thread.state == ThreadState::Blocked
+        {
+            thread.state = ThreadState::Ready;
+        }
+    });
+}
+
+/// Switches the current thread to the address space whose P4 table is at `p4`, None for the kernel one
+pub(crate) fn set_address_space(p4: Option<usize>) {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let address_space = p4.unwrap_or(scheduler.kernel_address_space());
+        scheduler.current().address_space = address_space;
+        unsafe { x86::write_cr3(address_space) };
+    });
+}
+
+/// Lets the other ready threads of the same priority run
+pub fn yield_now() {
+    if is_started() {
+        without_interrupts(schedule);
+    }
+}
+
+/// Sleeps for at least `ms` milliseconds, rounded up to the timer resolution. It can come from ring 3, a
+/// duration too long to count is forever.
+pub fn sleep(ms: u64) {
+    let ticks = ms.saturating_mul(pit::PIT_FREQUENCY).div_ceil(1000);
+    let until = pit::ticks().saturating_add(ticks);
+    if !is_started() {
+        while pit::ticks() < until {
+            x86::halt();
+        }
+        return;
+    }
+    without_interrupts(|| {
+        SCHEDULER.lock().current().state = ThreadState::Sleeping { until };
+        schedule();
+    });
+}
+
+/// Ends the current thread, what `entry` returning does
+pub fn exit() -> ! {
+    x86::disable_interrupts();
+    {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current();
+        assert!(current.entry.is_some(), "The boot thread can't exit");
+        current.state = ThreadState::Exited;
+        if let Some(joiner) = current.joiner.take()
+            && let Some(joiner) = scheduler.thread(joiner)
+            && joiner.state == ThreadState::Blocked
+        {
+            joiner.state = ThreadState::Ready;
+        }
+    }
+    schedule();
+    unreachable!("An exited thread was resumed");
+}
+
+/// Called by the timer interrupt after its EOI, may switch to another thread
+pub fn timer_tick() {
+    if !is_started() {
+        return;
+    }
+    let preempt = SCHEDULER.lock().tick(pit::ticks());
+    if preempt {
+        schedule();
+    }
+}
+
+////////////////////////////////
+
+fn threads(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    // Copied first, printing with interrupts disabled would hold up the timer
+    let mut list = [None; MAX_THREADS];
+    without_interrupts(|| {
+        let scheduler = SCHEDULER.lock();
+        for (entry, thread) in list.iter_mut().zip(scheduler.threads()) {
+            *entry = Some((
+                thread.id,
+                thread.name,
+                thread.priority,
+                thread.state,
+                thread.ticks,
+            ));
+        }
+    });
+
+    let _ = writeln!(
+        out,
+        "{:>4}  {:<12} {:<8} {:>10}  STATE",
+        "ID", "NAME", "PRIORITY", "CPU"
+    );
+    for (id, name, priority, state, ticks) in list.into_iter().flatten() {
+        let _ = writeln!(
+            out,
+            "{:>4}  {:<12} {:<8} {:>7} ms  {}",
+            id.0,
+            name,
+            priority,
+            ticks * 1000 / pit::PIT_FREQUENCY,
+            state
+        );
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "threads",
+    usage: "",
+    help: "Lists the kernel threads and their state",
+    run: threads,
+});
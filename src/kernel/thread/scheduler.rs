@@ -0,0 +1,269 @@
+// Round-robin scheduler with priorities : the highest priority ready thread runs, threads of the same
+// priority take turns every `TIME_SLICE_TICKS` timer ticks. The scheduler is only touched with interrupts
+// disabled, which is enough while there's a single CPU.
+
+use spin::Mutex;
+
+use super::{
+    Priority, ThreadError, ThreadId, ThreadState,
+    switch::{init_stack, switch_context},
+};
+use crate::{
+    gdt, stacks,
+    x86::{FpuState, read_cr3, write_cr3},
+};
+
+pub const MAX_THREADS: usize = 16;
+
+const BOOT_THREAD_SLOT: usize = 0;
+const TIME_SLICE_TICKS: u64 = 2; // 20ms at 100Hz
+
+/// What a thread runs
+#[derive(Clone, Copy)]
+pub(super) enum Entry {
+    Plain(fn()),
+    WithArg(fn(usize), usize),
+}
+
+pub(super) struct Thread {
+    pub(super) id: ThreadId,
+    pub(super) name: &'static str,
+    pub(super) priority: Priority,
+    pub(super) state: ThreadState,
+    pub(super) entry: Option<Entry>,
+    pub(super) address_space: usize, // Physical address of its P4 table, loaded in CR3 when it runs
+    pub(super) joiner: Option<usize>, // Slot of the thread waiting for this one to exit
+    pub(super) detached: bool,       // Nobody will join it, its slot is free once it exits
+    pub(super) ticks: u64,           // Timer ticks it was running for
+    rsp: usize,                      // Saved by `switch_context` while it isn't running
+    fpu: FpuState,
+}
+
+impl Thread {
+    fn new(
+        id: ThreadId,
+        name: &'static str,
+        priority: Priority,
+        state: ThreadState,
+        address_space: usize,
+    ) -> Self {
+        Thread {
+            id,
+            name,
+            priority,
+            state,
+            entry: None,
+            address_space,
+            joiner: None,
+            detached: false,
+            ticks: 0,
+            rsp: 0,
+            fpu: FpuState::new(),
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.state == ThreadState::Ready
+    }
+}
+
+pub(super) struct Scheduler {
+    threads: [Option<Thread>; MAX_THREADS],
+    current: usize,
+    slice_left: u64,
+    next_id: u64,
+    kernel_address_space: usize, // The one of the boot thread, every kernel thread shares it
+}
+
+pub(super) static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+
+impl Scheduler {
+    const fn new() -> Self {
+        Scheduler {
+            threads: [const { None }; MAX_THREADS],
+            current: BOOT_THREAD_SLOT,
+            slice_left: TIME_SLICE_TICKS,
+            next_id: 0,
+            kernel_address_space: 0,
+        }
+    }
+
+    fn next_id(&mut self) -> ThreadId {
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Makes the code that booted the kernel the first thread, it keeps the boot stack
+    pub(super) fn adopt_boot_thread(&mut self, name: &'static str) {
+        let id = self.next_id();
+        self.kernel_address_space = read_cr3();
+        self.threads[BOOT_THREAD_SLOT] = Some(Thread::new(
+            id,
+            name,
+            Priority::Normal,
+            ThreadState::Running,
+            self.kernel_address_space,
+        ));
+        self.current = BOOT_THREAD_SLOT;
+    }
+
+    /// Adds a ready thread that will run `entry`, returns its slot
+    pub(super) fn add(
+        &mut self,
+        name: &'static str,
+        priority: Priority,
+        entry: Entry,
+    ) -> Result<(usize, ThreadId), ThreadError> {
+        let current = self.current;
+        // A detached thread that exited can't be using its stack anymore, unless it's the current one
+        let slot = (0..MAX_THREADS)
+            .filter(|&slot| slot != BOOT_THREAD_SLOT && slot != current)
+            .find(|&slot| match &self.threads[slot] {
+                None => true,
+                Some(thread) => thread.state == ThreadState::Exited && thread.detached,
+            })
+            .ok_or(ThreadError::NoFreeSlot)?;
+
+        let id = self.next_id();
+        let mut thread = Thread::new(
+            id,
+            name,
+            priority,
+            ThreadState::Ready,
+            self.kernel_address_space,
+        );
+        thread.entry = Some(entry);
+        thread.rsp = unsafe { init_stack(stacks::thread_stack_top(slot), thread_start) };
+        self.threads[slot] = Some(thread);
+        Ok((slot, id))
+    }
+
+    /// Forgets an exited thread
+    pub(super) fn remove(&mut self, slot: usize) {
+        self.threads[slot] = None;
+    }
+
+    pub(super) fn current_slot(&self) -> usize {
+        self.current
+    }
+
+    pub(super) fn current(&mut self) -> &mut Thread {
+        self.threads[self.current]
+            .as_mut()
+            .expect("The current thread has no slot")
+    }
+
+    pub(super) fn kernel_address_space(&self) -> usize {
+        self.kernel_address_space
+    }
+
+    pub(super) fn thread(&mut self, slot: usize) -> Option<&mut Thread> {
+        self.threads.get_mut(slot)?.as_mut()
+    }
+
+    /// The thread with `id` if it's still there
+    pub(super) fn find(&mut self, slot: usize, id: ThreadId) -> Option<&mut Thread> {
+        self.thread(slot).filter(|thread| thread.id == id)
+    }
+
+    pub(super) fn threads(&self) -> impl Iterator<Item = &Thread> {
+        self.threads.iter().flatten()
+    }
+
+    // The ready thread with the highest priority, the first one after the current thread on a tie so
+    // that they take turns
+    fn next_ready(&self) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        for i in 1..=MAX_THREADS {
+            let slot = (self.current + i) % MAX_THREADS;
+            let Some(thread) = self.threads[slot]
+                .as_ref()
+                .filter(|thread| thread.is_ready())
+            else {
+                continue;
+            };
+            let better = match best.and_then(|best| self.threads[best].as_ref()) {
+                Some(best) => thread.priority > best.priority,
+                None => true,
+            };
+            if better {
+                best = Some(slot);
+            }
+        }
+        best
+    }
+
+    /// Chooses the thread to run next and switches the FPU state, the address space and the stack interrupts
+    /// from ring 3 use to it. Returns where to save the stack pointer of the current thread and the one to
+    /// load, None if the current thread keeps running.
+    fn pick_next(&mut self) -> Option<(*mut usize, usize)> {
+        let current = self.current;
+        let thread = self.current();
+        if thread.state == ThreadState::Running {
+            thread.state = ThreadState::Ready;
+        }
+        let next = self.next_ready().expect("The idle thread is always ready");
+        self.slice_left = TIME_SLICE_TICKS;
+        self.threads[next].as_mut()?.state = ThreadState::Running;
+        if next == current {
+            return None;
+        }
+
+        let old = self.threads[current].as_mut()?;
+        old.fpu.save();
+        let old_rsp = &raw mut old.rsp;
+        let old_address_space = old.address_space;
+        let new = self.threads[next].as_mut()?;
+        new.fpu.restore();
+        if new.address_space != old_address_space {
+            unsafe { write_cr3(new.address_space) };
+        }
+        // The boot thread never goes to ring 3
+        if next != BOOT_THREAD_SLOT {
+            gdt::set_kernel_stack(stacks::thread_stack_top(next) as u64);
+        }
+        self.current = next;
+        Some((old_rsp, new.rsp))
+    }
+
+    /// Accounts a timer tick, returns whether the current thread should be preempted
+    pub(super) fn tick(&mut self, now: u64) -> bool {
+        for thread in self.threads.iter_mut().flatten() {
+            if let ThreadState::Sleeping { until } = thread.state
+                && until <= now
+            {
+                thread.state = ThreadState::Ready;
+            }
+        }
+        let current = self.current();
+        current.ticks += 1;
+        let priority = current.priority;
+        self.slice_left = self.slice_left.saturating_sub(1);
+        self.slice_left == 0
+            || self
+                .threads()
+                .any(|thread| thread.is_ready() && thread.priority > priority)
+    }
+}
+
+/// Runs the next thread, the current one must already be in the state it waits in (or Running if it
+/// can go on). Interrupts must be disabled, the thread keeps them disabled when it's resumed.
+pub(super) fn schedule() {
+    let switch = SCHEDULER.lock().pick_next();
+    if let Some((old_rsp, new_rsp)) = switch {
+        unsafe { switch_context(old_rsp, new_rsp) };
+    }
+}
+
+// Where new threads start, right after the `switch_context` that resumed them for the first time
+extern "C" fn thread_start() -> ! {
+    let entry = SCHEDULER.lock().current().entry;
+    crate::x86::enable_interrupts();
+    match entry {
+        Some(Entry::Plain(entry)) => entry(),
+        Some(Entry::WithArg(entry, arg)) => entry(arg),
+        None => {}
+    }
+    super::exit()
+}
@@ -0,0 +1,54 @@
+// Context switch between kernel threads. Only the callee-saved registers are kept on the stack of the
+// thread that leaves, everything else was already saved by its caller (or by the trap stub when it's
+// preempted). The FPU state is switched by the scheduler.
+
+use core::arch::global_asm;
+
+global_asm!(
+    r#"
+// switch_context(old_rsp: *mut usize, new_rsp: usize)
+.global switch_context
+switch_context:
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rdi], rsp
+    mov rsp, rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+"#
+);
+
+unsafe extern "C" {
+    /// Saves the stack pointer of the current thread in `old_rsp` and resumes the one at `new_rsp`,
+    /// returns when something switches back to `old_rsp`
+    pub(super) fn switch_context(old_rsp: *mut usize, new_rsp: usize);
+}
+
+// Registers popped by `switch_context`, from r15 to rbp
+const SAVED_REGISTERS: usize = 6;
+
+/// Builds the stack of a new thread so that switching to it calls `entry`, returns its stack pointer
+pub(super) unsafe fn init_stack(stack_top: usize, entry: extern "C" fn() -> !) -> usize {
+    let top = (stack_top & !0xF) as *mut usize;
+    unsafe {
+        // `entry` is returned to by `switch_context`, it must find the stack as if it had been called
+        // (rsp + 8 aligned on 16 bytes), with a null return address to end backtraces
+        top.sub(1).write(0);
+        top.sub(2).write(entry as usize);
+        let rsp = top.sub(2 + SAVED_REGISTERS);
+        for i in 0..SAVED_REGISTERS {
+            // A null RBP ends backtraces too
+            rsp.add(i).write(0);
+        }
+        rsp as usize
+    }
+}
@@ -0,0 +1,462 @@
+// Everything the kernel wants to know from CPUID, read once and kept in `CpuInfo`
+
+use core::{arch::x86_64::__cpuid_count, fmt::Write};
+
+use spin::Once;
+
+use crate::{
+    shell::{CommandError, FnCommand},
+    shell_command,
+};
+
+/// Returns (eax, ebx, ecx, edx) for the given leaf and subleaf.
+/// `rbx` is reserved by LLVM so the intrinsic is used instead of raw asm.
+pub fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let res = __cpuid_count(leaf, subleaf);
+    (res.eax, res.ebx, res.ecx, res.edx)
+}
+
+const EXTENDED_LEAVES: u32 = 0x8000_0000;
+const HYPERVISOR_LEAVES: u32 = 0x4000_0000;
+
+// FEATURES ///
+
+// Registers of the leaves holding feature flags
+#[derive(Debug, Clone, Copy)]
+enum FeatureRegister {
+    Leaf1Ecx,
+    Leaf1Edx,
+    Leaf7Ebx,
+    Leaf7Ecx,
+    Leaf7Edx,
+    XsaveEax, // Leaf 0xD, subleaf 1
+    Extended1Ecx,
+    Extended1Edx,
+    Extended7Edx, // Power management
+}
+
+const FEATURE_REGISTERS: usize = 9;
+
+/// A CPUID feature flag, see `CpuInfo::has`
+#[derive(Debug, Clone, Copy)]
+pub struct Feature {
+    register: FeatureRegister,
+    bit: u8,
+    pub name: &'static str,
+}
+
+macro_rules! features {
+    ($($register:ident { $($name:ident = $bit:literal,)* })*) => {
+        impl Feature {
+            $($(
+                pub const $name: Feature = Feature {
+                    register: FeatureRegister::$register,
+                    bit: $bit,
+                    name: stringify!($name),
+                };
+            )*)*
+
+            pub const ALL: &[Feature] = &[$($(Feature::$name,)*)*];
+        }
+    };
+}
+
+features! {
+    Leaf1Edx {
+        FPU = 0,
+        VME = 1,
+        DE = 2,
+        PSE = 3,
+        TSC = 4,
+        MSR = 5,
+        PAE = 6,
+        MCE = 7,
+        CX8 = 8,
+        APIC = 9,
+        SEP = 11,
+        MTRR = 12,
+        PGE = 13,
+        MCA = 14,
+        CMOV = 15,
+        PAT = 16,
+        PSE36 = 17,
+        CLFSH = 19,
+        MMX = 23,
+        FXSR = 24,
+        SSE = 25,
+        SSE2 = 26,
+        HTT = 28,
+    }
+    Leaf1Ecx {
+        SSE3 = 0,
+        PCLMULQDQ = 1,
+        MONITOR = 3,
+        VMX = 5,
+        SSSE3 = 9,
+        FMA = 12,
+        CX16 = 13,
+        PCID = 17,
+        SSE4_1 = 19,
+        SSE4_2 = 20,
+        X2APIC = 21,
+        MOVBE = 22,
+        POPCNT = 23,
+        TSC_DEADLINE = 24,
+        AES = 25,
+        XSAVE = 26,
+        OSXSAVE = 27,
+        AVX = 28,
+        F16C = 29,
+        RDRAND = 30,
+        HYPERVISOR = 31,
+    }
+    Leaf7Ebx {
+        FSGSBASE = 0,
+        BMI1 = 3,
+        AVX2 = 5,
+        SMEP = 7,
+        BMI2 = 8,
+        ERMS = 9,
+        INVPCID = 10,
+        AVX512F = 16,
+        RDSEED = 18,
+        ADX = 19,
+        SMAP = 20,
+        CLFLUSHOPT = 23,
+        CLWB = 24,
+        SHA = 29,
+    }
+    Leaf7Ecx {
+        UMIP = 2,
+        PKU = 3,
+        OSPKE = 4,
+        CET_SS = 7,
+        LA57 = 16,
+        RDPID = 22,
+    }
+    Leaf7Edx {
+        FSRM = 4,
+        CET_IBT = 20,
+    }
+    XsaveEax {
+        XSAVEOPT = 0,
+        XSAVEC = 1,
+        XGETBV1 = 2,
+        XSAVES = 3,
+    }
+    Extended1Ecx {
+        LAHF_LM = 0,
+        SVM = 2,
+        LZCNT = 5,
+        TOPOEXT = 22,
+    }
+    Extended1Edx {
+        SYSCALL = 11,
+        NX = 20,
+        PDPE1GB = 26,
+        RDTSCP = 27,
+        LM = 29,
+    }
+    Extended7Edx {
+        INVARIANT_TSC = 8,
+    }
+}
+
+// CACHES ///
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cache {
+    pub level: u8,
+    pub kind: CacheKind,
+    pub size: usize,
+    pub line_size: usize,
+    pub ways: usize,
+    pub sets: usize,
+    pub shared_by: usize, // Maximum number of logical CPUs using it
+}
+
+const MAX_CACHES: usize = 8;
+
+// Leaf 4 on Intel, 0x8000001D on AMD, both have the same layout
+fn read_cache(leaf: u32, index: u32) -> Option<Cache> {
+    let (eax, ebx, ecx, _) = cpuid(leaf, index);
+    let kind = match eax & 0x1F {
+        1 => CacheKind::Data,
+        2 => CacheKind::Instruction,
+        3 => CacheKind::Unified,
+        _ => return None, // No more caches
+    };
+    let line_size = (ebx & 0xFFF) as usize + 1;
+    let partitions = ((ebx >> 12) & 0x3FF) as usize + 1;
+    let ways = (ebx >> 22) as usize + 1;
+    let sets = ecx as usize + 1;
+    Some(Cache {
+        level: ((eax >> 5) & 0x7) as u8,
+        kind,
+        size: ways * partitions * line_size * sets,
+        line_size,
+        ways,
+        sets,
+        shared_by: ((eax >> 14) & 0xFFF) as usize + 1,
+    })
+}
+
+// CPU INFO ///
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hypervisor {
+    signature: [u8; 12],
+    pub max_leaf: u32,
+}
+
+impl Hypervisor {
+    /// "KVMKVMKVM", "TCGTCGTCGTCG", "VBoxVBoxVBox"...
+    pub fn signature(&self) -> &str {
+        as_str(&self.signature)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    pub max_leaf: u32,
+    pub max_extended_leaf: u32,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    features: [u32; FEATURE_REGISTERS],
+    pub physical_address_width: u8,
+    pub linear_address_width: u8,
+    caches: [Option<Cache>; MAX_CACHES],
+    pub hypervisor: Option<Hypervisor>,
+    /// XCR0 bits the CPU supports, from leaf 0xD
+    pub xsave_components: u64,
+    /// Size of an XSAVE area holding every supported component
+    pub xsave_max_size: usize,
+}
+
+// Strings made of registers are padded with NULs or spaces
+fn as_str(bytes: &[u8]) -> &str {
+    core::str::from_utf8(bytes)
+        .unwrap_or("?")
+        .trim_matches(|c| c == '\0' || c == ' ')
+}
+
+fn register_bytes(registers: &[u32]) -> impl Iterator<Item = u8> + '_ {
+    registers.iter().flat_map(|register| register.to_le_bytes())
+}
+
+impl CpuInfo {
+    fn detect() -> Self {
+        let (max_leaf, ebx, ecx, edx) = cpuid(0, 0);
+        let max_extended_leaf = cpuid(EXTENDED_LEAVES, 0).0;
+        let leaf = |leaf: u32, subleaf: u32| {
+            let max = if leaf >= EXTENDED_LEAVES {
+                max_extended_leaf
+            } else {
+                max_leaf
+            };
+            if leaf <= max {
+                cpuid(leaf, subleaf)
+            } else {
+                (0, 0, 0, 0)
+            }
+        };
+
+        let mut vendor = [0; 12];
+        vendor
+            .iter_mut()
+            .zip(register_bytes(&[ebx, edx, ecx]))
+            .for_each(|(byte, value)| *byte = value);
+
+        let mut brand = [0; 48];
+        for (i, chunk) in brand.chunks_mut(16).enumerate() {
+            let (eax, ebx, ecx, edx) = leaf(EXTENDED_LEAVES + 2 + i as u32, 0);
+            chunk
+                .iter_mut()
+                .zip(register_bytes(&[eax, ebx, ecx, edx]))
+                .for_each(|(byte, value)| *byte = value);
+        }
+
+        let (signature, _, leaf1_ecx, leaf1_edx) = leaf(1, 0);
+        let base_family = (signature >> 8) & 0xF;
+        let base_model = (signature >> 4) & 0xF;
+        let family = match base_family {
+            0xF => base_family + ((signature >> 20) & 0xFF),
+            _ => base_family,
+        };
+        let model = match base_family {
+            0x6 | 0xF => base_model + (((signature >> 16) & 0xF) << 4),
+            _ => base_model,
+        };
+
+        let (_, leaf7_ebx, leaf7_ecx, leaf7_edx) = leaf(7, 0);
+        let (_, _, extended1_ecx, extended1_edx) = leaf(EXTENDED_LEAVES + 1, 0);
+        let mut features = [0; FEATURE_REGISTERS];
+        features[FeatureRegister::Leaf1Ecx as usize] = leaf1_ecx;
+        features[FeatureRegister::Leaf1Edx as usize] = leaf1_edx;
+        features[FeatureRegister::Leaf7Ebx as usize] = leaf7_ebx;
+        features[FeatureRegister::Leaf7Ecx as usize] = leaf7_ecx;
+        features[FeatureRegister::Leaf7Edx as usize] = leaf7_edx;
+        features[FeatureRegister::XsaveEax as usize] = leaf(0xD, 1).0;
+        features[FeatureRegister::Extended1Ecx as usize] = extended1_ecx;
+        features[FeatureRegister::Extended1Edx as usize] = extended1_edx;
+        features[FeatureRegister::Extended7Edx as usize] = leaf(EXTENDED_LEAVES + 7, 0).3;
+
+        // Architectural defaults when the leaf is missing
+        let (mut physical_address_width, mut linear_address_width) = (36, 48);
+        if max_extended_leaf >= EXTENDED_LEAVES + 8 {
+            let eax = cpuid(EXTENDED_LEAVES + 8, 0).0;
+            physical_address_width = eax as u8;
+            linear_address_width = (eax >> 8) as u8;
+        }
+
+        let (xsave_components_low, _, xsave_max_size, xsave_components_high) = leaf(0xD, 0);
+
+        let mut info = CpuInfo {
+            vendor,
+            brand,
+            max_leaf,
+            max_extended_leaf,
+            family,
+            model,
+            stepping: signature & 0xF,
+            features,
+            physical_address_width,
+            linear_address_width,
+            caches: [None; MAX_CACHES],
+            hypervisor: None,
+            xsave_components: ((xsave_components_high as u64) << 32) | xsave_components_low as u64,
+            xsave_max_size: xsave_max_size as usize,
+        };
+
+        let cache_leaf = if info.has(Feature::TOPOEXT) {
+            Some(EXTENDED_LEAVES + 0x1D)
+        } else if info.vendor() == "GenuineIntel" && max_leaf >= 4 {
+            Some(4)
+        } else {
+            None
+        };
+        if let Some(cache_leaf) = cache_leaf {
+            for (index, cache) in info.caches.iter_mut().enumerate() {
+                *cache = read_cache(cache_leaf, index as u32);
+                if cache.is_none() {
+                    break;
+                }
+            }
+        }
+
+        // Leaves 0x40000000 and up are only meaningful under a hypervisor
+        if info.has(Feature::HYPERVISOR) {
+            let (max_leaf, ebx, ecx, edx) = cpuid(HYPERVISOR_LEAVES, 0);
+            let mut signature = [0; 12];
+            signature
+                .iter_mut()
+                .zip(register_bytes(&[ebx, ecx, edx]))
+                .for_each(|(byte, value)| *byte = value);
+            info.hypervisor = Some(Hypervisor {
+                signature,
+                max_leaf,
+            });
+        }
+
+        info
+    }
+
+    /// "GenuineIntel", "AuthenticAMD"...
+    pub fn vendor(&self) -> &str {
+        as_str(&self.vendor)
+    }
+
+    pub fn brand(&self) -> &str {
+        as_str(&self.brand)
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features[feature.register as usize] & (1 << feature.bit) != 0
+    }
+
+    pub fn caches(&self) -> impl Iterator<Item = &Cache> {
+        self.caches.iter().map_while(Option::as_ref)
+    }
+}
+
+static CPU_INFO: Once<CpuInfo> = Once::new();
+
+/// What CPUID says about the CPU, read on first use
+pub fn cpu_info() -> &'static CpuInfo {
+    CPU_INFO.call_once(CpuInfo::detect)
+}
+
+pub fn init_cpu_info() {
+    let info = cpu_info();
+    log::info!("CPU : {} ({})", info.brand(), info.vendor());
+    if let Some(hypervisor) = info.hypervisor {
+        log::info!("Running under {}", hypervisor.signature());
+    }
+}
+
+////////////////////////////////
+
+fn cpuinfo(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let info = cpu_info();
+    let _ = writeln!(out, "{} ({})", info.brand(), info.vendor());
+    let _ = writeln!(
+        out,
+        "Family {:#x}, model {:#x}, stepping {}",
+        info.family, info.model, info.stepping
+    );
+    let _ = writeln!(
+        out,
+        "Address widths : {} bits physical, {} bits linear",
+        info.physical_address_width, info.linear_address_width
+    );
+    if let Some(hypervisor) = info.hypervisor {
+        let _ = writeln!(
+            out,
+            "Hypervisor : {} (max leaf {:#x})",
+            hypervisor.signature(),
+            hypervisor.max_leaf
+        );
+    }
+    for cache in info.caches() {
+        let _ = writeln!(
+            out,
+            "L{} {:?} cache : {} KiB, {} ways, {} bytes lines, shared by {} CPUs",
+            cache.level,
+            cache.kind,
+            cache.size / 1024,
+            cache.ways,
+            cache.line_size,
+            cache.shared_by
+        );
+    }
+    if info.has(Feature::XSAVE) {
+        let _ = writeln!(
+            out,
+            "XSAVE components {:#x}, up to {} bytes",
+            info.xsave_components, info.xsave_max_size
+        );
+    }
+    let _ = write!(out, "Features :");
+    for feature in Feature::ALL.iter().filter(|feature| info.has(**feature)) {
+        let _ = write!(out, " {}", feature.name);
+    }
+    let _ = writeln!(out);
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "cpuinfo",
+    usage: "",
+    help: "Shows what CPUID says about the CPU",
+    run: cpuinfo,
+});
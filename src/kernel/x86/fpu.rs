@@ -0,0 +1,348 @@
+// x87, SSE and AVX state. boot.s only enables SSE, here the XSAVE components the kernel knows how to switch
+// are enabled and the fastest way to save them is picked. The state is switched eagerly : whoever runs
+// saves and restores it, nothing relies on CR0.TS and #NM.
+
+use core::{
+    arch::asm,
+    cell::RefCell,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use bitflags::bitflags;
+use spin::Once;
+
+use super::{
+    CR0_EMULATION, CR0_MONITOR_COPROCESSOR, CR0_NUMERIC_ERROR, CR0_TASK_SWITCHED, CR4_OSFXSR,
+    CR4_OSXMMEXCPT, CR4_OSXSAVE, Feature, cpu_info, disable_interrupts, enable_interrupts,
+    interrupts_enabled, read_cr0, read_cr4, write_cr0, write_cr4,
+};
+use crate::{
+    interrupts::{DEVICE_NOT_AVAILABLE_VECTOR, GateOptions, IDT, TrapFrame},
+    percpu,
+};
+
+bitflags! {
+    /// State components of XCR0
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Xcr0: u64 {
+        const X87 = 1 << 0;
+        const SSE = 1 << 1;
+        const AVX = 1 << 2;
+        const OPMASK = 1 << 5;
+        const ZMM_HI256 = 1 << 6;
+        const HI16_ZMM = 1 << 7;
+    }
+}
+
+impl Xcr0 {
+    const AVX512: Xcr0 = Xcr0::OPMASK.union(Xcr0::ZMM_HI256).union(Xcr0::HI16_ZMM);
+}
+
+pub fn read_xcr0() -> Xcr0 {
+    let (eax, edx): (u32, u32);
+    unsafe { asm!("xgetbv", in("ecx") 0, out("eax") eax, out("edx") edx, options(nomem, nostack)) };
+    Xcr0::from_bits_retain(((edx as u64) << 32) | eax as u64)
+}
+
+unsafe fn write_xcr0(value: Xcr0) {
+    let bits = value.bits();
+    unsafe {
+        asm!(
+            "xsetbv",
+            in("ecx") 0,
+            in("eax") bits as u32,
+            in("edx") (bits >> 32) as u32,
+            options(nomem, nostack),
+        )
+    };
+}
+
+// SAVE AREA ///
+
+// Enough for x87, SSE, AVX and AVX-512 (2696 bytes), AMX isn't enabled
+const FPU_STATE_SIZE: usize = 4096;
+
+const DEFAULT_FCW: u16 = 0x037F; // Every x87 exception masked, 64 bits precision
+const DEFAULT_MXCSR: u32 = 0x1F80; // Every SSE exception masked
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SaveMethod {
+    Fxsave,
+    Xsave,
+    Xsaveopt, // Skips the components that didn't change since they were restored
+}
+
+struct FpuConfig {
+    method: SaveMethod,
+    components: Xcr0,
+    size: usize,
+}
+
+static CONFIG: Once<FpuConfig> = Once::new();
+
+fn config() -> &'static FpuConfig {
+    CONFIG.get().expect("The FPU isn't initialized")
+}
+
+/// Registers of the x87, SSE and AVX units, as saved by XSAVE or FXSAVE
+#[repr(C, align(64))]
+pub struct FpuState {
+    area: [u8; FPU_STATE_SIZE],
+}
+
+impl FpuState {
+    /// The state after `fninit`, with every exception masked
+    pub const fn new() -> Self {
+        let mut area = [0; FPU_STATE_SIZE];
+        // Legacy region : FCW at 0, MXCSR at 24. The XSAVE header after it is all zero, which means
+        // every other component is in its initial state.
+        let fcw = DEFAULT_FCW.to_le_bytes();
+        area[0] = fcw[0];
+        area[1] = fcw[1];
+        let mxcsr = DEFAULT_MXCSR.to_le_bytes();
+        let mut i = 0;
+        while i < 4 {
+            area[24 + i] = mxcsr[i];
+            i += 1;
+        }
+        FpuState { area }
+    }
+
+    /// Saves the registers of the calling CPU
+    pub fn save(&mut self) {
+        let config = config();
+        let mask = config.components.bits();
+        let area = self.area.as_mut_ptr();
+        unsafe {
+            match config.method {
+                SaveMethod::Fxsave => asm!("fxsave64 [{}]", in(reg) area, options(nostack)),
+                SaveMethod::Xsave => asm!(
+                    "xsave64 [{}]",
+                    in(reg) area,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack),
+                ),
+                SaveMethod::Xsaveopt => asm!(
+                    "xsaveopt64 [{}]",
+                    in(reg) area,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack),
+                ),
+            }
+        }
+    }
+
+    /// Loads the registers of the calling CPU, any state saved by `save` or made by `new` is valid
+    pub fn restore(&self) {
+        let config = config();
+        let mask = config.components.bits();
+        let area = self.area.as_ptr();
+        unsafe {
+            match config.method {
+                SaveMethod::Fxsave => asm!("fxrstor64 [{}]", in(reg) area, options(nostack)),
+                SaveMethod::Xsave | SaveMethod::Xsaveopt => asm!(
+                    "xrstor64 [{}]",
+                    in(reg) area,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack),
+                ),
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bytes of `FpuState` actually used by the save method
+pub fn fpu_state_size() -> usize {
+    config().size
+}
+
+// STATUS ///
+
+bitflags! {
+    /// Exception flags shared by the x87 status word and MXCSR
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FpuExceptions: u32 {
+        const INVALID_OPERATION = 1 << 0;
+        const DENORMAL = 1 << 1;
+        const DIVIDE_BY_ZERO = 1 << 2;
+        const OVERFLOW = 1 << 3;
+        const UNDERFLOW = 1 << 4;
+        const PRECISION = 1 << 5;
+    }
+}
+
+impl fmt::Display for FpuExceptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (FpuExceptions::INVALID_OPERATION, "invalid operation"),
+            (FpuExceptions::DENORMAL, "denormal operand"),
+            (FpuExceptions::DIVIDE_BY_ZERO, "divide by zero"),
+            (FpuExceptions::OVERFLOW, "overflow"),
+            (FpuExceptions::UNDERFLOW, "underflow"),
+            (FpuExceptions::PRECISION, "precision"),
+        ];
+        let mut first = true;
+        for (flag, name) in names {
+            if self.contains(flag) {
+                write!(f, "{}{}", if first { "" } else { ", " }, name)?;
+                first = false;
+            }
+        }
+        if first {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+/// x87 status word, its low bits are the pending exceptions
+pub fn read_fpu_status() -> u16 {
+    let value: u16;
+    unsafe { asm!("fnstsw ax", out("ax") value, options(nomem, nostack)) };
+    value
+}
+
+pub fn read_mxcsr() -> u32 {
+    let mut value: u32 = 0;
+    unsafe { asm!("stmxcsr [{}]", in(reg) &mut value, options(nostack)) };
+    value
+}
+
+// INIT ///
+
+// Nothing sets TS or EM, if they get set anyway the FPU is given back instead of panicking
+fn device_not_available_handler(_frame: &mut TrapFrame) {
+    log::warn!("#NM : CR0.TS or CR0.EM got set, clearing them");
+    unsafe { write_cr0(read_cr0() & !(CR0_TASK_SWITCHED | CR0_EMULATION)) };
+}
+
+// Control register bits and default control words, the same on every CPU
+fn enable_fpu(xsave: bool) {
+    unsafe {
+        write_cr0(
+            (read_cr0() | CR0_MONITOR_COPROCESSOR | CR0_NUMERIC_ERROR)
+                & !(CR0_EMULATION | CR0_TASK_SWITCHED),
+        );
+        let mut cr4 = read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if xsave {
+            cr4 |= CR4_OSXSAVE;
+        }
+        write_cr4(cr4);
+        asm!("fninit", options(nomem, nostack));
+        asm!("ldmxcsr [{}]", in(reg) &DEFAULT_MXCSR, options(nostack));
+    }
+}
+
+/// Enables the XSAVE components the kernel can switch, needs the IDT
+pub fn init_fpu() {
+    let info = cpu_info();
+    enable_fpu(info.has(Feature::XSAVE));
+
+    let config = if info.has(Feature::XSAVE) {
+        let supported = Xcr0::from_bits_truncate(info.xsave_components);
+        let mut components = supported & (Xcr0::X87 | Xcr0::SSE | Xcr0::AVX | Xcr0::AVX512);
+        // AVX-512 is only usable with all three components
+        if !components.contains(Xcr0::AVX512) {
+            components -= Xcr0::AVX512;
+        }
+        unsafe { write_xcr0(components) };
+        // EBX is the size for the components enabled in XCR0
+        let mut size = super::cpuid(0xD, 0).1 as usize;
+        if size > FPU_STATE_SIZE {
+            components -= Xcr0::AVX512;
+            unsafe { write_xcr0(components) };
+            size = super::cpuid(0xD, 0).1 as usize;
+        }
+        let method = if info.has(Feature::XSAVEOPT) {
+            SaveMethod::Xsaveopt
+        } else {
+            SaveMethod::Xsave
+        };
+        FpuConfig {
+            method,
+            components,
+            size,
+        }
+    } else {
+        FpuConfig {
+            method: SaveMethod::Fxsave,
+            components: Xcr0::X87 | Xcr0::SSE,
+            size: 512,
+        }
+    };
+    log::info!(
+        "FPU state : {:?} ({} bytes) with {:?}",
+        config.components,
+        config.size,
+        config.method
+    );
+    CONFIG.call_once(|| config);
+
+    IDT.lock()
+        .register(
+            DEVICE_NOT_AVAILABLE_VECTOR,
+            device_not_available_handler,
+            GateOptions::new(),
+        )
+        .expect("Couldn't register the #NM handler");
+}
+
+/// Gives the calling CPU the FPU configuration of the first one, after `init_fpu`
+pub fn init_fpu_ap() {
+    let config = config();
+    let xsave = config.method != SaveMethod::Fxsave;
+    enable_fpu(xsave);
+    if xsave {
+        unsafe { write_xcr0(config.components) };
+    }
+}
+
+// KERNEL USE ///
+
+// The trap stubs only save what SSE code can change, kernel code using AVX or the x87 must go through
+// `kernel_fpu_begin` so that it doesn't break the state of whatever it interrupted.
+percpu!(KERNEL_FPU_SAVE: RefCell<FpuState> = RefCell::new(FpuState::new()));
+percpu!(IN_KERNEL_FPU: AtomicBool = AtomicBool::new(false));
+
+/// Lets kernel code use every FPU register until it's dropped, interrupts are disabled meanwhile
+pub struct KernelFpu {
+    interrupts_were_enabled: bool,
+}
+
+/// Saves the FPU state so that the caller can use AVX or the x87, it's restored by `kernel_fpu_end`
+pub fn kernel_fpu_begin() -> KernelFpu {
+    let interrupts_were_enabled = interrupts_enabled();
+    disable_interrupts();
+    assert!(
+        !IN_KERNEL_FPU.get().swap(true, Ordering::Acquire),
+        "kernel_fpu_begin can't be nested"
+    );
+    KERNEL_FPU_SAVE.with(|save| save.borrow_mut().save());
+    KernelFpu {
+        interrupts_were_enabled,
+    }
+}
+
+pub fn kernel_fpu_end(guard: KernelFpu) {
+    drop(guard);
+}
+
+impl Drop for KernelFpu {
+    fn drop(&mut self) {
+        KERNEL_FPU_SAVE.with(|save| save.borrow().restore());
+        IN_KERNEL_FPU.get().store(false, Ordering::Release);
+        if self.interrupts_were_enabled {
+            enable_interrupts();
+        }
+    }
+}
@@ -1,5 +1,11 @@
+mod cpuid;
+mod fpu;
 mod msr;
+mod mtrr;
 mod utils;
 
+pub use cpuid::*;
+pub use fpu::*;
 pub use msr::*;
+pub use mtrr::*;
 pub use utils::*;
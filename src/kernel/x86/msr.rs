@@ -1,58 +1,109 @@
-use core::{arch::asm, ops::RangeInclusive};
+use core::{arch::asm, fmt};
 
-use super::{utils::MSR_FEATURE, without_interrupts};
+use bitflags::bitflags;
 
-// TODO : bitflags crate looks perfect for this and i already use it elsewhere ?
+use super::{Feature, cpu_info, with_caches_disabled};
 
 ////////////////////////////////
 
-const WC_MEMORY_TYPE: usize = 1;
-// TODO : Find this through cpuid or smth
-const ADDRESS_WIDTH: usize = 48;
+/// Memory types as encoded in the PAT and in the MTRRs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MemoryType {
+    Uncacheable = 0,
+    WriteCombining = 1,
+    WriteThrough = 4,
+    WriteProtected = 5,
+    WriteBack = 6,
+    UncacheableMinus = 7, // PAT only
+}
+
+impl MemoryType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(MemoryType::Uncacheable),
+            1 => Some(MemoryType::WriteCombining),
+            4 => Some(MemoryType::WriteThrough),
+            5 => Some(MemoryType::WriteProtected),
+            6 => Some(MemoryType::WriteBack),
+            7 => Some(MemoryType::UncacheableMinus),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemoryType::Uncacheable => "UC",
+            MemoryType::WriteCombining => "WC",
+            MemoryType::WriteThrough => "WT",
+            MemoryType::WriteProtected => "WP",
+            MemoryType::WriteBack => "WB",
+            MemoryType::UncacheableMinus => "UC-",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The PAT layout we program, indexed by the PAT/PCD/PWT bits of a page table entry.
+/// Same as the power-on default except for entry 1 (WT -> WC) and 5, 7 which are unused.
+pub const PAT_LAYOUT: [MemoryType; 8] = [
+    MemoryType::WriteBack,
+    MemoryType::WriteCombining,
+    MemoryType::UncacheableMinus,
+    MemoryType::Uncacheable,
+    MemoryType::WriteBack,
+    MemoryType::WriteProtected,
+    MemoryType::UncacheableMinus,
+    MemoryType::WriteThrough,
+];
 
 ///////////////////////////////
 
-unsafe fn readmsr_byte(reg: usize) -> usize {
+/// Reads a whole MSR, the #GP raised if it doesn't exist is caught
+pub fn rdmsr(reg: usize) -> Result<usize, MsrError> {
     let (eax, edx): (u32, u32);
-
+    let faulted: u32;
+    // `faulted` is only cleared if rdmsr didn't jump to the fixup
     unsafe {
         asm!(
+            "2:",
             "rdmsr",
+            "xor {faulted:e}, {faulted:e}",
+            "3:",
+            crate::exception_table_entry!("2b", "3b"),
             in("ecx") reg,
             out("eax") eax,
             out("edx") edx,
+            faulted = inout(reg) 1u32 => faulted,
         )
     };
-    ((edx as usize) << 32) | eax as usize
+    if faulted != 0 {
+        return Err(MsrError::Rejected);
+    }
+    Ok(((edx as usize) << 32) | eax as usize)
 }
 
-unsafe fn writemsr_byte(reg: usize, value: usize) {
-    let eax = value as u32;
-    let edx = (value >> 32) as u32;
+/// Writes a whole MSR, the #GP raised if it doesn't exist or the value is invalid for it is caught
+pub unsafe fn wrmsr(reg: usize, value: usize) -> Result<(), MsrError> {
+    let faulted: u32;
     unsafe {
         asm!(
+            "2:",
             "wrmsr",
+            "xor {faulted:e}, {faulted:e}",
+            "3:",
+            crate::exception_table_entry!("2b", "3b"),
             in("ecx") reg,
-            in("eax") eax,
-            in("edx") edx,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            faulted = inout(reg) 1u32 => faulted,
         )
     };
-}
-
-unsafe fn readmsr(reg: usize, bits: RangeInclusive<usize>) -> usize {
-    let byte = unsafe { readmsr_byte(reg) };
-    let mask = (1 << (bits.end() + 1)) - (1 << bits.start());
-    (byte & mask) >> bits.start()
-}
-
-unsafe fn writemsr(reg: usize, bits: RangeInclusive<usize>, value: usize) -> Result<(), MsrError> {
-    if value >> (bits.end() - bits.start() + 1) != 0 {
-        return Err(MsrError::ValueExceedsBitRange);
+    if faulted != 0 {
+        return Err(MsrError::Rejected);
     }
-    let byte = unsafe { readmsr_byte(reg) };
-    let mask = (1 << (bits.end() + 1)) - (1 << bits.start());
-    let new_byte = (byte & !mask) | ((value << bits.start()) & mask);
-    unsafe { writemsr_byte(reg, new_byte) };
     Ok(())
 }
 
@@ -63,104 +114,247 @@ pub enum MsrError {
     NoMsrSupport,
     NoWCTypeSupport,
     NoFreeMtrPair,
-    ValueExceedsBitRange,
-}
-trait ConstMsr {
-    const REG: usize;
+    NoPatSupport,
+    MisalignedRange,
+    RangeOverflow,   // The range goes past the end of the address space
+    OverlappingMtrr, // The range already has a variable MTRR
+    NotAvailable,    // CPUID says the MSR doesn't exist
+    Rejected,        // The CPU raised a #GP : no such MSR, or a value it doesn't accept
 }
 
-struct Ia32MtrrCap;
+// TYPED MSRS ///
 
-impl ConstMsr for Ia32MtrrCap {
-    const REG: usize = 0xFE;
+/// Conversion of the value of an MSR from and to its raw bits
+pub trait MsrValue: Copy {
+    fn from_raw(raw: usize) -> Self;
+    fn to_raw(self) -> usize;
 }
 
-impl Ia32MtrrCap {
-    fn has_wc_type_support() -> bool {
-        unsafe { readmsr(Self::REG, 10..=10) == 1 }
+impl MsrValue for usize {
+    fn from_raw(raw: usize) -> Self {
+        raw
     }
 
-    fn mtrr_pair_reg_nb() -> usize {
-        unsafe { readmsr(Self::REG, 0..=7) }
+    fn to_raw(self) -> usize {
+        self
     }
 }
 
-struct Ia32MtrrDefType;
+/// An architectural MSR, only accessed when CPUID says it exists
+pub trait Msr {
+    const REG: usize;
+    /// Feature that makes the MSR exist
+    const FEATURE: Feature;
+    type Value: MsrValue;
+
+    fn is_available() -> bool {
+        cpu_info().has(Feature::MSR) && cpu_info().has(Self::FEATURE)
+    }
+
+    fn read() -> Result<Self::Value, MsrError> {
+        if !Self::is_available() {
+            return Err(MsrError::NotAvailable);
+        }
+        rdmsr(Self::REG).map(Self::Value::from_raw)
+    }
 
-impl ConstMsr for Ia32MtrrDefType {
-    const REG: usize = 0x2FF;
+    /// Writing an MSR can change how the CPU works in any way
+    unsafe fn write(value: Self::Value) -> Result<(), MsrError> {
+        if !Self::is_available() {
+            return Err(MsrError::NotAvailable);
+        }
+        unsafe { wrmsr(Self::REG, value.to_raw()) }
+    }
+
+    /// Read, modify, write
+    unsafe fn update(f: impl FnOnce(Self::Value) -> Self::Value) -> Result<(), MsrError> {
+        let value = Self::read()?;
+        unsafe { Self::write(f(value)) }
+    }
 }
 
-impl Ia32MtrrDefType {
-    fn enable_mtrr() {
-        unsafe {
-            writemsr(Self::REG, 11..=11, 1).unwrap();
+macro_rules! msr {
+    ($(#[$doc:meta])* $name:ident, $reg:literal, $feature:ident, $value:ty) => {
+        $(#[$doc])*
+        pub struct $name;
+
+        impl Msr for $name {
+            const REG: usize = $reg;
+            const FEATURE: Feature = Feature::$feature;
+            type Value = $value;
         }
+    };
+}
+
+macro_rules! bitflags_msr_value {
+    ($($value:ty),*) => {
+        $(impl MsrValue for $value {
+            fn from_raw(raw: usize) -> Self {
+                Self::from_bits_retain(raw as u64)
+            }
+
+            fn to_raw(self) -> usize {
+                self.bits() as usize
+            }
+        })*
+    };
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Efer: u64 {
+        const SYSCALL_ENABLE = 1 << 0;
+        const LONG_MODE_ENABLE = 1 << 8;
+        const LONG_MODE_ACTIVE = 1 << 10;
+        const NO_EXECUTE_ENABLE = 1 << 11;
+        const SVM_ENABLE = 1 << 12;
+        const FAST_FXSAVE = 1 << 14;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ApicBase: u64 {
+        const BSP = 1 << 8;
+        const X2APIC_ENABLE = 1 << 10;
+        const ENABLE = 1 << 11;
+        const _ = !0; // The base address
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MtrrCap: u64 {
+        const FIXED = 1 << 8;
+        const WRITE_COMBINING = 1 << 10;
+        const SMRR = 1 << 11;
+        const _ = 0xFF; // Number of variable MTRRs
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MtrrDefType: u64 {
+        const FIXED_ENABLE = 1 << 10;
+        const ENABLE = 1 << 11;
+        const _ = 0xFF; // Default memory type
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MtrrPhysMask: u64 {
+        const VALID = 1 << 11;
+        const _ = !0; // The mask
     }
 }
 
-struct MtrrPhysPair {
-    base: usize,
+bitflags_msr_value!(Efer, ApicBase, MtrrCap, MtrrDefType);
+
+impl ApicBase {
+    const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+    /// Physical address of the local APIC registers
+    pub fn address(self) -> usize {
+        (self.bits() & Self::ADDRESS_MASK) as usize
+    }
 }
 
-impl MtrrPhysPair {
-    fn base_reg(&self) -> usize {
-        self.base
+impl MtrrCap {
+    /// Number of variable MTRR pairs
+    pub fn variable_count(self) -> usize {
+        (self.bits() & 0xFF) as usize
     }
-    fn mask_reg(&self) -> usize {
-        self.base + 1
+}
+
+impl MtrrDefType {
+    /// Memory type of what no MTRR covers, as encoded in `MemoryType`
+    pub fn default_type(self) -> u8 {
+        self.bits() as u8
     }
+}
 
-    fn free_mtrr_pair() -> Option<MtrrPhysPair> {
-        let mttr_pair_reg_nb = Ia32MtrrCap::mtrr_pair_reg_nb();
-        let mut mttr_pair_reg = None;
-        for i in 0..mttr_pair_reg_nb {
-            let mask_reg = 0x201 + i * 2;
-            let valid_bit = unsafe { readmsr(mask_reg, 11..=11) };
-            if valid_bit == 0 {
-                mttr_pair_reg = Some(MtrrPhysPair { base: mask_reg - 1 });
-                break;
-            }
+/// Segments loaded by SYSCALL and SYSRET
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Star {
+    /// SYSCALL loads CS from it, SS from the next descriptor
+    pub syscall_cs: u16,
+    /// SYSRET loads SS from the next descriptor, CS from the one after it
+    pub sysret_base: u16,
+}
+
+impl MsrValue for Star {
+    fn from_raw(raw: usize) -> Self {
+        Star {
+            syscall_cs: (raw >> 32) as u16,
+            sysret_base: (raw >> 48) as u16,
         }
-        mttr_pair_reg
     }
 
-    fn set_memory_type(
-        &self,
-        addr: usize,
-        size: usize,
-        memory_type: usize,
-    ) -> Result<(), MsrError> {
-        // size must be aligned to a boundary of a power of two and not be bigger than NB_PHYSYCAL_ADDRESS_BITS bits
-        let mask = !(size.next_power_of_two() - 1) & ((1 << ADDRESS_WIDTH) - 1);
+    fn to_raw(self) -> usize {
+        ((self.sysret_base as usize) << 48) | ((self.syscall_cs as usize) << 32)
+    }
+}
 
-        without_interrupts(move || unsafe {
-            writemsr(self.base_reg(), 12..=(ADDRESS_WIDTH - 1), addr >> 12)?; // Set the base address
-            writemsr(self.base_reg(), 0..=7, memory_type)?; // Set the memory type
-            writemsr(self.mask_reg(), 12..=(ADDRESS_WIDTH - 1), mask >> 12)?; // Set the mask
-            writemsr(self.mask_reg(), 11..=11, 1)?; // Set the valid bit
-            Ok(())
-        })?;
+msr!(Ia32Efer, 0xC000_0080, LM, Efer);
+msr!(Ia32ApicBase, 0x1B, APIC, ApicBase);
+msr!(
+    /// Memory types of the PAT entries, one per byte
+    Ia32Pat,
+    0x277,
+    PAT,
+    usize
+);
+msr!(Ia32MtrrCap, 0xFE, MTRR, MtrrCap);
+msr!(Ia32MtrrDefType, 0x2FF, MTRR, MtrrDefType);
+msr!(Ia32Star, 0xC000_0081, SYSCALL, Star);
+msr!(
+    /// Entry point of SYSCALL
+    Ia32Lstar,
+    0xC000_0082,
+    SYSCALL,
+    usize
+);
+msr!(
+    /// RFLAGS bits cleared by SYSCALL
+    Ia32Fmask,
+    0xC000_0084,
+    SYSCALL,
+    usize
+);
+msr!(Ia32FsBase, 0xC000_0100, LM, usize);
+msr!(Ia32GsBase, 0xC000_0101, LM, usize);
+msr!(
+    /// Swapped with the GS base by SWAPGS
+    Ia32KernelGsBase,
+    0xC000_0102,
+    LM,
+    usize
+);
+msr!(
+    /// TSC value at which the local APIC timer fires in TSC deadline mode
+    Ia32TscDeadline,
+    0x6E0,
+    TSC_DEADLINE,
+    usize
+);
 
-        Ok(())
+impl Ia32Pat {
+    fn layout_value(layout: &[MemoryType; 8]) -> usize {
+        layout
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, ty)| acc | ((*ty as usize) << (i * 8)))
     }
 }
 
 ///////////////////////////////
 
-// TODO : Think of a better way than .next_power_of_two() to align the size (several MTRRs can be used)
-pub fn set_mtrr_wc(addr: usize, size: usize) -> Result<(), MsrError> {
-    if !MSR_FEATURE.cpu_has_feature() {
+/// Programs the PAT with `PAT_LAYOUT`, making WC available to page table entries.
+pub fn init_pat() -> Result<(), MsrError> {
+    if !cpu_info().has(Feature::MSR) {
         return Err(MsrError::NoMsrSupport);
     }
-    if !Ia32MtrrCap::has_wc_type_support() {
-        return Err(MsrError::NoWCTypeSupport);
+    if !cpu_info().has(Feature::PAT) {
+        return Err(MsrError::NoPatSupport);
     }
 
-    Ia32MtrrDefType::enable_mtrr();
-
-    let mtrr_phys_pair = MtrrPhysPair::free_mtrr_pair().ok_or(MsrError::NoFreeMtrPair)?;
-    mtrr_phys_pair.set_memory_type(addr, size, WC_MEMORY_TYPE)?;
+    with_caches_disabled(|_| unsafe { Ia32Pat::write(Ia32Pat::layout_value(&PAT_LAYOUT)) })
+}
 
-    Ok(())
+/// Index in `PAT_LAYOUT` of the given memory type
+pub fn pat_index(memory_type: MemoryType) -> Option<usize> {
+    PAT_LAYOUT.iter().position(|ty| *ty == memory_type)
 }
@@ -0,0 +1,527 @@
+// Memory Type Range Registers : the memory type of physical ranges, which the PAT can then change per page.
+// Every CPU must have the same MTRRs, so the layout set by the kernel is kept for the CPUs started later.
+
+use core::fmt::Write;
+
+use spin::Mutex;
+
+use super::{
+    CR0_CACHE_DISABLE, CR0_NOT_WRITE_THROUGH, CR4_PGE, Feature, Ia32MtrrCap, Ia32MtrrDefType,
+    MemoryType, Msr, MsrError, MtrrCap, MtrrDefType, MtrrPhysMask, cpu_info, flush_tlb, rdmsr,
+    read_cr0, read_cr4, wbinvd, without_interrupts, write_cr0, write_cr4, wrmsr,
+};
+use crate::{
+    shell::{CommandError, FnCommand, parse_number},
+    shell_command, smp,
+};
+
+// MSR, start and size of each of the 8 ranges of the fixed MTRRs, which cover the first MiB
+const FIXED_MTRRS: [(usize, usize, usize); 11] = [
+    (0x250, 0x00000, 0x10000),
+    (0x258, 0x80000, 0x4000),
+    (0x259, 0xA0000, 0x4000),
+    (0x268, 0xC0000, 0x1000),
+    (0x269, 0xC8000, 0x1000),
+    (0x26A, 0xD0000, 0x1000),
+    (0x26B, 0xD8000, 0x1000),
+    (0x26C, 0xE0000, 0x1000),
+    (0x26D, 0xE8000, 0x1000),
+    (0x26E, 0xF0000, 0x1000),
+    (0x26F, 0xF8000, 0x1000),
+];
+const FIXED_MTRRS_END: usize = 0x100000;
+
+// The CPUs we know of have at most 10
+const MAX_VARIABLE_MTRRS: usize = 16;
+
+fn physical_mask() -> usize {
+    (1 << cpu_info().physical_address_width) - 1
+}
+
+// MTRRS ///
+
+/// A variable MTRR, a range of `size` bytes aligned on its size
+#[derive(Debug, Clone, Copy)]
+pub struct VariableMtrr {
+    base: usize,
+    mask: MtrrPhysMask,
+}
+
+impl VariableMtrr {
+    const FREE: VariableMtrr = VariableMtrr {
+        base: 0,
+        mask: MtrrPhysMask::empty(),
+    };
+
+    fn new(addr: usize, size: usize, memory_type: MemoryType) -> Self {
+        let mask = !(size - 1) & physical_mask() & !0xFFF;
+        VariableMtrr {
+            base: (addr & physical_mask() & !0xFFF) | memory_type as usize,
+            mask: MtrrPhysMask::from_bits_retain(mask as u64) | MtrrPhysMask::VALID,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.mask.contains(MtrrPhysMask::VALID)
+    }
+
+    pub fn addr(&self) -> usize {
+        self.base & !0xFFF
+    }
+
+    fn address_mask(&self) -> usize {
+        (self.mask - MtrrPhysMask::VALID).bits() as usize & physical_mask()
+    }
+
+    /// Only meaningful for masks without holes, which is all that `set_mtrr_range` writes
+    pub fn size(&self) -> usize {
+        (!self.address_mask() & physical_mask()) + 1
+    }
+
+    pub fn memory_type(&self) -> MemoryType {
+        // The CPU refuses to hold anything else
+        MemoryType::from_u8(self.base as u8).unwrap_or(MemoryType::Uncacheable)
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.is_valid() && addr & self.address_mask() == self.addr() & self.address_mask()
+    }
+}
+
+/// Every MTRR of a CPU
+#[derive(Debug, Clone)]
+pub struct MtrrState {
+    pub cap: MtrrCap,
+    pub def_type: MtrrDefType,
+    fixed: [usize; FIXED_MTRRS.len()],
+    variable: [VariableMtrr; MAX_VARIABLE_MTRRS],
+}
+
+impl MtrrState {
+    pub fn read() -> Result<Self, MsrError> {
+        let cap = Ia32MtrrCap::read()?;
+        let mut state = MtrrState {
+            cap,
+            def_type: Ia32MtrrDefType::read()?,
+            fixed: [0; FIXED_MTRRS.len()],
+            variable: [VariableMtrr::FREE; MAX_VARIABLE_MTRRS],
+        };
+        if cap.contains(MtrrCap::FIXED) {
+            for (value, &(msr, _, _)) in state.fixed.iter_mut().zip(&FIXED_MTRRS) {
+                *value = rdmsr(msr)?;
+            }
+        }
+        let variable_count = state.variable_count();
+        for (i, mtrr) in state.variable[..variable_count].iter_mut().enumerate() {
+            *mtrr = VariableMtrr {
+                base: rdmsr(0x200 + 2 * i)?,
+                mask: MtrrPhysMask::from_bits_retain(rdmsr(0x201 + 2 * i)? as u64),
+            };
+        }
+        Ok(state)
+    }
+
+    /// Writes every MTRR, must be called through `with_caches_disabled`
+    unsafe fn write(&self) -> Result<(), MsrError> {
+        unsafe {
+            if self.cap.contains(MtrrCap::FIXED) {
+                for (value, &(msr, _, _)) in self.fixed.iter().zip(&FIXED_MTRRS) {
+                    wrmsr(msr, *value)?;
+                }
+            }
+            for (i, mtrr) in self.variable().enumerate() {
+                wrmsr(0x200 + 2 * i, mtrr.base)?;
+                wrmsr(0x201 + 2 * i, mtrr.mask.bits() as usize)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.def_type.contains(MtrrDefType::ENABLE)
+    }
+
+    fn fixed_enabled(&self) -> bool {
+        self.cap.contains(MtrrCap::FIXED) && self.def_type.contains(MtrrDefType::FIXED_ENABLE)
+    }
+
+    pub fn default_type(&self) -> MemoryType {
+        MemoryType::from_u8(self.def_type.default_type()).unwrap_or(MemoryType::Uncacheable)
+    }
+
+    fn variable_count(&self) -> usize {
+        self.cap.variable_count().min(MAX_VARIABLE_MTRRS)
+    }
+
+    /// Every variable MTRR, valid or not
+    pub fn variable(&self) -> impl Iterator<Item = &VariableMtrr> {
+        self.variable[..self.variable_count()].iter()
+    }
+
+    /// Start, size and type of the ranges of the fixed MTRRs
+    pub fn fixed_ranges(&self) -> impl Iterator<Item = (usize, usize, MemoryType)> + '_ {
+        self.fixed
+            .iter()
+            .zip(&FIXED_MTRRS)
+            .flat_map(|(value, &(_, start, size))| {
+                (0..8).map(move |i| {
+                    let memory_type = MemoryType::from_u8((value >> (i * 8)) as u8)
+                        .unwrap_or(MemoryType::Uncacheable);
+                    (start + i * size, size, memory_type)
+                })
+            })
+    }
+
+    /// Memory type the MTRRs give to a physical address (Intel SDM Vol. 3A 12.11.4.1)
+    pub fn memory_type_at(&self, addr: usize) -> MemoryType {
+        if !self.is_enabled() {
+            return MemoryType::Uncacheable;
+        }
+        if addr < FIXED_MTRRS_END && self.fixed_enabled() {
+            let (_, _, memory_type) = self
+                .fixed_ranges()
+                .find(|&(start, size, _)| (start..start + size).contains(&addr))
+                .expect("The fixed MTRRs cover the first MiB");
+            return memory_type;
+        }
+
+        let mut matching = self.variable().filter(|mtrr| mtrr.contains(addr));
+        let Some(first) = matching.next() else {
+            return self.default_type();
+        };
+        matching.fold(first.memory_type(), |current, mtrr| {
+            match (current, mtrr.memory_type()) {
+                (a, b) if a == b => a,
+                (MemoryType::Uncacheable, _) | (_, MemoryType::Uncacheable) => {
+                    MemoryType::Uncacheable
+                }
+                (MemoryType::WriteThrough, MemoryType::WriteBack)
+                | (MemoryType::WriteBack, MemoryType::WriteThrough) => MemoryType::WriteThrough,
+                _ => MemoryType::Uncacheable, // Undefined, assume the worst
+            }
+        })
+    }
+
+    /// Memory type of the whole range, None if its parts have different ones
+    pub fn effective_memory_type(&self, addr: usize, size: usize) -> Option<MemoryType> {
+        let end = addr.checked_add(size)?;
+        // The type can only change where a range of an MTRR starts or ends
+        let fixed_bounds = self
+            .fixed_ranges()
+            .map(|(start, _, _)| start)
+            .chain([FIXED_MTRRS_END]);
+        let variable_bounds = self
+            .variable()
+            .filter(|mtrr| mtrr.is_valid())
+            .flat_map(|mtrr| [mtrr.addr(), mtrr.addr() + mtrr.size()]);
+        let memory_type = self.memory_type_at(addr);
+        fixed_bounds
+            .chain(variable_bounds)
+            .filter(|bound| (addr + 1..end).contains(bound))
+            .all(|bound| self.memory_type_at(bound) == memory_type)
+            .then_some(memory_type)
+    }
+}
+
+// RANGES ///
+
+/// Splits `[addr, addr + size)` into naturally aligned power of two blocks,
+/// which is what a variable MTRR pair can describe.
+fn split_mtrr_range(mut addr: usize, mut size: usize, mut f: impl FnMut(usize, usize)) {
+    while size > 0 {
+        let align = if addr == 0 {
+            usize::MAX
+        } else {
+            1 << addr.trailing_zeros()
+        };
+        let biggest_fit = 1 << (usize::BITS - 1 - size.leading_zeros());
+        let block = align.min(biggest_fit);
+        f(addr, block);
+        addr += block;
+        size -= block;
+    }
+}
+
+fn block_count(addr: usize, size: usize) -> usize {
+    let mut count = 0;
+    split_mtrr_range(addr, size, |_, _| count += 1);
+    count
+}
+
+/// Variable MTRRs giving a type to a range
+#[derive(Debug, Clone, Copy)]
+pub struct MtrrPlan {
+    mtrrs: [VariableMtrr; MAX_VARIABLE_MTRRS],
+    len: usize,
+}
+
+impl MtrrPlan {
+    fn push(&mut self, addr: usize, size: usize, memory_type: MemoryType) -> Result<(), MsrError> {
+        let slot = self
+            .mtrrs
+            .get_mut(self.len)
+            .ok_or(MsrError::NoFreeMtrPair)?;
+        *slot = VariableMtrr::new(addr, size, memory_type);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn push_blocks(
+        &mut self,
+        addr: usize,
+        size: usize,
+        memory_type: MemoryType,
+    ) -> Result<(), MsrError> {
+        let mut result = Ok(());
+        split_mtrr_range(addr, size, |block_addr, block_size| {
+            if result.is_ok() {
+                result = self.push(block_addr, block_size, memory_type);
+            }
+        });
+        result
+    }
+
+    pub fn mtrrs(&self) -> &[VariableMtrr] {
+        &self.mtrrs[..self.len]
+    }
+}
+
+/// Fewest variable MTRRs giving `memory_type` to `[addr, addr + size)`. Either one per aligned block of
+/// the range, or one block bigger than the range with the excess carved out as UC, when it is already UC.
+pub fn plan_mtrr_range(
+    state: &MtrrState,
+    addr: usize,
+    size: usize,
+    memory_type: MemoryType,
+) -> Result<MtrrPlan, MsrError> {
+    let mut plan = MtrrPlan {
+        mtrrs: [VariableMtrr::FREE; MAX_VARIABLE_MTRRS],
+        len: 0,
+    };
+    let end = addr.checked_add(size).ok_or(MsrError::RangeOverflow)?;
+
+    // Smallest aligned block containing the range
+    let mut block = size
+        .checked_next_power_of_two()
+        .ok_or(MsrError::RangeOverflow)?;
+    let block_end = loop {
+        let block_end = (addr & !(block - 1))
+            .checked_add(block)
+            .ok_or(MsrError::RangeOverflow)?;
+        if block_end >= end {
+            break block_end;
+        }
+        block = block.checked_mul(2).ok_or(MsrError::RangeOverflow)?;
+    };
+    let block_start = block_end - block;
+    let carved =
+        1 + block_count(block_start, addr - block_start) + block_count(end, block_end - end);
+    let can_carve = memory_type != MemoryType::Uncacheable
+        && block_end - 1 <= physical_mask()
+        && [(block_start, addr - block_start), (end, block_end - end)]
+            .iter()
+            .filter(|&&(_, size)| size != 0)
+            .all(|&(start, size)| {
+                state.effective_memory_type(start, size) == Some(MemoryType::Uncacheable)
+            });
+
+    if can_carve && carved < block_count(addr, size) {
+        plan.push(block_start, block, memory_type)?;
+        plan.push_blocks(block_start, addr - block_start, MemoryType::Uncacheable)?;
+        plan.push_blocks(end, block_end - end, MemoryType::Uncacheable)?;
+    } else {
+        plan.push_blocks(addr, size, memory_type)?;
+    }
+    Ok(plan)
+}
+
+// UPDATES ///
+
+// What the kernel wrote, for the CPUs that start later. The others never change after that.
+static KERNEL_MTRRS: Mutex<Option<MtrrState>> = Mutex::new(None);
+
+/// Runs `f` following the Intel SDM (Vol. 3A 12.11.7.2) sequence for changing memory types :
+/// caches disabled and flushed, TLBs flushed, MTRRs disabled, then everything restored in order.
+/// `f` gets the MTRR default type value that will be written back when re-enabling them.
+pub(super) fn with_caches_disabled<R>(f: impl FnOnce(&mut MtrrDefType) -> R) -> R {
+    without_interrupts(|| unsafe {
+        let cr0 = read_cr0();
+        let cr4 = read_cr4();
+
+        write_cr0((cr0 | CR0_CACHE_DISABLE) & !CR0_NOT_WRITE_THROUGH);
+        wbinvd();
+        if cr4 & CR4_PGE != 0 {
+            write_cr4(cr4 & !CR4_PGE); // Also flushes global pages
+        } else {
+            flush_tlb();
+        }
+
+        let has_mtrr = Ia32MtrrDefType::is_available();
+        let mut def_type = MtrrDefType::empty();
+        if has_mtrr {
+            def_type = Ia32MtrrDefType::read().expect("MTRRs are available");
+            Ia32MtrrDefType::write(def_type - MtrrDefType::ENABLE).expect("MTRRs are available");
+        }
+
+        let r = f(&mut def_type);
+
+        if has_mtrr {
+            Ia32MtrrDefType::write(def_type).expect("MTRRs are available");
+        }
+
+        wbinvd();
+        flush_tlb();
+        write_cr0(cr0);
+        write_cr4(cr4);
+        r
+    })
+}
+
+fn write_state(state: &MtrrState) -> Result<(), MsrError> {
+    with_caches_disabled(|def_type| {
+        *def_type = state.def_type;
+        unsafe { state.write() }
+    })
+}
+
+/// Gives a memory type to a physical range with as few variable MTRRs as possible.
+/// The range must not have one already, overlapping MTRRs would only make it UC.
+/// Only before the other CPUs start : they copy the MTRRs of the first one then, and changing them on every
+/// online CPU at once would need them all in the SDM sequence together.
+pub fn set_mtrr_range(addr: usize, size: usize, memory_type: MemoryType) -> Result<(), MsrError> {
+    assert!(
+        !smp::started(),
+        "MTRRs can only be changed before the other CPUs start"
+    );
+    if !Ia32MtrrCap::is_available() {
+        return Err(MsrError::NoMsrSupport);
+    }
+    if addr & 0xFFF != 0 || size & 0xFFF != 0 || size == 0 {
+        return Err(MsrError::MisalignedRange);
+    }
+    let mut state = MtrrState::read()?;
+    if memory_type == MemoryType::WriteCombining && !state.cap.contains(MtrrCap::WRITE_COMBINING) {
+        return Err(MsrError::NoWCTypeSupport);
+    }
+    let end = addr.checked_add(size).ok_or(MsrError::RangeOverflow)?;
+    let overlaps = |mtrr: &VariableMtrr| {
+        mtrr.is_valid() && mtrr.addr() < end && addr < mtrr.addr() + mtrr.size()
+    };
+    if state.variable().any(overlaps) {
+        return Err(MsrError::OverlappingMtrr);
+    }
+
+    let plan = plan_mtrr_range(&state, addr, size, memory_type)?;
+    let variable_count = state.variable_count();
+    let mut free = state.variable[..variable_count]
+        .iter_mut()
+        .filter(|mtrr| !mtrr.is_valid());
+    for mtrr in plan.mtrrs() {
+        *free.next().ok_or(MsrError::NoFreeMtrPair)? = *mtrr;
+    }
+    state.def_type |= MtrrDefType::ENABLE;
+
+    write_state(&state)?;
+    *KERNEL_MTRRS.lock() = Some(state);
+    Ok(())
+}
+
+/// Fallback for CPUs without PAT
+pub fn set_mtrr_wc(addr: usize, size: usize) -> Result<(), MsrError> {
+    if !cpu_info().has(Feature::MTRR) {
+        return Err(MsrError::NoMsrSupport);
+    }
+    set_mtrr_range(addr, size, MemoryType::WriteCombining)
+}
+
+/// Gives the calling CPU the MTRRs the kernel set on the first one
+pub fn sync_mtrrs() -> Result<(), MsrError> {
+    match &*KERNEL_MTRRS.lock() {
+        Some(state) => write_state(state),
+        None => Ok(()), // Still what the firmware set, on every CPU
+    }
+}
+
+////////////////////////////////
+
+fn mtrr(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let state = MtrrState::read().map_err(|_| CommandError::Failed("no MTRRs"))?;
+
+    // With a range, only its type
+    if let Some(addr) = args.first() {
+        let addr = parse_number(addr)? as usize;
+        let size = args.get(1).map_or(Ok(1), |size| parse_number(size))? as usize;
+        match state.effective_memory_type(addr, size) {
+            Some(memory_type) => {
+                let _ = writeln!(out, "{}", memory_type);
+            }
+            None => {
+                let _ = writeln!(out, "mixed");
+            }
+        }
+        return Ok(());
+    }
+
+    let _ = writeln!(
+        out,
+        "MTRRs {}, default type {}, {} variable, WC {}",
+        if state.is_enabled() {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        state.default_type(),
+        state.cap.variable_count(),
+        if state.cap.contains(MtrrCap::WRITE_COMBINING) {
+            "supported"
+        } else {
+            "unsupported"
+        },
+    );
+
+    if state.fixed_enabled() {
+        let _ = writeln!(out, "Fixed :");
+        // Neighbours of the same type are merged
+        let mut ranges = state.fixed_ranges().peekable();
+        while let Some((start, mut size, memory_type)) = ranges.next() {
+            while let Some(&(_, next_size, _)) = ranges
+                .peek()
+                .filter(|&&(_, _, next_type)| next_type == memory_type)
+            {
+                size += next_size;
+                ranges.next();
+            }
+            let _ = writeln!(
+                out,
+                "  {:#07x} - {:#07x}  {}",
+                start,
+                start + size - 1,
+                memory_type
+            );
+        }
+    }
+
+    let _ = writeln!(out, "Variable :");
+    for (i, mtrr) in state.variable().enumerate() {
+        if mtrr.is_valid() {
+            let _ = writeln!(
+                out,
+                "  {}: {:#018x} - {:#018x}  {}",
+                i,
+                mtrr.addr(),
+                mtrr.addr() + mtrr.size() - 1,
+                mtrr.memory_type()
+            );
+        } else {
+            let _ = writeln!(out, "  {}: free", i);
+        }
+    }
+    Ok(())
+}
+
+shell_command!(FnCommand {
+    name: "mtrr",
+    usage: "[<addr> [size]]",
+    help: "Shows the MTRRs, or the memory type they give to a range",
+    run: mtrr,
+});
@@ -1,39 +1,210 @@
 use core::arch::asm;
 
-// CPUID ///
-
-pub struct Feature {
-    leaf: usize,
-    bit: u8,
-}
-
-impl Feature {
-    pub fn cpu_has_feature(&self) -> bool {
-        let edx: usize;
-        unsafe {
-            asm!(
-                "cpuid",
-                in("eax") self.leaf,
-                out("edx") edx,
-            );
-        }
-        edx & (1 << self.bit) != 0
-    }
+// INTERRUPTS ///
+
+pub const RFLAGS_TF: u64 = 1 << 8; // Trap after every instruction
+const RFLAGS_IF: u64 = 1 << 9;
+pub const RFLAGS_RF: u64 = 1 << 16; // Ignore instruction breakpoints for one instruction
+
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe { asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags)) };
+    rflags & RFLAGS_IF != 0
 }
 
-pub const MSR_FEATURE: Feature = Feature { leaf: 1, bit: 12 };
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nostack)) };
+}
 
-// INTERRUPTS ///
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nostack)) };
+}
+
+/// Traps into the #BP handler
+pub fn breakpoint() {
+    unsafe { asm!("int3", options(nomem, nostack)) };
+}
+
+/// Sleeps until the next interrupt
+pub fn halt() {
+    unsafe { asm!("hlt", options(nomem, nostack)) };
+}
 
+/// Runs `f` with interrupts disabled, restoring their previous state afterwards
+/// so that it can be nested and used from interrupt handlers
 pub fn without_interrupts<F, R>(f: F) -> R
 where
     F: FnOnce() -> R,
 {
-    let r: R;
-    unsafe {
-        asm!("cli");
-        r = f();
-        asm!("sti");
+    let were_enabled = interrupts_enabled();
+    if were_enabled {
+        disable_interrupts();
+    }
+    let r = f();
+    if were_enabled {
+        enable_interrupts();
     }
     r
 }
+
+// CONTROL REGISTERS ///
+
+pub const CR0_MONITOR_COPROCESSOR: usize = 1 << 1;
+pub const CR0_EMULATION: usize = 1 << 2;
+pub const CR0_TASK_SWITCHED: usize = 1 << 3;
+pub const CR0_NUMERIC_ERROR: usize = 1 << 5; // #MF instead of the legacy IRQ13
+pub const CR0_WRITE_PROTECT: usize = 1 << 16;
+pub const CR0_NOT_WRITE_THROUGH: usize = 1 << 29;
+pub const CR0_CACHE_DISABLE: usize = 1 << 30;
+pub const CR4_PGE: usize = 1 << 7;
+pub const CR4_OSFXSR: usize = 1 << 9;
+pub const CR4_OSXMMEXCPT: usize = 1 << 10;
+pub const CR4_OSXSAVE: usize = 1 << 18;
+
+pub fn read_cr0() -> usize {
+    let value;
+    unsafe { asm!("mov {}, cr0", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub unsafe fn write_cr0(value: usize) {
+    unsafe { asm!("mov cr0, {}", in(reg) value, options(nostack)) };
+}
+
+/// Address of the last page fault
+pub fn read_cr2() -> usize {
+    let value;
+    unsafe { asm!("mov {}, cr2", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub fn read_cr3() -> usize {
+    let value;
+    unsafe { asm!("mov {}, cr3", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub unsafe fn write_cr3(value: usize) {
+    unsafe { asm!("mov cr3, {}", in(reg) value, options(nostack)) };
+}
+
+pub fn read_cr4() -> usize {
+    let value;
+    unsafe { asm!("mov {}, cr4", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub unsafe fn write_cr4(value: usize) {
+    unsafe { asm!("mov cr4, {}", in(reg) value, options(nostack)) };
+}
+
+/// Writes back and invalidates every cache line.
+pub unsafe fn wbinvd() {
+    unsafe { asm!("wbinvd", options(nostack)) };
+}
+
+/// Flushes every non global TLB entry by reloading CR3.
+pub fn flush_tlb() {
+    unsafe { write_cr3(read_cr3()) };
+}
+
+/// Flushes every TLB entry, global ones too by toggling CR4.PGE when it's set.
+pub fn flush_tlb_global() {
+    let cr4 = read_cr4();
+    if cr4 & CR4_PGE == 0 {
+        flush_tlb();
+        return;
+    }
+    without_interrupts(|| unsafe {
+        write_cr4(cr4 & !CR4_PGE);
+        write_cr4(cr4);
+    });
+}
+
+pub fn invlpg(addr: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) addr, options(nostack)) };
+}
+
+// SEGMENTS ///
+
+pub fn read_ds() -> u16 {
+    let selector;
+    unsafe { asm!("mov {:x}, ds", out(reg) selector, options(nomem, nostack)) };
+    selector
+}
+
+pub fn read_es() -> u16 {
+    let selector;
+    unsafe { asm!("mov {:x}, es", out(reg) selector, options(nomem, nostack)) };
+    selector
+}
+
+pub fn read_fs() -> u16 {
+    let selector;
+    unsafe { asm!("mov {:x}, fs", out(reg) selector, options(nomem, nostack)) };
+    selector
+}
+
+pub fn read_gs() -> u16 {
+    let selector;
+    unsafe { asm!("mov {:x}, gs", out(reg) selector, options(nomem, nostack)) };
+    selector
+}
+
+// DEBUG REGISTERS ///
+
+pub const DR6_SINGLE_STEP: usize = 1 << 14;
+
+/// Address of the breakpoint `index` (0-3)
+pub unsafe fn write_dr(index: usize, addr: usize) {
+    unsafe {
+        match index {
+            0 => asm!("mov dr0, {}", in(reg) addr, options(nomem, nostack)),
+            1 => asm!("mov dr1, {}", in(reg) addr, options(nomem, nostack)),
+            2 => asm!("mov dr2, {}", in(reg) addr, options(nomem, nostack)),
+            3 => asm!("mov dr3, {}", in(reg) addr, options(nomem, nostack)),
+            _ => panic!("There is no DR{}", index),
+        }
+    }
+}
+
+/// Status of the last #DB, the CPU never clears it
+pub fn read_dr6() -> usize {
+    let value;
+    unsafe { asm!("mov {}, dr6", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub unsafe fn write_dr6(value: usize) {
+    unsafe { asm!("mov dr6, {}", in(reg) value, options(nomem, nostack)) };
+}
+
+pub fn read_dr7() -> usize {
+    let value;
+    unsafe { asm!("mov {}, dr7", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub unsafe fn write_dr7(value: usize) {
+    unsafe { asm!("mov dr7, {}", in(reg) value, options(nomem, nostack)) };
+}
+
+// POWER ///
+
+/// Resets the machine through the keyboard controller, or a triple fault if that doesn't work
+pub fn reboot() -> ! {
+    use crate::io::{PS2_KEYBOARD_OUT, inb, outb};
+
+    const INPUT_BUFFER_FULL: u8 = 1 << 1;
+    const PULSE_RESET_LINE: u8 = 0xFE;
+
+    disable_interrupts();
+    unsafe {
+        while inb(PS2_KEYBOARD_OUT) & INPUT_BUFFER_FULL != 0 {}
+        outb(PS2_KEYBOARD_OUT, PULSE_RESET_LINE);
+
+        // An empty IDT makes any exception a triple fault
+        let empty_idt = [0u16; 5];
+        asm!("lidt [{}]", "int3", in(reg) &empty_idt, options(noreturn));
+    }
+}
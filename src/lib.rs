@@ -1,5 +1,4 @@
 #![no_std]
-#![feature(abi_x86_interrupt)]
 #![allow(clippy::missing_safety_doc)]
 
 mod kernel;
@@ -24,3 +23,22 @@ pub unsafe fn load_multiboot(multiboot_info_addr: usize) -> Result<(), LoadError
     MULTIBOOT2_INFO.call_once(|| multiboot_info);
     Ok(())
 }
+
+/// Content of the first Multiboot2 module whose command line starts with `name`
+pub fn find_module(name: &str) -> Option<&'static [u8]> {
+    let boot_info = MULTIBOOT2_INFO.get()?;
+    boot_info
+        .module_tags()
+        .find(|module| {
+            module
+                .cmdline()
+                .is_ok_and(|cmdline| cmdline.split_whitespace().next() == Some(name))
+        })
+        .map(|module| unsafe {
+            // Modules are loaded below 4GiB, which is identity mapped
+            core::slice::from_raw_parts(
+                module.start_address() as *const u8,
+                module.module_size() as usize,
+            )
+        })
+}
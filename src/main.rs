@@ -3,18 +3,38 @@
 
 mod real_main;
 
-use ab_os_bel::{hlt_loop, serial_println};
-
 // INITIALIZATION
 pub fn init(multiboot_info_addr: usize) {
-    serial_println!("Initializing ab_os_bel...");
+    ab_os_bel::logger::init();
+    log::info!("Initializing ab_os_bel...");
+    ab_os_bel::x86::init_cpu_info();
 
     ab_os_bel::gdt::init(); // Initialize the segmentation for interruption stacks
+    ab_os_bel::smp::init_bsp(); // Per-CPU data, after the GDT which resets GS_BASE
     ab_os_bel::interrupts::init_idt(); // Initialize the interruptions and the handlers
+    ab_os_bel::stacks::init();
+    ab_os_bel::x86::init_fpu();
+    ab_os_bel::io::pic::init();
+    ab_os_bel::io::pit::init();
+    ab_os_bel::thread::init();
+    ab_os_bel::process::init();
+    ab_os_bel::io::keyboard::init();
     unsafe { ab_os_bel::load_multiboot(multiboot_info_addr).expect("Couldn't load multiboot") };
+    ab_os_bel::params::init();
+    ab_os_bel::sync::init(); // Lock debugging, from the `lockdep` parameter
+    ab_os_bel::backtrace::init();
+    ab_os_bel::io::serial::init();
+    ab_os_bel::gdb::init();
+    ab_os_bel::logger::apply_cmdline_filters();
     ab_os_bel::framebuffer::init_graphics();
 
-    serial_println!("ab_os_bel initialized.");
+    ab_os_bel::io::pic::unmask(ab_os_bel::io::pic::TIMER_IRQ);
+    ab_os_bel::io::pic::unmask(ab_os_bel::io::pic::KEYBOARD_IRQ);
+    ab_os_bel::io::serial::enable_interrupts();
+    ab_os_bel::x86::enable_interrupts();
+    ab_os_bel::smp::start_aps(); // Waits for them with the timer
+
+    log::info!("ab_os_bel initialized.");
 }
 
 // MAIN
@@ -25,5 +45,5 @@ pub extern "C" fn main(multiboot_info_addr: usize) -> ! {
 
     real_main::main();
 
-    hlt_loop()
+    ab_os_bel::shell::run()
 }
@@ -26,8 +26,5 @@ pub fn main() {
 }
 
 fn log_tag<T: core::fmt::Debug>(tag: T) {
-    serial_dbg!(&tag);
-    serial_print!("\n\n");
-    dbg!(&tag);
-    println!("\n\n");
+    log::info!("{:#?}", tag);
 }
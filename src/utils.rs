@@ -1,12 +1,18 @@
 use core::{arch::asm, panic::PanicInfo};
 
-use crate::{framebuffer::WRITER, println, serial_println};
+use crate::{backtrace::Backtrace, framebuffer::WRITER, println, serial_println};
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    serial_println!("{}", info);
-    if WRITER.get().is_some() {
-        println!("{}", info);
+    crate::x86::disable_interrupts();
+    crate::smp::halt_others();
+    crate::io::serial::switch_to_polling();
+    let backtrace = Backtrace::current();
+    serial_println!("{}\n{}", info, backtrace);
+    if let Some(writer) = WRITER.get() {
+        // Forced as the other CPUs may have been halted while holding it, or it was the code that panicked
+        unsafe { writer.force_unlock() };
+        println!("{}\n{}", info, backtrace);
     }
     hlt_loop()
 }